@@ -54,14 +54,14 @@ impl<T> Array<T> {
         }
     }
 
-    pub fn iter(&self) -> Iter<T> {
+    pub fn iter(&self) -> Iter<'_, T> {
         Iter {
             len: 0,
             array: self,
         }
     }
 
-    pub fn iter_mut(&mut self) -> IterMut<T> {
+    pub fn iter_mut(&mut self) -> IterMut<'_, T> {
         IterMut {
             len: 0,
             array: self,
@@ -140,13 +140,13 @@ impl<T> Deref for Array<T> {
     type Target = [T];
 
     fn deref(&self) -> &Self::Target {
-        self.buf.as_slice()
+        &self.buf.as_slice()[..self.count]
     }
 }
 
 impl<T> DerefMut for Array<T> {
     fn deref_mut(&mut self) -> &mut Self::Target {
-        self.buf.as_mut_slice()
+        &mut self.buf.as_mut_slice()[..self.count]
     }
 }
 
@@ -206,7 +206,7 @@ where
     }
 }
 
-unsafe impl<T> Send for Array<T> {}
+unsafe impl<T: Send> Send for Array<T> {}
 
 #[cfg(test)]
 mod tests {
@@ -259,7 +259,7 @@ mod tests {
     fn test_is_empty_when_is_empty() {
         let array: Array<Foo> = Array::new();
 
-        assert_eq!(true, array.is_empty())
+        assert!(array.is_empty())
     }
 
     #[test]
@@ -267,13 +267,13 @@ mod tests {
         let mut array: Array<Foo> = Array::new();
         array.push(Foo::new(1, "lol".to_string()));
 
-        assert_eq!(false, array.is_empty())
+        assert!(!array.is_empty())
     }
 
     #[test]
     fn test_pop_none_when_its_empty() {
         let mut array: Array<usize> = Array::new();
-        assert_eq!(true, array.is_empty());
+        assert!(array.is_empty());
         assert_eq!(array.pop(), None);
     }
 
@@ -0,0 +1,201 @@
+use std::fmt::{Debug, Display};
+use std::ops::{Deref, DerefMut};
+use std::slice;
+
+use crate::raw_array::RawArray;
+
+/// Error returned when pushing onto a full `FixedStack`.
+#[derive(Debug, PartialEq, Eq)]
+pub struct StackOverflow;
+
+/// A stack with a capacity fixed at construction.
+///
+/// The storage is allocated once and never moves, so `top` can point one past the last value.
+/// The `_unchecked` operations skip every bounds check and are meant for hot paths where the
+/// caller already knows the stack depth.
+pub struct FixedStack<T: Copy> {
+    buf: RawArray<T>,
+    top: *mut T,
+}
+
+impl<T: Copy> FixedStack<T> {
+    pub fn with_capacity(capacity: usize) -> Self {
+        let buf = match capacity {
+            0 => RawArray::new(),
+            _ => RawArray::with_capacity(capacity),
+        };
+        let top = buf.as_ptr();
+
+        Self { buf, top }
+    }
+
+    #[inline]
+    pub fn capacity(&self) -> usize {
+        self.buf.capacity()
+    }
+
+    #[inline]
+    pub fn len(&self) -> usize {
+        // Safety: `top` always points inside the buffer, or one past its end.
+        unsafe { self.top.offset_from(self.buf.as_ptr()) as usize }
+    }
+
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.top == self.buf.as_ptr()
+    }
+
+    #[inline]
+    pub fn is_full(&self) -> bool {
+        self.len() == self.capacity()
+    }
+
+    #[inline]
+    pub fn push(&mut self, value: T) -> Result<(), StackOverflow> {
+        if self.is_full() {
+            return Err(StackOverflow);
+        }
+
+        unsafe { self.push_unchecked(value) };
+        Ok(())
+    }
+
+    /// # Safety
+    ///
+    /// The stack must not be full.
+    #[inline]
+    pub unsafe fn push_unchecked(&mut self, value: T) {
+        debug_assert!(!self.is_full(), "stack overflow");
+        self.top.write(value);
+        self.top = self.top.add(1);
+    }
+
+    #[inline]
+    pub fn pop(&mut self) -> Option<T> {
+        if self.is_empty() {
+            return None;
+        }
+
+        Some(unsafe { self.pop_unchecked() })
+    }
+
+    /// # Safety
+    ///
+    /// The stack must not be empty.
+    #[inline]
+    pub unsafe fn pop_unchecked(&mut self) -> T {
+        debug_assert!(!self.is_empty(), "stack underflow");
+        self.top = self.top.sub(1);
+        self.top.read()
+    }
+
+    #[inline]
+    pub fn peek(&self, distance: usize) -> Option<&T> {
+        if distance >= self.len() {
+            return None;
+        }
+
+        Some(unsafe { self.peek_unchecked(distance) })
+    }
+
+    /// # Safety
+    ///
+    /// `distance` must be smaller than the length of the stack.
+    #[inline]
+    pub unsafe fn peek_unchecked(&self, distance: usize) -> &T {
+        debug_assert!(
+            distance < self.len(),
+            "peeking past the bottom of the stack"
+        );
+        &*self.top.sub(distance + 1)
+    }
+
+    pub fn reset(&mut self) {
+        self.top = self.buf.as_ptr();
+    }
+}
+
+impl<T: Copy + Display> Display for FixedStack<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "[ ")?;
+        for elem in self.iter() {
+            write!(f, "{} ", elem)?;
+        }
+        write!(f, "]")
+    }
+}
+
+impl<T: Copy + Debug> Debug for FixedStack<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_list().entries(self.iter()).finish()
+    }
+}
+
+impl<T: Copy> Deref for FixedStack<T> {
+    type Target = [T];
+
+    fn deref(&self) -> &Self::Target {
+        unsafe { slice::from_raw_parts(self.buf.as_ptr(), self.len()) }
+    }
+}
+
+impl<T: Copy> DerefMut for FixedStack<T> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        unsafe { slice::from_raw_parts_mut(self.buf.as_ptr(), self.len()) }
+    }
+}
+
+// `top` only ever points into the buffer owned by the stack.
+unsafe impl<T: Copy + Send> Send for FixedStack<T> {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_push_pop() {
+        let mut stack: FixedStack<i32> = FixedStack::with_capacity(3);
+        assert_eq!(Ok(()), stack.push(1));
+        assert_eq!(Ok(()), stack.push(2));
+
+        assert_eq!(2, stack.len());
+        assert_eq!(&[1, 2], &stack[..]);
+        assert_eq!(Some(2), stack.pop());
+        assert_eq!(Some(1), stack.pop());
+        assert_eq!(None, stack.pop());
+    }
+
+    #[test]
+    fn test_overflow() {
+        let mut stack: FixedStack<i32> = FixedStack::with_capacity(2);
+        assert_eq!(Ok(()), stack.push(1));
+        assert_eq!(Ok(()), stack.push(2));
+
+        assert!(stack.is_full());
+        assert_eq!(Err(StackOverflow), stack.push(3));
+        assert_eq!(&[1, 2], &stack[..]);
+    }
+
+    #[test]
+    fn test_zero_capacity() {
+        let mut stack: FixedStack<i32> = FixedStack::with_capacity(0);
+
+        assert!(stack.is_empty());
+        assert_eq!(Err(StackOverflow), stack.push(1));
+        assert_eq!(None, stack.peek(0));
+    }
+
+    #[test]
+    fn test_peek() {
+        let mut stack: FixedStack<i32> = FixedStack::with_capacity(4);
+        stack.push(1).unwrap();
+        stack.push(2).unwrap();
+        stack.push(3).unwrap();
+
+        assert_eq!(Some(&3), stack.peek(0));
+        assert_eq!(Some(&1), stack.peek(2));
+        assert_eq!(None, stack.peek(3));
+        stack.reset();
+        assert_eq!(None, stack.peek(0));
+    }
+}
@@ -40,10 +40,10 @@ where
     }
 
     #[inline]
-    fn find_entry_index<Q: ?Sized>(&self, key: &Q) -> usize
+    fn find_entry_index<Q>(&self, key: &Q) -> usize
     where
         K: Borrow<Q>,
-        Q: Hash + Eq,
+        Q: Hash + Eq + ?Sized,
     {
         let hash = self.hash(key.borrow());
         let mut index = (hash % self.capacity() as u64) as usize;
@@ -52,9 +52,7 @@ where
         loop {
             let entry = self.get_entry(index);
             match entry {
-                Entry::Vacant => {
-                    break tombstone.map_or_else(|| index, |tombstone_index| tombstone_index)
-                }
+                Entry::Vacant => break tombstone.unwrap_or(index),
                 Entry::Tombstone => {
                     if tombstone.is_none() {
                         tombstone = Some(index);
@@ -71,19 +69,19 @@ where
     }
 
     #[inline]
-    pub fn find_entry<Q: ?Sized>(&self, key: &Q) -> &Entry<K, V>
+    pub fn find_entry<Q>(&self, key: &Q) -> &Entry<K, V>
     where
         K: Borrow<Q>,
-        Q: Hash + Eq,
+        Q: Hash + Eq + ?Sized,
     {
         self.get_entry(self.find_entry_index(key))
     }
 
     #[inline]
-    pub fn find_entry_mut<Q: ?Sized>(&mut self, key: &Q) -> &mut Entry<K, V>
+    pub fn find_entry_mut<Q>(&mut self, key: &Q) -> &mut Entry<K, V>
     where
         K: Borrow<Q>,
-        Q: Hash + Eq,
+        Q: Hash + Eq + ?Sized,
     {
         self.get_entry_mut(self.find_entry_index(key))
     }
@@ -168,18 +166,18 @@ where
         self.set(key, value)
     }
 
-    pub fn contains_key<Q: ?Sized>(&self, key: &Q) -> bool
+    pub fn contains_key<Q>(&self, key: &Q) -> bool
     where
         K: Borrow<Q>,
-        Q: Hash + Eq,
+        Q: Hash + Eq + ?Sized,
     {
         self.get(key).is_some()
     }
 
-    pub fn get<Q: ?Sized>(&self, key: &Q) -> Option<&V>
+    pub fn get<Q>(&self, key: &Q) -> Option<&V>
     where
         K: Borrow<Q>,
-        Q: Hash + Eq,
+        Q: Hash + Eq + ?Sized,
     {
         if self.is_empty() {
             return None;
@@ -198,10 +196,10 @@ where
         }
     }
 
-    pub fn delete<Q: ?Sized>(&mut self, key: &Q) -> bool
+    pub fn delete<Q>(&mut self, key: &Q) -> bool
     where
         K: Borrow<Q>,
-        Q: Hash + Eq,
+        Q: Hash + Eq + ?Sized,
     {
         if self.is_empty() {
             return false;
@@ -218,10 +216,10 @@ where
         true
     }
 
-    pub fn remove<Q: ?Sized>(&mut self, key: &Q) -> bool
+    pub fn remove<Q>(&mut self, key: &Q) -> bool
     where
         K: Borrow<Q>,
-        Q: Hash + Eq,
+        Q: Hash + Eq + ?Sized,
     {
         self.delete(key)
     }
@@ -422,11 +420,12 @@ where
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Default)]
 pub enum Entry<K, V>
 where
     K: Hash + Eq,
 {
+    #[default]
     Vacant,
     Occupied(OccupiedEntry<K, V>),
     Tombstone,
@@ -475,15 +474,6 @@ where
     }
 }
 
-impl<K, V> Default for Entry<K, V>
-where
-    K: Hash + Eq,
-{
-    fn default() -> Self {
-        Self::Vacant
-    }
-}
-
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -525,11 +515,11 @@ mod tests {
     #[test]
     fn test_set_empty() {
         let mut map: HashMap<&str, Foo> = HashMap::new();
-        assert_eq!(true, map.is_empty());
+        assert!(map.is_empty());
         let result = map.set("1", Foo::new(2));
         assert_eq!(Some(&Foo { bar: 2 }), map.get("1"));
         assert_eq!(1, map.len());
-        assert_eq!(true, result);
+        assert!(result);
     }
 
     #[test]
@@ -539,8 +529,8 @@ mod tests {
         let result2 = map.set("1".into(), Foo::new(2));
         assert_eq!(Some(&Foo::new(2)), map.get("1"));
         assert_eq!(1, map.len());
-        assert_eq!(true, result1);
-        assert_eq!(false, result2);
+        assert!(result1);
+        assert!(!result2);
     }
 
     #[test]
@@ -565,15 +555,15 @@ mod tests {
     #[test]
     fn test_delete_empty() {
         let mut map: HashMap<&str, Foo> = HashMap::new();
-        assert_eq!(true, map.is_empty());
-        assert_eq!(map.delete("1"), false);
+        assert!(map.is_empty());
+        assert!(!map.delete("1"));
     }
 
     #[test]
     fn test_delete_non_empty_existing() {
         let mut map: HashMap<&str, Foo> = HashMap::new();
         map.set("1", Foo::new(1));
-        assert_eq!(map.delete("1"), true);
+        assert!(map.delete("1"));
         assert_eq!(map.get("1"), None);
     }
 
@@ -582,7 +572,7 @@ mod tests {
         let mut map: HashMap<&str, Foo> = HashMap::new();
         map.set("1", Foo::new(1));
         map.set("2", Foo::new(2));
-        assert_eq!(map.delete("0"), false);
+        assert!(!map.delete("0"));
         assert_eq!(Some(&Foo::new(1)), map.get("1"));
         assert_eq!(Some(&Foo::new(2)), map.get("2"));
     }
@@ -596,7 +586,7 @@ mod tests {
         assert_eq!(1000, map.len());
 
         for (key, _) in &keys_values {
-            assert_eq!(true, map.delete(key));
+            assert!(map.delete(key));
         }
         assert_eq!(0, map.len());
     }
@@ -1,8 +1,10 @@
 pub mod array;
+pub mod fixed_stack;
 pub mod hashmap;
 mod raw_array;
 pub mod stack;
 
 pub use array::Array;
+pub use fixed_stack::FixedStack;
 pub use hashmap::HashMap;
 pub use stack::Stack;
@@ -49,7 +49,7 @@ impl<T> RawArray<T> {
 
     #[inline]
     pub fn as_mut_slice(&mut self) -> &mut [T] {
-        unsafe { slice::from_raw_parts_mut(self.ptr.as_ptr() as *mut T, self.capacity) }
+        unsafe { slice::from_raw_parts_mut(self.ptr.as_ptr(), self.capacity) }
     }
 
     #[inline]
@@ -134,7 +134,7 @@ impl<T> Drop for RawArray<T> {
     }
 }
 
-unsafe impl<T> Send for RawArray<T> {}
+unsafe impl<T: Send> Send for RawArray<T> {}
 
 #[cfg(test)]
 mod tests {
@@ -0,0 +1,588 @@
+//! Assembles textual bytecode into a `Chunk`.
+//!
+//! Each line holds at most one instruction, named as `OpCode` displays it, optionally preceded by
+//! a `label:` and followed by a `;` comment:
+//!
+//! ```text
+//!         OP_CONSTANT 0           ; literal constants: numbers, "strings", true, false, nil
+//! loop:   OP_GET_LOCAL 0
+//!         OP_CONSTANT 3
+//!         OP_LESS
+//!         OP_JUMP_IF_FALSE done   ; jumps take a label or an absolute offset
+//!         OP_POP
+//!         OP_GET_GLOBAL counter   ; globals take a name, as an identifier or a string
+//!         OP_PRINT
+//!         OP_LOOP loop
+//! done:   OP_POP
+//!         OP_RETURN
+//! ```
+//!
+//! The assembler also reads `Disassembler` listings, so a disassembled chunk assembles back into
+//! the same code. A listing prefixes every instruction with its offset and line, spells constants
+//! as their index followed by the literal in single quotes, e.g. `'1'` or `'"1"'`, and jumps as
+//! `offset -> target`. In
+//! hand-written code, instructions take the line they appear on in the source.
+//!
+//! The result is not verified, which makes the assembler handy for writing malformed chunks too.
+
+use std::collections::HashMap;
+
+use crate::bytecode::{Chunk, OpCode};
+use crate::heap::Heap;
+use crate::string::String as LoxString;
+use crate::value::Value;
+
+/// The chunk can address at most this many constants and inline caches.
+const MAX_INDEX: usize = u8::MAX as usize;
+
+/// An assembly error, with the source line it was found on.
+#[derive(Debug, PartialEq, Eq)]
+pub struct AssemblerError {
+    msg: String,
+    line: usize,
+}
+
+impl AssemblerError {
+    fn new(msg: String, line: usize) -> Self {
+        Self { msg, line }
+    }
+
+    pub fn msg(&self) -> &str {
+        &self.msg
+    }
+
+    pub fn line(&self) -> usize {
+        self.line
+    }
+}
+
+/// A constant operand, either as a listing spells it or as a literal.
+#[derive(Debug)]
+enum Constant {
+    Indexed(usize, Literal),
+    Literal(Literal),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Literal {
+    Number(f64),
+    Boolean(bool),
+    Nil,
+    String(String),
+}
+
+/// Where a jump goes, resolved once every label is known.
+#[derive(Debug)]
+enum Target {
+    Label(String),
+    Offset(usize),
+}
+
+/// An operand to fill in after the whole source has been read.
+#[derive(Debug)]
+enum Patch {
+    Constant(Constant),
+    Cache(Option<usize>),
+    Jump(OpCode, Target),
+}
+
+pub(crate) struct Assembler<'a> {
+    source: &'a str,
+    heap: &'a mut Heap,
+    chunk: Chunk,
+    labels: HashMap<&'a str, usize>,
+    /// Offsets of operands to fill in, with what goes there and the source line that asked.
+    patches: Vec<(usize, Patch, usize)>,
+}
+
+impl<'a> Assembler<'a> {
+    pub fn new(source: &'a str, heap: &'a mut Heap) -> Self {
+        Self {
+            source,
+            heap,
+            chunk: Chunk::new(),
+            labels: HashMap::new(),
+            patches: Vec::new(),
+        }
+    }
+
+    pub fn assemble(mut self) -> Result<Chunk, AssemblerError> {
+        let mut line = 0;
+        for (index, text) in self.source.lines().enumerate() {
+            line = self.assemble_line(Cursor::new(text, index + 1), line)?;
+        }
+
+        self.patch()?;
+        Ok(self.chunk)
+    }
+
+    /// Assembles one source line. `previous` is the line of the last instruction, which listings
+    /// abbreviate as `|`. Returns the line of this instruction, or `previous` if there is none.
+    fn assemble_line(
+        &mut self,
+        mut cursor: Cursor<'a>,
+        previous: usize,
+    ) -> Result<usize, AssemblerError> {
+        if cursor.rest().starts_with("==") {
+            return Ok(previous);
+        }
+
+        if let Some(label) = cursor.label() {
+            if self.labels.insert(label, self.chunk.len()).is_some() {
+                return Err(cursor.error(format!("label '{}' defined twice.", label)));
+            }
+        }
+
+        let mut word = cursor.word();
+        if word.is_empty() {
+            return cursor.end().map(|_| previous);
+        }
+
+        // Listings start with the offset and the line, or `|` when the line did not change.
+        let mut line = cursor.number;
+        if word.bytes().all(|byte| byte.is_ascii_digit()) {
+            line = match cursor.word() {
+                "|" => previous,
+                column => cursor.integer(column, usize::MAX)?,
+            };
+            word = cursor.word();
+        }
+
+        let opcode =
+            opcode(word).ok_or_else(|| cursor.error(format!("unknown instruction '{}'.", word)))?;
+        let operand = self.chunk.len() + 1;
+        self.chunk.write(opcode as u8, line);
+        for _ in 0..opcode.operand_width() {
+            self.chunk.write(0, line);
+        }
+
+        let source_line = cursor.number;
+        match opcode {
+            OpCode::AddConstant => {
+                let constant = cursor.constant()?;
+                self.patches
+                    .push((operand, Patch::Constant(constant), source_line));
+            }
+            OpCode::DefineGlobal | OpCode::GetGlobal | OpCode::SetGlobal => {
+                let name = cursor.name()?;
+                self.patches
+                    .push((operand, Patch::Constant(name), source_line));
+                if opcode != OpCode::DefineGlobal {
+                    let cache = cursor.cache()?;
+                    self.patches
+                        .push((operand + 1, Patch::Cache(cache), source_line));
+                }
+            }
+            OpCode::GetLocal | OpCode::SetLocal => {
+                let slot = cursor.word();
+                self.chunk.code_mut()[operand] = cursor.integer(slot, MAX_INDEX)? as u8;
+            }
+            OpCode::JumpIfFalse | OpCode::JumpIfTrue | OpCode::Jump | OpCode::Loop => {
+                let target = cursor.target()?;
+                self.patches
+                    .push((operand, Patch::Jump(opcode, target), source_line));
+            }
+            _ => {}
+        }
+
+        cursor.end()?;
+        Ok(line)
+    }
+
+    /// Fills in constants, caches and jumps.
+    ///
+    /// Indexed constants and caches keep the index the listing gave them, the rest are numbered
+    /// after the highest one.
+    fn patch(&mut self) -> Result<(), AssemblerError> {
+        let mut constants: Vec<Option<Literal>> = Vec::new();
+        let mut caches = 0;
+        for (_, patch, line) in &self.patches {
+            match patch {
+                Patch::Constant(Constant::Indexed(index, literal)) => {
+                    if *index >= constants.len() {
+                        constants.resize(index + 1, None);
+                    }
+                    match &constants[*index] {
+                        Some(known) if known != literal => {
+                            return Err(AssemblerError::new(
+                                format!("constant {} defined twice.", index),
+                                *line,
+                            ))
+                        }
+                        _ => constants[*index] = Some(literal.clone()),
+                    }
+                }
+                Patch::Cache(Some(index)) => caches = caches.max(index + 1),
+                _ => {}
+            }
+        }
+
+        for (offset, patch, line) in &self.patches {
+            let byte = match patch {
+                Patch::Constant(Constant::Indexed(index, _)) => *index,
+                Patch::Constant(Constant::Literal(literal)) => {
+                    constants.push(Some(literal.clone()));
+                    constants.len() - 1
+                }
+                Patch::Cache(Some(index)) => *index,
+                Patch::Cache(None) => {
+                    caches += 1;
+                    caches - 1
+                }
+                Patch::Jump(opcode, target) => {
+                    let jump = self.jump(*opcode, *offset - 1, target, *line)?;
+                    self.chunk.code_mut()[*offset..*offset + 2]
+                        .copy_from_slice(&jump.to_le_bytes());
+                    continue;
+                }
+            };
+
+            if byte > MAX_INDEX {
+                let what = match patch {
+                    Patch::Cache(_) => "inline caches",
+                    _ => "constants",
+                };
+                return Err(AssemblerError::new(format!("too many {}.", what), *line));
+            }
+            self.chunk.code_mut()[*offset] = byte as u8;
+        }
+
+        for literal in constants {
+            let value = match literal.unwrap_or(Literal::Nil) {
+                Literal::Number(number) => Value::Number(number),
+                Literal::Boolean(boolean) => Value::Boolean(boolean),
+                Literal::Nil => Value::Nil,
+                Literal::String(string) => {
+                    let handle = self
+                        .heap
+                        .allocate_string(LoxString::new(&string))
+                        .map_err(|_| AssemblerError::new("out of memory.".into(), 0))?;
+                    Value::String(handle)
+                }
+            };
+            self.chunk.add_constant(value);
+        }
+        for _ in 0..caches {
+            self.chunk.add_cache();
+        }
+
+        Ok(())
+    }
+
+    /// The operand of the jump at `offset` that reaches `target`.
+    fn jump(
+        &self,
+        opcode: OpCode,
+        offset: usize,
+        target: &Target,
+        line: usize,
+    ) -> Result<u16, AssemblerError> {
+        let target = match target {
+            Target::Offset(target) => *target,
+            Target::Label(label) => *self.labels.get(label.as_str()).ok_or_else(|| {
+                AssemblerError::new(format!("undefined label '{}'.", label), line)
+            })?,
+        };
+
+        let next = offset + 3;
+        let jump = match opcode {
+            OpCode::Loop => next.checked_sub(target),
+            _ => target.checked_sub(next),
+        };
+        let direction = match opcode {
+            OpCode::Loop => "backward",
+            _ => "forward",
+        };
+
+        jump.and_then(|jump| u16::try_from(jump).ok())
+            .ok_or_else(|| {
+                AssemblerError::new(
+                    format!("{} can't jump {} to {}.", opcode, direction, target),
+                    line,
+                )
+            })
+    }
+}
+
+/// Finds the opcode `OpCode`'s `Display` spells as `name`.
+fn opcode(name: &str) -> Option<OpCode> {
+    (0..=u8::MAX)
+        .map_while(OpCode::from_repr)
+        .find(|opcode| opcode.to_string() == name)
+}
+
+/// Reads the parts of a single source line.
+struct Cursor<'a> {
+    text: &'a str,
+    number: usize,
+}
+
+impl<'a> Cursor<'a> {
+    fn new(text: &'a str, number: usize) -> Self {
+        Self {
+            text: text.trim_start(),
+            number,
+        }
+    }
+
+    fn error(&self, msg: String) -> AssemblerError {
+        AssemblerError::new(msg, self.number)
+    }
+
+    fn rest(&self) -> &'a str {
+        self.text
+    }
+
+    fn advance(&mut self, len: usize) {
+        self.text = self.text[len..].trim_start();
+    }
+
+    /// The next run of characters up to whitespace or a comment.
+    fn word(&mut self) -> &'a str {
+        let len = self
+            .text
+            .find(|c: char| c.is_whitespace() || c == ';')
+            .unwrap_or(self.text.len());
+        let word = &self.text[..len];
+        self.advance(len);
+        word
+    }
+
+    fn label(&mut self) -> Option<&'a str> {
+        let len = self
+            .text
+            .find(|c: char| !(c.is_alphanumeric() || c == '_'))?;
+        if len == 0 || !self.text[len..].starts_with(':') {
+            return None;
+        }
+
+        let label = &self.text[..len];
+        self.advance(len + 1);
+        Some(label)
+    }
+
+    fn integer(&self, word: &str, max: usize) -> Result<usize, AssemblerError> {
+        match word.parse() {
+            Ok(integer) if integer <= max => Ok(integer),
+            _ => Err(self.error(format!(
+                "expected an integer up to {}, found '{}'.",
+                max, word
+            ))),
+        }
+    }
+
+    /// A constant operand: a literal, or an index followed by the value in single quotes.
+    fn constant(&mut self) -> Result<Constant, AssemblerError> {
+        if self.text.starts_with('"') {
+            return self.string().map(Constant::Literal);
+        }
+
+        let word = self.word();
+        let literal = match word {
+            "true" => Literal::Boolean(true),
+            "false" => Literal::Boolean(false),
+            "nil" => Literal::Nil,
+            _ if self.text.starts_with('\'') => {
+                let index = self.integer(word, MAX_INDEX)?;
+                let value = self.quoted()?;
+                let literal = match value {
+                    "true" => Literal::Boolean(true),
+                    "false" => Literal::Boolean(false),
+                    "nil" => Literal::Nil,
+                    _ if value.len() >= 2 && value.starts_with('"') && value.ends_with('"') => {
+                        Literal::String(value[1..value.len() - 1].to_string())
+                    }
+                    _ => Literal::Number(value.parse().map_err(|_| {
+                        self.error(format!("expected a constant, found '{}'.", value))
+                    })?),
+                };
+                return Ok(Constant::Indexed(index, literal));
+            }
+            _ => Literal::Number(
+                word.parse()
+                    .map_err(|_| self.error(format!("expected a constant, found '{}'.", word)))?,
+            ),
+        };
+
+        Ok(Constant::Literal(literal))
+    }
+
+    /// A global name: an identifier, a string, or an index followed by the name in quotes.
+    fn name(&mut self) -> Result<Constant, AssemblerError> {
+        if self.text.starts_with('"') {
+            return self.string().map(Constant::Literal);
+        }
+
+        let word = self.word();
+        if self.text.starts_with('\'') {
+            let index = self.integer(word, MAX_INDEX)?;
+            let name = self.quoted()?.to_string();
+            return Ok(Constant::Indexed(index, Literal::String(name)));
+        }
+
+        match word.chars().next() {
+            Some(c) if c.is_alphabetic() || c == '_' => {
+                Ok(Constant::Literal(Literal::String(word.to_string())))
+            }
+            _ => Err(self.error(format!("expected a variable name, found '{}'.", word))),
+        }
+    }
+
+    fn string(&mut self) -> Result<Literal, AssemblerError> {
+        let len = self.text[1..]
+            .find('"')
+            .ok_or_else(|| self.error("unterminated string.".into()))?;
+        let string = self.text[1..len + 1].to_string();
+        self.advance(len + 2);
+        Ok(Literal::String(string))
+    }
+
+    /// A value in single quotes, as listings print it. The value may itself contain quotes, so it
+    /// runs to the last quote on the line.
+    fn quoted(&mut self) -> Result<&'a str, AssemblerError> {
+        let end = self.text.rfind('\'').filter(|end| *end > 0);
+        let end = end.ok_or_else(|| self.error("unterminated quoted value.".into()))?;
+        let value = &self.text[1..end];
+        self.advance(end + 1);
+        Ok(value)
+    }
+
+    /// The inline cache listings print as `(cache N)`, if any.
+    fn cache(&mut self) -> Result<Option<usize>, AssemblerError> {
+        if !self.text.starts_with("(cache") {
+            return Ok(None);
+        }
+
+        self.advance("(cache".len());
+        let len = self.text.find(')').unwrap_or(self.text.len());
+        let index = self.integer(self.text[..len].trim(), MAX_INDEX)?;
+        self.advance((len + 1).min(self.text.len()));
+        Ok(Some(index))
+    }
+
+    /// A jump target: a label, an absolute offset, or `offset -> target` as listings print it.
+    fn target(&mut self) -> Result<Target, AssemblerError> {
+        let mut word = self.word();
+        if self.text.starts_with("->") {
+            self.advance(2);
+            word = self.word();
+        }
+
+        match word.chars().next() {
+            Some(c) if c.is_ascii_digit() => Ok(Target::Offset(self.integer(word, usize::MAX)?)),
+            Some(_) => Ok(Target::Label(word.to_string())),
+            None => Err(self.error("expected a jump target.".into())),
+        }
+    }
+
+    /// Checks nothing but a comment is left.
+    fn end(&self) -> Result<(), AssemblerError> {
+        if self.text.is_empty() || self.text.starts_with(';') {
+            Ok(())
+        } else {
+            Err(self.error(format!("unexpected '{}'.", self.text)))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bytecode::Disassembler;
+    use crate::compiler::Compiler;
+
+    /// Assembles `source` into a throwaway heap, so only the error is usable.
+    fn error(source: &str) -> (String, usize) {
+        let error = Assembler::new(source, &mut Heap::new())
+            .assemble()
+            .unwrap_err();
+        (error.msg().to_string(), error.line())
+    }
+
+    #[test]
+    fn assembles_labels_and_literals() {
+        let mut heap = Heap::new();
+        let source = "        OP_CONSTANT 1.5
+            loop:   OP_GET_LOCAL 0     ; comment
+
+                    OP_JUMP_IF_FALSE done
+                    OP_GET_GLOBAL \"a b\"
+                    OP_SET_GLOBAL a
+                    OP_LOOP loop
+            done:   OP_RETURN";
+        let chunk = Assembler::new(source, &mut heap).assemble().unwrap();
+
+        let [low, high] = 9u16.to_le_bytes();
+        let [back_low, back_high] = 14u16.to_le_bytes();
+        let code = [
+            OpCode::AddConstant as u8,
+            0,
+            OpCode::GetLocal as u8,
+            0,
+            OpCode::JumpIfFalse as u8,
+            low,
+            high,
+            OpCode::GetGlobal as u8,
+            1,
+            0,
+            OpCode::SetGlobal as u8,
+            2,
+            1,
+            OpCode::Loop as u8,
+            back_low,
+            back_high,
+            OpCode::Return as u8,
+        ];
+        assert_eq!(&code, &chunk.code()[..]);
+        assert_eq!(
+            vec![1, 1, 2, 2, 4, 4, 4],
+            chunk.lines().iter().take(7).collect::<Vec<_>>()
+        );
+        assert_eq!(Value::Number(1.5), chunk.constants()[0]);
+        assert_eq!(
+            Some("a b"),
+            chunk.constants()[1].as_string().map(|s| s.as_str())
+        );
+        assert_eq!(2, chunk.caches().len());
+    }
+
+    #[test]
+    fn round_trips_disassembler_output() {
+        let mut heap = Heap::new();
+        let source = "var a = \"it's\";\nvar b = nil;\n{\n  var c = 1;\n  while (c < 3 and true) {\n    c = c + 1;\n    a = a + \"!\";\n  }\n  print a or b;\n}\nvar d = \"1\";\nprint d + \"x\";";
+        let chunk = Compiler::new(None).compile(source, &mut heap).unwrap();
+        let listing = Disassembler::disassemble_chunk(&chunk, "code");
+
+        let assembled = Assembler::new(&listing, &mut heap).assemble().unwrap();
+
+        assert_eq!(&chunk.code()[..], &assembled.code()[..]);
+        assert_eq!(chunk.lines(), assembled.lines());
+        assert_eq!(&chunk.constants()[..], &assembled.constants()[..]);
+        assert_eq!(chunk.caches().len(), assembled.caches().len());
+    }
+
+    #[test]
+    fn reports_errors_with_lines() {
+        assert_eq!(
+            ("unknown instruction 'OP_CALL'.".into(), 2),
+            error("OP_NIL\nOP_CALL")
+        );
+        assert_eq!(
+            ("undefined label 'end'.".into(), 1),
+            error("OP_JUMP end\nOP_RETURN")
+        );
+        assert_eq!(
+            ("OP_LOOP can't jump backward to 4.".into(), 1),
+            error("OP_LOOP 4\nOP_RETURN")
+        );
+        assert_eq!(
+            ("expected an integer up to 255, found '256'.".into(), 1),
+            error("OP_GET_LOCAL 256")
+        );
+        assert_eq!(
+            ("label 'a' defined twice.".into(), 2),
+            error("a: OP_NIL\na: OP_RETURN")
+        );
+        assert_eq!(("unexpected 'OP_POP'.".into(), 1), error("OP_NIL OP_POP"));
+    }
+}
@@ -0,0 +1,283 @@
+//! The syntax tree of a Lox program, as built by the `Parser`.
+//!
+//! Every node carries the span of source it was parsed from. Operators keep the `TokenKind` they
+//! were written with.
+
+use std::fmt::Write;
+
+use crate::scanner::{Token, TokenKind};
+
+/// A range of the source.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub(crate) struct Span {
+    /// Offset of the start.
+    pub start: usize,
+    /// Offset one past the end.
+    pub end: usize,
+    /// Line of the start.
+    pub line: usize,
+    /// Line of the end.
+    pub end_line: usize,
+}
+
+impl Span {
+    pub fn of(token: &Token) -> Self {
+        let len = if token.is_eof() {
+            0
+        } else {
+            token.lexeme().len()
+        };
+
+        Self {
+            start: token.start,
+            end: token.start + len,
+            line: token.line,
+            end_line: token.line,
+        }
+    }
+
+    /// The span from the start of `self` to the end of `other`.
+    pub fn to(self, other: Span) -> Self {
+        Self {
+            start: self.start,
+            end: other.end,
+            line: self.line,
+            end_line: other.end_line,
+        }
+    }
+}
+
+/// A whole script.
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) struct Program {
+    pub statements: Vec<Stmt>,
+    /// Covers the source up to the end of the file.
+    pub span: Span,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) struct Stmt {
+    pub kind: StmtKind,
+    pub span: Span,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) enum StmtKind {
+    Print(Expr),
+    Expression(Expr),
+    Var {
+        name: Identifier,
+        initializer: Option<Expr>,
+    },
+    Block(Vec<Stmt>),
+    If {
+        condition: Expr,
+        then_branch: Box<Stmt>,
+        else_branch: Option<Box<Stmt>>,
+    },
+    While {
+        condition: Expr,
+        body: Box<Stmt>,
+    },
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) struct Expr {
+    pub kind: ExprKind,
+    pub span: Span,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) enum ExprKind {
+    Literal(Literal),
+    Variable(Identifier),
+    Assign {
+        name: Identifier,
+        value: Box<Expr>,
+    },
+    Unary {
+        operator: Operator,
+        operand: Box<Expr>,
+    },
+    Binary {
+        left: Box<Expr>,
+        operator: Operator,
+        right: Box<Expr>,
+    },
+    /// `and` and `or`, which only evaluate their right operand when needed.
+    Logical {
+        left: Box<Expr>,
+        operator: Operator,
+        right: Box<Expr>,
+    },
+    Grouping(Box<Expr>),
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) enum Literal {
+    Nil,
+    Boolean(bool),
+    Number(f64),
+    String(String),
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(crate) struct Identifier {
+    pub name: String,
+    pub span: Span,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) struct Operator {
+    pub kind: TokenKind,
+    pub span: Span,
+}
+
+impl Operator {
+    /// How the operator is written in source.
+    pub fn symbol(&self) -> &'static str {
+        match self.kind {
+            TokenKind::Minus => "-",
+            TokenKind::Plus => "+",
+            TokenKind::Slash => "/",
+            TokenKind::Star => "*",
+            TokenKind::Bang => "!",
+            TokenKind::BangEqual => "!=",
+            TokenKind::EqualEqual => "==",
+            TokenKind::Greater => ">",
+            TokenKind::GreaterEqual => ">=",
+            TokenKind::Less => "<",
+            TokenKind::LessEqual => "<=",
+            TokenKind::And => "and",
+            TokenKind::Or => "or",
+            _ => "?",
+        }
+    }
+}
+
+/// Prints the tree, one node per line, indented by depth and followed by its span.
+pub(crate) fn dump(program: &Program) -> String {
+    let mut out = String::new();
+    node(&mut out, 0, "Program", program.span);
+    for statement in &program.statements {
+        dump_stmt(&mut out, 1, statement);
+    }
+    out
+}
+
+fn dump_stmt(out: &mut String, depth: usize, stmt: &Stmt) {
+    match &stmt.kind {
+        StmtKind::Print(expr) => {
+            node(out, depth, "Print", stmt.span);
+            dump_expr(out, depth + 1, expr);
+        }
+        StmtKind::Expression(expr) => {
+            node(out, depth, "Expression", stmt.span);
+            dump_expr(out, depth + 1, expr);
+        }
+        StmtKind::Var { name, initializer } => {
+            node(out, depth, &format!("Var {}", name.name), stmt.span);
+            if let Some(initializer) = initializer {
+                dump_expr(out, depth + 1, initializer);
+            }
+        }
+        StmtKind::Block(statements) => {
+            node(out, depth, "Block", stmt.span);
+            for statement in statements {
+                dump_stmt(out, depth + 1, statement);
+            }
+        }
+        StmtKind::If {
+            condition,
+            then_branch,
+            else_branch,
+        } => {
+            node(out, depth, "If", stmt.span);
+            dump_expr(out, depth + 1, condition);
+            dump_stmt(out, depth + 1, then_branch);
+            if let Some(else_branch) = else_branch {
+                dump_stmt(out, depth + 1, else_branch);
+            }
+        }
+        StmtKind::While { condition, body } => {
+            node(out, depth, "While", stmt.span);
+            dump_expr(out, depth + 1, condition);
+            dump_stmt(out, depth + 1, body);
+        }
+    }
+}
+
+fn dump_expr(out: &mut String, depth: usize, expr: &Expr) {
+    match &expr.kind {
+        ExprKind::Literal(literal) => {
+            let value = match literal {
+                Literal::Nil => "nil".to_string(),
+                Literal::Boolean(boolean) => boolean.to_string(),
+                Literal::Number(number) => number.to_string(),
+                Literal::String(string) => format!("\"{}\"", string),
+            };
+            node(out, depth, &format!("Literal {}", value), expr.span);
+        }
+        ExprKind::Variable(name) => {
+            node(out, depth, &format!("Variable {}", name.name), expr.span);
+        }
+        ExprKind::Assign { name, value } => {
+            node(out, depth, &format!("Assign {}", name.name), expr.span);
+            dump_expr(out, depth + 1, value);
+        }
+        ExprKind::Unary { operator, operand } => {
+            node(
+                out,
+                depth,
+                &format!("Unary {}", operator.symbol()),
+                expr.span,
+            );
+            dump_expr(out, depth + 1, operand);
+        }
+        ExprKind::Binary {
+            left,
+            operator,
+            right,
+        } => {
+            node(
+                out,
+                depth,
+                &format!("Binary {}", operator.symbol()),
+                expr.span,
+            );
+            dump_expr(out, depth + 1, left);
+            dump_expr(out, depth + 1, right);
+        }
+        ExprKind::Logical {
+            left,
+            operator,
+            right,
+        } => {
+            node(
+                out,
+                depth,
+                &format!("Logical {}", operator.symbol()),
+                expr.span,
+            );
+            dump_expr(out, depth + 1, left);
+            dump_expr(out, depth + 1, right);
+        }
+        ExprKind::Grouping(inner) => {
+            node(out, depth, "Grouping", expr.span);
+            dump_expr(out, depth + 1, inner);
+        }
+    }
+}
+
+fn node(out: &mut String, depth: usize, label: &str, span: Span) {
+    writeln!(
+        out,
+        "{}{} [{}:{}..{}]",
+        "  ".repeat(depth),
+        label,
+        span.line,
+        span.start,
+        span.end
+    )
+    .unwrap();
+}
@@ -1,4 +1,5 @@
 use std::{
+    cell::Cell,
     fmt::{Debug, Display, Write},
     ops::{Deref, DerefMut},
 };
@@ -15,7 +16,133 @@ use rlox_common::Array;
 pub(crate) struct Chunk {
     code: Array<u8>,
     constants: Constants,
-    lines: Array<usize>,
+    lines: LineTable,
+    caches: Array<InlineCache>,
+    locals: Vec<LocalInfo>,
+}
+
+/// The source line of every byte of code, run-length encoded.
+///
+/// Consecutive bytes nearly always come from the same line, so the table keeps one entry per
+/// change of line instead of one per byte.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub(crate) struct LineTable {
+    /// The offset each run starts at and its line, by increasing offset.
+    runs: Vec<(usize, usize)>,
+    /// Number of bytes covered.
+    len: usize,
+}
+
+impl LineTable {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Records the line of the next byte.
+    pub fn push(&mut self, line: usize) {
+        if self.last() != Some(line) {
+            self.runs.push((self.len, line));
+        }
+        self.len += 1;
+    }
+
+    /// Forgets the lines of the bytes from `len` on.
+    pub fn truncate(&mut self, len: usize) {
+        if len >= self.len {
+            return;
+        }
+        self.len = len;
+        let runs = self.runs.partition_point(|(start, _)| *start < len);
+        self.runs.truncate(runs);
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// The line of the byte at `offset`, or `None` past the end of the table.
+    pub fn line_for_offset(&self, offset: usize) -> Option<usize> {
+        if offset >= self.len {
+            return None;
+        }
+        let run = self.runs.partition_point(|(start, _)| *start <= offset) - 1;
+        Some(self.runs[run].1)
+    }
+
+    /// The line of the last byte.
+    pub fn last(&self) -> Option<usize> {
+        self.runs.last().map(|(_, line)| *line)
+    }
+
+    /// Each run as its line and the number of bytes it covers, in code order.
+    pub fn runs(&self) -> impl Iterator<Item = (usize, usize)> + '_ {
+        self.runs.iter().enumerate().map(|(index, (start, line))| {
+            let end = self.runs.get(index + 1).map_or(self.len, |(end, _)| *end);
+            (*line, end - start)
+        })
+    }
+
+    /// The line of every byte, in code order.
+    pub fn iter(&self) -> impl Iterator<Item = usize> + '_ {
+        self.runs()
+            .flat_map(|(line, count)| std::iter::repeat_n(line, count))
+    }
+}
+
+/// Debug information about a local variable.
+///
+/// The VM only knows locals by their stack slot. Tools showing the code to people, like the
+/// decompiler, use this to name them again.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(crate) struct LocalInfo {
+    pub name: std::string::String,
+    pub slot: u8,
+    /// Offset of the first instruction after the initializer.
+    pub start: usize,
+    /// Offset of the `OP_POP` that ends the scope of the variable.
+    pub end: usize,
+}
+
+/// An inline cache for a global variable access.
+///
+/// `OP_GET_GLOBAL` and `OP_SET_GLOBAL` carry the index of their cache as a second operand. The
+/// cache remembers which slot of the VM's global table the name resolved to, so later executions
+/// skip the hash lookup. Globals are never removed, so a slot stays valid once filled.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub(crate) struct InlineCache {
+    slot: Cell<Option<u32>>,
+    hits: Cell<u64>,
+    misses: Cell<u64>,
+}
+
+impl InlineCache {
+    /// Returns the cached slot, counting the lookup as a hit or a miss.
+    #[inline]
+    pub fn lookup(&self) -> Option<usize> {
+        match self.slot.get() {
+            Some(slot) => {
+                self.hits.set(self.hits.get() + 1);
+                Some(slot as usize)
+            }
+            None => {
+                self.misses.set(self.misses.get() + 1);
+                None
+            }
+        }
+    }
+
+    #[inline]
+    pub fn fill(&self, slot: usize) {
+        self.slot.set(Some(slot as u32));
+    }
+
+    pub fn hits(&self) -> u64 {
+        self.hits.get()
+    }
+
+    pub fn misses(&self) -> u64 {
+        self.misses.get()
+    }
 }
 
 #[derive(Clone, PartialEq)]
@@ -58,13 +185,23 @@ impl Chunk {
         Self {
             code: Array::new(),
             constants: Constants::new(),
-            lines: Array::new(),
+            lines: LineTable::new(),
+            caches: Array::new(),
+            locals: Vec::new(),
         }
     }
 
     pub fn write(&mut self, byte: u8, line: usize) {
         self.code.write(byte);
-        self.lines.write(line);
+        self.lines.push(line);
+    }
+
+    /// Shortens the chunk to `len` bytes, keeping the line information in sync.
+    pub fn truncate(&mut self, len: usize) {
+        while self.code.len() > len {
+            self.code.pop();
+        }
+        self.lines.truncate(len);
     }
 
     pub fn add_constant(&mut self, value: Value) -> usize {
@@ -72,6 +209,28 @@ impl Chunk {
         self.constants.len() - 1
     }
 
+    pub fn add_cache(&mut self) -> usize {
+        self.caches.write(InlineCache::default());
+        self.caches.len() - 1
+    }
+
+    pub fn caches(&self) -> &Array<InlineCache> {
+        &self.caches
+    }
+
+    pub fn add_local(&mut self, local: LocalInfo) -> usize {
+        self.locals.push(local);
+        self.locals.len() - 1
+    }
+
+    pub fn locals(&self) -> &[LocalInfo] {
+        &self.locals
+    }
+
+    pub fn locals_mut(&mut self) -> &mut [LocalInfo] {
+        &mut self.locals
+    }
+
     pub fn ptr(&self) -> *mut u8 {
         self.code.as_ptr()
     }
@@ -96,12 +255,15 @@ impl Chunk {
         &self.constants
     }
 
-    pub fn constants_mut(&mut self) -> &mut Constants {
-        &mut self.constants
+    pub fn lines(&self) -> &LineTable {
+        &self.lines
     }
 
-    pub fn lines(&self) -> &Array<usize> {
-        &self.lines
+    /// The source line of the instruction at `offset`, which must be inside the code.
+    pub fn line_for_offset(&self, offset: usize) -> usize {
+        self.lines
+            .line_for_offset(offset)
+            .expect("every byte of code has a line")
     }
 }
 
@@ -112,7 +274,7 @@ impl Debug for Chunk {
     }
 }
 
-#[derive(FromRepr, Debug, PartialEq)]
+#[derive(Clone, Copy, FromRepr, Debug, PartialEq)]
 #[repr(u8)]
 pub(crate) enum OpCode {
     Return,
@@ -122,7 +284,9 @@ pub(crate) enum OpCode {
     AddFalse,
     Equal,
     Greater,
+    GreaterEqual,
     Less,
+    LessEqual,
     Negate,
     Add,
     Substract,
@@ -137,10 +301,78 @@ pub(crate) enum OpCode {
     GetLocal,
     SetLocal,
     JumpIfFalse,
+    JumpIfTrue,
+    Jump,
+    Loop,
+}
+
+/// What follows an opcode in the code.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum Operands {
+    None,
+    /// The index of a constant.
+    Constant,
+    /// The stack slot of a local variable.
+    Local,
+    /// The index of the constant naming the variable, then the index of its inline cache.
+    Global,
+    /// A 16-bit offset forward from the next instruction.
     Jump,
+    /// A 16-bit offset backward from the next instruction.
     Loop,
 }
 
+impl Operands {
+    /// Number of operand bytes.
+    pub fn width(&self) -> usize {
+        match self {
+            Operands::None => 0,
+            Operands::Constant | Operands::Local => 1,
+            Operands::Global | Operands::Jump | Operands::Loop => 2,
+        }
+    }
+}
+
+impl OpCode {
+    /// The operands following the opcode. Everything that decodes bytecode goes through this.
+    pub fn operands(&self) -> Operands {
+        match self {
+            OpCode::Return
+            | OpCode::AddNil
+            | OpCode::AddTrue
+            | OpCode::AddFalse
+            | OpCode::Equal
+            | OpCode::Greater
+            | OpCode::GreaterEqual
+            | OpCode::Less
+            | OpCode::LessEqual
+            | OpCode::Negate
+            | OpCode::Add
+            | OpCode::Substract
+            | OpCode::Multiply
+            | OpCode::Divide
+            | OpCode::Not
+            | OpCode::Print
+            | OpCode::Pop => Operands::None,
+            OpCode::AddConstant | OpCode::DefineGlobal => Operands::Constant,
+            OpCode::GetLocal | OpCode::SetLocal => Operands::Local,
+            OpCode::GetGlobal | OpCode::SetGlobal => Operands::Global,
+            OpCode::JumpIfFalse | OpCode::JumpIfTrue | OpCode::Jump => Operands::Jump,
+            OpCode::Loop => Operands::Loop,
+        }
+    }
+
+    /// Number of operand bytes following the opcode.
+    pub fn operand_width(&self) -> usize {
+        self.operands().width()
+    }
+
+    /// Returns `true` for instructions that transfer control to a jump target.
+    pub fn is_jump(&self) -> bool {
+        matches!(self.operands(), Operands::Jump | Operands::Loop)
+    }
+}
+
 impl Display for OpCode {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         let me_str = match self {
@@ -151,7 +383,9 @@ impl Display for OpCode {
             OpCode::AddFalse => "OP_FALSE",
             OpCode::Equal => "OP_EQUAL",
             OpCode::Greater => "OP_GREATER",
+            OpCode::GreaterEqual => "OP_GREATER_EQUAL",
             OpCode::Less => "OP_LESS",
+            OpCode::LessEqual => "OP_LESS_EQUAL",
             OpCode::Negate => "OP_NEGATE",
             OpCode::Add => "OP_ADD",
             OpCode::Substract => "OP_SUBSTRACT",
@@ -166,42 +400,84 @@ impl Display for OpCode {
             OpCode::GetLocal => "OP_GET_LOCAL",
             OpCode::SetLocal => "OP_SET_LOCAL",
             OpCode::JumpIfFalse => "OP_JUMP_IF_FALSE",
+            OpCode::JumpIfTrue => "OP_JUMP_IF_TRUE",
             OpCode::Jump => "OP_JUMP",
             OpCode::Loop => "OP_LOOP",
         };
 
-        write!(f, "{}", me_str)
+        f.pad(me_str)
     }
 }
 
+/// What a `Disassembler` listing shows besides the instructions.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct DisassemblerOptions {
+    /// Show the bytes of each instruction.
+    pub raw_bytes: bool,
+    /// Name jump targets `L0`, `L1`, ... and mark them in the listing.
+    pub labels: bool,
+    /// List the constant pool before the code.
+    pub constants: bool,
+    /// Follow each function constant with the listing of its chunk.
+    pub functions: bool,
+    /// Draw the control-flow graph in Graphviz DOT instead of listing the code.
+    pub cfg: bool,
+}
+
+/// A bytecode disassembler.
+///
+/// Takes a Chunk as an input and disassembles the bytecode into a human readable format. It
+/// never trusts the code: invalid opcodes, truncated instructions and out of range operands are
+/// shown as such instead of stopping the listing.
 #[derive(Debug)]
 pub(crate) struct Disassembler<'d> {
     chunk: &'d Chunk,
     name: &'d str,
+    options: DisassemblerOptions,
+    /// Jump targets, in order, when listing labels.
+    labels: Vec<usize>,
     offset: usize,
     output: String,
 }
 
-/// A bytecode disassembler.
-///
-/// Takes a Chunk as an input and disassembles the bytecode into a human readable format.
 impl<'d> Disassembler<'d> {
     pub fn new(chunk: &'d Chunk, name: &'d str) -> Self {
-        Self {
+        Self::with_options(chunk, name, DisassemblerOptions::default())
+    }
+
+    pub fn with_options(chunk: &'d Chunk, name: &'d str, options: DisassemblerOptions) -> Self {
+        let mut disassembler = Self {
             chunk,
             name,
+            options,
+            labels: Vec::new(),
             offset: 0,
             output: String::new(),
+        };
+        if options.labels {
+            disassembler.labels = disassembler.jump_targets();
         }
+        disassembler
     }
 
     pub fn disassemble(&mut self) -> &str {
-        writeln!(self.output, "== {} ==", self.name);
+        writeln!(self.output, "== {} ==", self.name).unwrap();
+
+        if self.options.constants {
+            self.constant_pool();
+        }
 
         while self.offset < self.chunk.code.len() {
+            if let Some(label) = self.label(self.offset) {
+                writeln!(self.output, "{}:", label).unwrap();
+            }
             self.disassemble_current_instruction();
         }
 
+        if self.options.functions {
+            self.functions();
+        }
+
         &self.output
     }
 
@@ -212,93 +488,177 @@ impl<'d> Disassembler<'d> {
     pub fn disassemble_instruction(&mut self, offset: usize) -> String {
         // This is so we can keep using the instance after we have called this function.
         let old_offset = self.offset;
+        let old_len = self.output.len();
         self.set_offset(offset);
-        let result = self.disassemble_current_instruction().to_owned();
+        self.disassemble_current_instruction();
+        let result = self.output.split_off(old_len);
         self.set_offset(old_offset);
         result
     }
 
-    fn disassemble_current_instruction(&mut self) -> &str {
-        write!(self.output, "{:0<4} ", self.offset);
+    fn disassemble_current_instruction(&mut self) {
+        let code = &self.chunk.code;
+        write!(self.output, "{:04} ", self.offset).unwrap();
 
-        if self.offset > 0 && self.chunk.lines[self.offset] == self.chunk.lines[self.offset - 1] {
-            write!(self.output, "   | ");
+        let line = self.chunk.lines.line_for_offset(self.offset);
+        if self.offset > 0 && line == self.chunk.lines.line_for_offset(self.offset - 1) {
+            write!(self.output, "   | ").unwrap();
+        } else if let Some(line) = line {
+            write!(self.output, "{:04} ", line).unwrap();
         } else {
-            write!(self.output, "{:0>4} ", self.chunk.lines[self.offset]);
+            write!(self.output, "   ? ").unwrap();
         }
 
-        let opcode: OpCode =
-            OpCode::from_repr(self.chunk.code[self.offset]).expect("error fetching opcode");
-
-        match opcode {
-            OpCode::Return => self.simple_instruction("OP_RETURN"),
-            OpCode::AddConstant => self.constant_instruction("OP_CONSTANT"),
-            OpCode::AddNil => self.constant_instruction("OP_NIL"),
-            OpCode::AddTrue => self.constant_instruction("OP_TRUE"),
-            OpCode::AddFalse => self.constant_instruction("OP_FALSE"),
-            OpCode::Equal => self.constant_instruction("OP_EQUAL"),
-            OpCode::Greater => self.constant_instruction("OP_GREATER"),
-            OpCode::Less => self.constant_instruction("OP_LESS"),
-            OpCode::Negate => self.simple_instruction("OP_NEGATE"),
-            OpCode::Add => self.simple_instruction("OP_ADD"),
-            OpCode::Substract => self.simple_instruction("OP_SUBSTRACT"),
-            OpCode::Multiply => self.simple_instruction("OP_MULTIPLY"),
-            OpCode::Divide => self.simple_instruction("OP_DIVIDE"),
-            OpCode::Not => self.simple_instruction("OP_NOT"),
-            OpCode::Print => self.simple_instruction("OP_PRINT"),
-            OpCode::Pop => self.simple_instruction("OP_POP"),
-            OpCode::DefineGlobal => self.constant_instruction("OP_DEFINE_GLOBAL"),
-            OpCode::GetGlobal => self.constant_instruction("OP_GET_GLOBAL"),
-            OpCode::SetGlobal => self.constant_instruction("OP_SET_GLOBAL"),
-            OpCode::GetLocal => self.byte_instruction("OP_GET_LOCAL"),
-            OpCode::SetLocal => self.byte_instruction("OP_SET_LOCAL"),
-            OpCode::JumpIfFalse => self.jump_instruction("OP_JUMP_IF_FALSE", 1),
-            OpCode::Jump => self.jump_instruction("OP_JUMP", 1),
-            OpCode::Loop => self.jump_instruction("OP_LOOP", -1),
-            _ => unreachable!(),
+        let Some(opcode) = OpCode::from_repr(code[self.offset]) else {
+            self.raw_bytes(1);
+            writeln!(self.output, "<invalid opcode {:#04x}>", code[self.offset]).unwrap();
+            self.offset += 1;
+            return;
         };
 
-        &self.output
+        let len = 1 + opcode.operand_width();
+        if self.offset + len > code.len() {
+            self.raw_bytes(code.len() - self.offset);
+            writeln!(self.output, "<truncated {}>", opcode).unwrap();
+            self.offset = code.len();
+            return;
+        }
+
+        self.raw_bytes(len);
+        let operands = &code[self.offset + 1..self.offset + len];
+        match opcode.operands() {
+            Operands::None => writeln!(self.output, "{}", opcode),
+            Operands::Constant => writeln!(
+                self.output,
+                "{:<16} {:<4} '{}'",
+                opcode,
+                operands[0],
+                match opcode {
+                    OpCode::DefineGlobal => self.constant(operands[0]),
+                    _ => self.literal(operands[0]),
+                }
+            ),
+            Operands::Local => writeln!(self.output, "{:<16} {:<4}", opcode, operands[0]),
+            Operands::Global => writeln!(
+                self.output,
+                "{:<16} {:<4} '{}' (cache {})",
+                opcode,
+                operands[0],
+                self.constant(operands[0]),
+                operands[1]
+            ),
+            Operands::Jump | Operands::Loop => {
+                let target = self.jump_target(self.offset);
+                let target = match target.and_then(|target| self.label(target)) {
+                    Some(label) => label,
+                    None => target.map_or("<out of range>".to_string(), |t| t.to_string()),
+                };
+                writeln!(
+                    self.output,
+                    "{:<16} {:<4} -> {}",
+                    opcode, self.offset, target
+                )
+            }
+        }
+        .unwrap();
+
+        self.offset += len;
     }
 
-    fn simple_instruction(&mut self, name: &str) {
-        writeln!(self.output, "{}", name);
-        self.offset += 1;
+    /// Writes the first `len` bytes of the current instruction, when raw bytes are enabled.
+    fn raw_bytes(&mut self, len: usize) {
+        if !self.options.raw_bytes {
+            return;
+        }
+
+        let bytes = &self.chunk.code[self.offset..self.offset + len];
+        let hex: Vec<_> = bytes.iter().map(|byte| format!("{:02x}", byte)).collect();
+        write!(self.output, "{:<9} ", hex.join(" ")).unwrap();
     }
 
-    fn constant_instruction(&mut self, name: &str) {
-        let constant_idx = self.chunk.code[self.offset + 1];
+    fn constant(&self, index: u8) -> String {
+        match self.chunk.constants.get(index as usize) {
+            Some(value) => value.to_string(),
+            None => "<out of range>".to_string(),
+        }
+    }
 
-        writeln!(
-            self.output,
-            "{:<16} {:<4} '{}'",
-            name, constant_idx, &self.chunk.constants[constant_idx as usize]
-        );
-        self.offset += 2;
+    /// The constant at `index` as a literal, with strings in double quotes so they read
+    /// differently from numbers and keywords.
+    fn literal(&self, index: u8) -> String {
+        match self.chunk.constants.get(index as usize) {
+            Some(Value::String(string)) => format!("\"{}\"", **string),
+            _ => self.constant(index),
+        }
     }
 
-    fn byte_instruction(&mut self, name: &str) {
-        let slot = self.chunk.code[self.offset + 1];
+    /// Where the jump at `offset` lands, if that is inside the code.
+    fn jump_target(&self, offset: usize) -> Option<usize> {
+        let code = &self.chunk.code;
+        let jump = u16::from_le_bytes([code[offset + 1], code[offset + 2]]) as usize;
+        let next = offset + 3;
+        let target = match OpCode::from_repr(code[offset])?.operands() {
+            Operands::Loop => next.checked_sub(jump)?,
+            _ => next + jump,
+        };
 
-        writeln!(self.output, "{:<16} {:<4}", name, slot);
-        self.offset += 2;
+        (target < code.len()).then_some(target)
     }
 
-    fn jump_instruction(&mut self, name: &str, sign: i16) {
-        let jump_bytes = [
-            self.chunk.code[self.offset + 1],
-            self.chunk.code[self.offset + 2],
-        ];
-        let jump = u16::from_ne_bytes(jump_bytes);
-        writeln!(
-            self.output,
-            "{:<16} {:<4} -> {}",
-            name,
-            self.offset,
-            (self.offset as i16) + 3 + (sign * jump as i16)
-        );
+    fn jump_targets(&self) -> Vec<usize> {
+        let code = &self.chunk.code;
+        let mut targets = Vec::new();
+        let mut offset = 0;
+
+        while offset < code.len() {
+            let Some(opcode) = OpCode::from_repr(code[offset]) else {
+                offset += 1;
+                continue;
+            };
+            let next = offset + 1 + opcode.operand_width();
+            if opcode.is_jump() && next <= code.len() {
+                targets.extend(self.jump_target(offset));
+            }
+            offset = next;
+        }
 
-        self.offset += 3;
+        targets.sort_unstable();
+        targets.dedup();
+        targets
+    }
+
+    fn label(&self, offset: usize) -> Option<String> {
+        let index = self.labels.binary_search(&offset).ok()?;
+        Some(format!("L{}", index))
+    }
+
+    fn constant_pool(&mut self) {
+        writeln!(self.output, "-- constants --").unwrap();
+        for (index, value) in self.chunk.constants.iter().enumerate() {
+            let kind = match value {
+                Value::Number(_) => "number",
+                Value::Boolean(_) => "boolean",
+                Value::Nil => "nil",
+                Value::String(_) => "string",
+                Value::Function(_) => "function",
+            };
+            writeln!(self.output, "{:04} {:<8} {}", index, kind, value).unwrap();
+        }
+        writeln!(self.output, "-- code --").unwrap();
+    }
+
+    fn functions(&mut self) {
+        for value in self.chunk.constants.iter() {
+            let Value::Function(function) = value else {
+                continue;
+            };
+            if let Some(chunk) = function.chunk() {
+                let name = format!("fn {}", function.name());
+                let mut disassembler = Disassembler::with_options(chunk, &name, self.options);
+                writeln!(self.output).unwrap();
+                self.output.push_str(disassembler.disassemble());
+            }
+        }
     }
 
     fn set_offset(&mut self, offset: usize) {
@@ -306,3 +666,149 @@ impl<'d> Disassembler<'d> {
         self.offset = offset;
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compiler::Compiler;
+    use crate::function::Function;
+    use crate::heap::Heap;
+    use crate::string::String as LoxString;
+
+    #[test]
+    fn line_table_stores_runs() {
+        let mut chunk = Chunk::new();
+        for line in [1, 1, 1, 2, 2, 5, 1] {
+            chunk.write(OpCode::Pop as u8, line);
+        }
+
+        assert_eq!(
+            vec![(1, 3), (2, 2), (5, 1), (1, 1)],
+            chunk.lines().runs().collect::<Vec<_>>()
+        );
+        assert_eq!(1, chunk.line_for_offset(2));
+        assert_eq!(2, chunk.line_for_offset(3));
+        assert_eq!(5, chunk.line_for_offset(5));
+        assert_eq!(1, chunk.line_for_offset(6));
+        assert_eq!(None, chunk.lines().line_for_offset(7));
+
+        chunk.truncate(4);
+        assert_eq!(
+            vec![(1, 3), (2, 1)],
+            chunk.lines().runs().collect::<Vec<_>>()
+        );
+        chunk.write(OpCode::Pop as u8, 2);
+        assert_eq!(
+            vec![1, 1, 1, 2, 2],
+            chunk.lines().iter().collect::<Vec<_>>()
+        );
+    }
+
+    fn listing(source: &str, options: DisassemblerOptions) -> String {
+        let mut heap = Heap::new();
+        let chunk = Compiler::new(None).compile(source, &mut heap).unwrap();
+        Disassembler::with_options(&chunk, "code", options)
+            .disassemble()
+            .to_string()
+    }
+
+    #[test]
+    fn lists_every_instruction_once() {
+        let source = "var a = 1;\nprint nil == (a < 2);";
+        let listing = listing(source, DisassemblerOptions::default());
+        let expected = "\
+== code ==
+0000 0001 OP_CONSTANT      1    '1'
+0002    | OP_DEFINE_GLOBAL 0    'a'
+0004 0002 OP_NIL
+0005    | OP_GET_GLOBAL    0    'a' (cache 0)
+0008    | OP_CONSTANT      2    '2'
+0010    | OP_LESS
+0011    | OP_EQUAL
+0012    | OP_PRINT
+0013    | OP_RETURN
+";
+
+        assert_eq!(expected, listing);
+    }
+
+    #[test]
+    fn labels_jump_targets() {
+        let options = DisassemblerOptions {
+            labels: true,
+            ..Default::default()
+        };
+        let listing = listing("while (true) print 1;", options);
+
+        assert!(listing.contains("L0:\n0000 0001 OP_TRUE"), "{}", listing);
+        assert!(listing.contains("-> L1\n"), "{}", listing);
+        assert!(
+            listing.contains("OP_LOOP          8    -> L0\nL1:\n"),
+            "{}",
+            listing
+        );
+    }
+
+    #[test]
+    fn lists_malformed_code() {
+        let mut chunk = Chunk::new();
+        for byte in [0xff, OpCode::AddConstant as u8, 7, OpCode::Jump as u8, 0] {
+            chunk.write(byte, 1);
+        }
+        let options = DisassemblerOptions {
+            raw_bytes: true,
+            ..Default::default()
+        };
+
+        let listing = Disassembler::with_options(&chunk, "bad", options)
+            .disassemble()
+            .to_string();
+
+        let expected = "\
+== bad ==
+0000 0001 ff        <invalid opcode 0xff>
+0001    | 01 07     OP_CONSTANT      7    '<out of range>'
+0003    | 19 00     <truncated OP_JUMP>
+";
+        assert_eq!(expected, listing);
+    }
+
+    #[test]
+    fn lists_constants_and_functions() {
+        let mut heap = Heap::new();
+        let mut inner = Chunk::new();
+        inner.write(OpCode::AddNil as u8, 1);
+        inner.write(OpCode::Return as u8, 1);
+        let function = Function::new(Some(inner), Some(LoxString::new("f")));
+
+        let mut chunk = Chunk::new();
+        chunk.add_constant(Value::Number(1.5));
+        chunk.add_constant(Value::Function(heap.allocate(function).unwrap()));
+        chunk.write(OpCode::Return as u8, 1);
+        let options = DisassemblerOptions {
+            constants: true,
+            functions: true,
+            ..Default::default()
+        };
+
+        let listing = Disassembler::with_options(&chunk, "code", options)
+            .disassemble()
+            .to_string();
+
+        let expected = "\
+== code ==
+-- constants --
+0000 number   1.5
+0001 function f
+-- code --
+0000 0001 OP_RETURN
+
+== fn f ==
+-- constants --
+-- code --
+0000 0001 OP_NIL
+0001    | OP_RETURN
+";
+        assert_eq!(expected, listing);
+    }
+}
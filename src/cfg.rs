@@ -0,0 +1,240 @@
+//! Basic blocks and the control-flow graph of a `Chunk`.
+
+use std::fmt::Write;
+
+use crate::bytecode::{Chunk, Disassembler, DisassemblerOptions, OpCode};
+
+/// How control gets from one block to the next.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum Edge {
+    /// Falls through to the following block.
+    Next,
+    /// Takes an unconditional `Jump` or `Loop`.
+    Jump,
+    /// Follows a conditional jump when the condition is truthy.
+    True,
+    /// Follows a conditional jump when the condition is falsey.
+    False,
+}
+
+/// A run of instructions only entered at the top and only left at the bottom.
+#[derive(Debug, PartialEq, Eq)]
+pub(crate) struct BasicBlock {
+    /// Offset of the first instruction.
+    pub start: usize,
+    /// Offset one past the last instruction.
+    pub end: usize,
+    /// Indices of the blocks control can go to next.
+    pub successors: Vec<(usize, Edge)>,
+}
+
+/// The basic blocks of a chunk, in code order.
+#[derive(Debug)]
+pub(crate) struct Cfg {
+    blocks: Vec<BasicBlock>,
+}
+
+impl Cfg {
+    /// Splits `chunk` into basic blocks. Returns `None` if the code is malformed, which the
+    /// verifier rules out.
+    ///
+    /// A block starts at the beginning of the code, at every jump target and after every jump or
+    /// return.
+    pub fn new(chunk: &Chunk) -> Option<Self> {
+        let code = chunk.code();
+        let mut instructions = Vec::new();
+        let mut leaders = vec![false; code.len() + 1];
+        leaders[0] = true;
+
+        let mut offset = 0;
+        while offset < code.len() {
+            let opcode = OpCode::from_repr(code[offset])?;
+            let next = offset + 1 + opcode.operand_width();
+            if next > code.len() {
+                return None;
+            }
+
+            if opcode.is_jump() {
+                let target = jump_target(chunk, opcode, offset)?;
+                *leaders.get_mut(target)? = true;
+            }
+            if opcode.is_jump() || opcode == OpCode::Return {
+                leaders[next] = true;
+            }
+
+            instructions.push((offset, opcode));
+            offset = next;
+        }
+
+        // Jumps into the middle of an instruction have no block to go to.
+        let starts: Vec<_> = instructions
+            .iter()
+            .map(|(offset, _)| *offset)
+            .filter(|offset| leaders[*offset])
+            .collect();
+        let block_at = |offset: usize| starts.binary_search(&offset).ok();
+
+        let mut blocks = Vec::with_capacity(starts.len());
+        for (index, start) in starts.iter().enumerate() {
+            let end = starts.get(index + 1).copied().unwrap_or(code.len());
+            let (last, opcode) = instructions
+                .iter()
+                .rev()
+                .find(|(offset, _)| *offset < end)
+                .copied()?;
+
+            let mut successors = Vec::new();
+            let target = || block_at(jump_target(chunk, opcode, last)?);
+            match opcode {
+                OpCode::Return => {}
+                OpCode::Jump | OpCode::Loop => successors.push((target()?, Edge::Jump)),
+                OpCode::JumpIfFalse => {
+                    successors.push((index + 1, Edge::True));
+                    successors.push((target()?, Edge::False));
+                }
+                OpCode::JumpIfTrue => {
+                    successors.push((target()?, Edge::True));
+                    successors.push((index + 1, Edge::False));
+                }
+                _ => successors.push((index + 1, Edge::Next)),
+            }
+            // Falling off the end of the code leads nowhere.
+            successors.retain(|(block, _)| *block < starts.len());
+
+            blocks.push(BasicBlock {
+                start: *start,
+                end,
+                successors,
+            });
+        }
+
+        Some(Self { blocks })
+    }
+
+    #[cfg(test)]
+    pub fn blocks(&self) -> &[BasicBlock] {
+        &self.blocks
+    }
+
+    /// Renders the graph in Graphviz DOT, one node per block listing its instructions.
+    pub fn to_dot(&self, chunk: &Chunk, name: &str, options: DisassemblerOptions) -> String {
+        let mut dot = String::new();
+        let mut disassembler = Disassembler::with_options(chunk, name, options);
+
+        writeln!(dot, "digraph \"{}\" {{", escape(name)).unwrap();
+        writeln!(dot, "    node [shape=box, fontname=\"monospace\"];").unwrap();
+
+        for (index, block) in self.blocks.iter().enumerate() {
+            let mut label = String::new();
+            let mut offset = block.start;
+            while offset < block.end {
+                let instruction = disassembler.disassemble_instruction(offset);
+                write!(label, "{}\\l", escape(instruction.trim_end())).unwrap();
+                let opcode = OpCode::from_repr(chunk.code()[offset]).unwrap();
+                offset += 1 + opcode.operand_width();
+            }
+            writeln!(dot, "    b{} [label=\"{}\"];", index, label).unwrap();
+        }
+
+        for (index, block) in self.blocks.iter().enumerate() {
+            for (successor, edge) in &block.successors {
+                let label = match edge {
+                    Edge::True => " [label=\"true\"]",
+                    Edge::False => " [label=\"false\"]",
+                    Edge::Next | Edge::Jump => "",
+                };
+                writeln!(dot, "    b{} -> b{}{};", index, successor, label).unwrap();
+            }
+        }
+
+        writeln!(dot, "}}").unwrap();
+        dot
+    }
+}
+
+fn jump_target(chunk: &Chunk, opcode: OpCode, offset: usize) -> Option<usize> {
+    let code = chunk.code();
+    let jump = u16::from_le_bytes([code[offset + 1], code[offset + 2]]) as usize;
+    let next = offset + 3;
+
+    match opcode {
+        OpCode::Loop => next.checked_sub(jump),
+        _ => Some(next + jump),
+    }
+}
+
+fn escape(text: &str) -> String {
+    text.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compiler::Compiler;
+    use crate::heap::Heap;
+
+    fn cfg(source: &str, heap: &mut Heap) -> (Cfg, Chunk) {
+        let chunk = Compiler::new(None).compile(source, heap).unwrap();
+        (Cfg::new(&chunk).unwrap(), chunk)
+    }
+
+    fn edges(cfg: &Cfg) -> Vec<Vec<(usize, Edge)>> {
+        cfg.blocks()
+            .iter()
+            .map(|block| block.successors.clone())
+            .collect()
+    }
+
+    #[test]
+    fn splits_if_else() {
+        let (cfg, chunk) = cfg(
+            "var a = true; if (a) print 1; else print 2;",
+            &mut Heap::new(),
+        );
+
+        assert_eq!(
+            vec![
+                vec![(1, Edge::True), (2, Edge::False)],
+                vec![(3, Edge::Jump)],
+                vec![(3, Edge::Next)],
+                vec![],
+            ],
+            edges(&cfg)
+        );
+        assert_eq!(0, cfg.blocks()[0].start);
+        assert_eq!(chunk.len(), cfg.blocks()[3].end);
+    }
+
+    #[test]
+    fn splits_while_with_back_edge() {
+        let (cfg, _) = cfg("var i = 0; while (i < 2) i = i + 1;", &mut Heap::new());
+
+        assert_eq!(
+            vec![
+                vec![(1, Edge::Next)],
+                vec![(2, Edge::True), (3, Edge::False)],
+                vec![(1, Edge::Jump)],
+                vec![],
+            ],
+            edges(&cfg)
+        );
+    }
+
+    #[test]
+    fn renders_dot() {
+        let mut heap = Heap::new();
+        let (cfg, chunk) = cfg("var a = \"x\"; print a or \"y\";", &mut heap);
+        let dot = cfg.to_dot(&chunk, "code", DisassemblerOptions::default());
+
+        assert!(dot.starts_with("digraph \"code\" {\n"), "{}", dot);
+        assert!(
+            dot.contains("OP_CONSTANT      1    '\\\"x\\\"'\\l"),
+            "{}",
+            dot
+        );
+        // `or` skips the right operand through an unconditional jump when the left one is truthy.
+        let edges = "    b0 -> b1 [label=\"true\"];\n    b0 -> b2 [label=\"false\"];\n    b1 -> b3;\n    b2 -> b3;\n";
+        assert!(dot.contains(edges), "{}", dot);
+        assert!(dot.ends_with("}\n"), "{}", dot);
+    }
+}
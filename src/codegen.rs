@@ -0,0 +1,402 @@
+use crate::{
+    ast::{Expr, ExprKind, Identifier, Literal, Program, Stmt, StmtKind},
+    bytecode::{Chunk, LocalInfo, OpCode},
+    compiler::CompilerError,
+    heap::Heap,
+    object::Handle,
+    scanner::TokenKind,
+    string::String,
+    value::Value,
+};
+
+/// A local variable
+#[derive(Debug)]
+struct Local {
+    name: std::string::String,
+    /// Scope depth of the declaration, `None` until its initializer has been compiled.
+    depth: Option<usize>,
+    /// Index of the debug information of the local in the chunk, once initialized.
+    info: usize,
+}
+
+/// Generates bytecode for a parsed program, resolving variables along the way.
+///
+/// Every instruction gets the line of the source that completes it: an operator the line its
+/// right operand ends on, a statement the line of its semicolon.
+pub(crate) struct Codegen<'h> {
+    chunk: Chunk,
+    heap: &'h mut Heap,
+    locals: Vec<Local>,
+    scope_depth: usize,
+}
+
+impl<'h> Codegen<'h> {
+    pub fn new(heap: &'h mut Heap) -> Self {
+        Self {
+            chunk: Chunk::new(),
+            heap,
+            locals: Vec::new(),
+            scope_depth: 0,
+        }
+    }
+
+    pub fn generate(mut self, program: &Program) -> Result<Chunk, CompilerError> {
+        for statement in &program.statements {
+            self.statement(statement)?;
+        }
+        self.emit(OpCode::Return, program.span.end_line);
+
+        Ok(self.chunk)
+    }
+
+    fn statement(&mut self, stmt: &Stmt) -> Result<(), CompilerError> {
+        let line = stmt.span.end_line;
+
+        match &stmt.kind {
+            StmtKind::Print(expr) => {
+                self.expression(expr)?;
+                self.emit(OpCode::Print, line);
+            }
+            StmtKind::Expression(expr) => {
+                self.expression(expr)?;
+                self.emit(OpCode::Pop, line);
+            }
+            StmtKind::Var { name, initializer } => {
+                let global = self.declare_variable(name)?;
+
+                match initializer {
+                    Some(initializer) => self.expression(initializer)?,
+                    None => self.emit(OpCode::AddNil, name.span.line),
+                }
+
+                match global {
+                    Some(global) => self.emit_bytes(OpCode::DefineGlobal, global, line),
+                    None => self.make_initialized(),
+                }
+            }
+            StmtKind::Block(statements) => {
+                self.scope_depth += 1;
+                for statement in statements {
+                    self.statement(statement)?;
+                }
+                self.end_scope(line);
+            }
+            StmtKind::If {
+                condition,
+                then_branch,
+                else_branch,
+            } => {
+                self.expression(condition)?;
+                let then_jump = self.emit_jump(OpCode::JumpIfFalse, condition.span.end_line);
+                self.emit(OpCode::Pop, condition.span.end_line);
+                self.statement(then_branch)?;
+
+                let then_line = then_branch.span.end_line;
+                let else_jump = self.emit_jump(OpCode::Jump, then_line);
+                self.patch_jump(then_jump)?;
+                self.emit(OpCode::Pop, then_line);
+
+                if let Some(else_branch) = else_branch {
+                    self.statement(else_branch)?;
+                }
+                self.patch_jump(else_jump)?;
+            }
+            StmtKind::While { condition, body } => {
+                let loop_start = self.chunk.len();
+                self.expression(condition)?;
+                let exit_jump = self.emit_jump(OpCode::JumpIfFalse, condition.span.end_line);
+                self.emit(OpCode::Pop, condition.span.end_line);
+                self.statement(body)?;
+
+                let body_line = body.span.end_line;
+                self.emit_loop(loop_start, body_line)?;
+                self.patch_jump(exit_jump)?;
+                self.emit(OpCode::Pop, body_line);
+            }
+        }
+
+        Ok(())
+    }
+
+    fn expression(&mut self, expr: &Expr) -> Result<(), CompilerError> {
+        let line = expr.span.end_line;
+
+        match &expr.kind {
+            ExprKind::Literal(literal) => match literal {
+                Literal::Nil => self.emit(OpCode::AddNil, line),
+                Literal::Boolean(true) => self.emit(OpCode::AddTrue, line),
+                Literal::Boolean(false) => self.emit(OpCode::AddFalse, line),
+                Literal::Number(number) => self.emit_constant(Value::Number(*number), line)?,
+                Literal::String(string) => {
+                    let string = self.allocate_string(string, line)?;
+                    self.emit_constant(Value::String(string), line)?;
+                }
+            },
+            ExprKind::Variable(name) => {
+                match self.resolve_local(name)? {
+                    Some(slot) => self.emit_bytes(OpCode::GetLocal, slot, line),
+                    None => {
+                        let (global, cache) = self.global(name)?;
+                        self.emit_bytes(OpCode::GetGlobal, global, line);
+                        self.chunk.write(cache, line);
+                    }
+                };
+            }
+            ExprKind::Assign { name, value } => match self.resolve_local(name)? {
+                Some(slot) => {
+                    self.expression(value)?;
+                    self.emit_bytes(OpCode::SetLocal, slot, line);
+                }
+                None => {
+                    let (global, cache) = self.global(name)?;
+                    self.expression(value)?;
+                    self.emit_bytes(OpCode::SetGlobal, global, line);
+                    self.chunk.write(cache, line);
+                }
+            },
+            ExprKind::Unary { operator, operand } => {
+                self.expression(operand)?;
+
+                match operator.kind {
+                    TokenKind::Bang => self.emit(OpCode::Not, line),
+                    TokenKind::Minus => self.emit(OpCode::Negate, line),
+                    _ => unreachable!(),
+                }
+            }
+            ExprKind::Binary {
+                left,
+                operator,
+                right,
+            } => {
+                self.expression(left)?;
+                self.expression(right)?;
+
+                match operator.kind {
+                    TokenKind::BangEqual => self.emit_ops(&[OpCode::Equal, OpCode::Not], line),
+                    TokenKind::EqualEqual => self.emit(OpCode::Equal, line),
+                    TokenKind::Greater => self.emit(OpCode::Greater, line),
+                    TokenKind::GreaterEqual => self.emit(OpCode::GreaterEqual, line),
+                    TokenKind::Less => self.emit(OpCode::Less, line),
+                    TokenKind::LessEqual => self.emit(OpCode::LessEqual, line),
+                    TokenKind::Plus => self.emit(OpCode::Add, line),
+                    TokenKind::Minus => self.emit(OpCode::Substract, line),
+                    TokenKind::Star => self.emit(OpCode::Multiply, line),
+                    TokenKind::Slash => self.emit(OpCode::Divide, line),
+                    _ => unreachable!(),
+                }
+            }
+            ExprKind::Logical {
+                left,
+                operator,
+                right,
+            } => {
+                let operator_line = operator.span.line;
+                self.expression(left)?;
+
+                if operator.kind == TokenKind::And {
+                    let end_jump = self.emit_jump(OpCode::JumpIfFalse, operator_line);
+                    self.emit(OpCode::Pop, operator_line);
+                    self.expression(right)?;
+                    self.patch_jump(end_jump)?;
+                } else {
+                    let else_jump = self.emit_jump(OpCode::JumpIfFalse, operator_line);
+                    let end_jump = self.emit_jump(OpCode::Jump, operator_line);
+                    self.patch_jump(else_jump)?;
+                    self.emit(OpCode::Pop, operator_line);
+                    self.expression(right)?;
+                    self.patch_jump(end_jump)?;
+                }
+            }
+            ExprKind::Grouping(inner) => self.expression(inner)?,
+        }
+
+        Ok(())
+    }
+
+    /// Declares `name` in the current scope. Returns the constant naming it if it is a global.
+    fn declare_variable(&mut self, name: &Identifier) -> Result<Option<u8>, CompilerError> {
+        if self.scope_depth == 0 {
+            let string = self.allocate_string(&name.name, name.span.line)?;
+            return self
+                .make_constant(Value::String(string), name.span.line)
+                .map(Some);
+        }
+
+        // Slots are a single byte.
+        if self.locals.len() > u8::MAX as usize {
+            return Err(CompilerError::new(
+                "too many local variables in one chunk.",
+                name.span.line,
+            ));
+        }
+
+        for local in self.locals.iter().rev() {
+            if local.depth.is_some_and(|depth| depth < self.scope_depth) {
+                break;
+            }
+
+            if local.name == name.name {
+                return Err(CompilerError::new(
+                    "already a variable with this name in this scope.",
+                    name.span.line,
+                ));
+            }
+        }
+
+        self.locals.push(Local {
+            name: name.name.clone(),
+            depth: None,
+            info: 0,
+        });
+        Ok(None)
+    }
+
+    fn make_initialized(&mut self) {
+        let slot = self.locals.len() - 1;
+        let info = self.chunk.add_local(LocalInfo {
+            name: self.locals[slot].name.clone(),
+            slot: slot as u8,
+            start: self.chunk.len(),
+            end: self.chunk.len(),
+        });
+
+        self.locals[slot].depth = Some(self.scope_depth);
+        self.locals[slot].info = info;
+    }
+
+    fn end_scope(&mut self, line: usize) {
+        self.scope_depth -= 1;
+
+        while let Some(local) = self.locals.last() {
+            if local.depth.is_some_and(|depth| depth <= self.scope_depth) {
+                break;
+            }
+
+            let info = local.info;
+            self.chunk.locals_mut()[info].end = self.chunk.len();
+            self.emit(OpCode::Pop, line);
+            self.locals.pop();
+        }
+    }
+
+    fn resolve_local(&self, name: &Identifier) -> Result<Option<u8>, CompilerError> {
+        for (slot, local) in self.locals.iter().enumerate().rev() {
+            if local.name == name.name {
+                if local.depth.is_none() {
+                    return Err(CompilerError::new(
+                        "can't read local variable in its own initializer.",
+                        name.span.line,
+                    ));
+                }
+                return Ok(Some(slot as u8));
+            }
+        }
+
+        Ok(None)
+    }
+
+    /// The constant naming the global `name` and a fresh inline cache for accessing it.
+    ///
+    /// Accesses to the same global share its name constant, so a chunk runs out of caches, one
+    /// per access, before it runs out of constants.
+    fn global(&mut self, name: &Identifier) -> Result<(u8, u8), CompilerError> {
+        let line = name.span.line;
+        let global = match name_constant(self.chunk.constants(), &name.name) {
+            Some(global) => global,
+            None => {
+                let string = self.allocate_string(&name.name, line)?;
+                self.make_constant(Value::String(string), line)?
+            }
+        };
+
+        let cache = self.chunk.add_cache();
+        if cache > u8::MAX as usize {
+            return Err(CompilerError::new(
+                "too many global accesses in one chunk.",
+                line,
+            ));
+        }
+
+        Ok((global, cache as u8))
+    }
+
+    fn allocate_string(
+        &mut self,
+        chars: &str,
+        line: usize,
+    ) -> Result<Handle<String>, CompilerError> {
+        self.heap
+            .allocate_string(String::new(chars))
+            .map_err(|error| CompilerError::new(&error.to_string(), line))
+    }
+
+    fn emit(&mut self, opcode: OpCode, line: usize) {
+        self.chunk.write(opcode as u8, line);
+    }
+
+    fn emit_ops(&mut self, opcodes: &[OpCode], line: usize) {
+        for opcode in opcodes {
+            self.emit(*opcode, line);
+        }
+    }
+
+    fn emit_bytes(&mut self, opcode: OpCode, operand: u8, line: usize) {
+        self.emit(opcode, line);
+        self.chunk.write(operand, line);
+    }
+
+    fn emit_constant(&mut self, value: Value, line: usize) -> Result<(), CompilerError> {
+        let constant = self.make_constant(value, line)?;
+        self.emit_bytes(OpCode::AddConstant, constant, line);
+        Ok(())
+    }
+
+    fn make_constant(&mut self, value: Value, line: usize) -> Result<u8, CompilerError> {
+        let constant = self.chunk.add_constant(value);
+        u8::try_from(constant)
+            .map_err(|_| CompilerError::new("too many constants in one chunk.", line))
+    }
+
+    /// Emits a jump with a placeholder operand, returning the offset of the operand.
+    fn emit_jump(&mut self, opcode: OpCode, line: usize) -> usize {
+        self.emit(opcode, line);
+        self.chunk.write(0xff, line);
+        self.chunk.write(0xff, line);
+
+        self.chunk.len() - 2
+    }
+
+    /// Points the jump whose operand is at `offset` to the end of the code.
+    fn patch_jump(&mut self, offset: usize) -> Result<(), CompilerError> {
+        let jump = u16::try_from(self.chunk.len() - offset - 2).map_err(|_| {
+            CompilerError::new(
+                "too much code to jump over.",
+                self.chunk.line_for_offset(offset),
+            )
+        })?;
+        let jump_bytes = jump.to_le_bytes();
+
+        self.chunk.code_mut()[offset] = jump_bytes[0];
+        self.chunk.code_mut()[offset + 1] = jump_bytes[1];
+        Ok(())
+    }
+
+    fn emit_loop(&mut self, loop_start: usize, line: usize) -> Result<(), CompilerError> {
+        self.emit(OpCode::Loop, line);
+
+        let offset = u16::try_from(self.chunk.len() - loop_start + 2)
+            .map_err(|_| CompilerError::new("loop body too large.", line))?;
+        for byte in offset.to_le_bytes() {
+            self.chunk.write(byte, line);
+        }
+        Ok(())
+    }
+}
+
+/// The index of the string constant holding `name`, if the pool has one.
+pub(crate) fn name_constant(constants: &[Value], name: &str) -> Option<u8> {
+    constants
+        .iter()
+        .position(|constant| matches!(constant, Value::String(string) if string.as_str() == name))
+        .map(|index| index as u8)
+}
@@ -0,0 +1,776 @@
+//! Reconstructs Lox source from a `Chunk`.
+//!
+//! The compiler emits a fixed shape of code for every construct, so the decompiler reads those
+//! shapes back: expressions from the stack operations, `and`, `or`, `if` and `while` from the
+//! jumps around them, and the names and scopes of locals from the chunk's debug information. The
+//! source compiles back to the same code, but blocks without locals and redundant parentheses are
+//! not recovered.
+
+use std::fmt::Write;
+
+use crate::{
+    bytecode::{Chunk, LocalInfo, OpCode},
+    parser::Precedence,
+    value::Value,
+};
+
+/// Code the decompiler does not recognize, with the offset of the offending instruction.
+#[derive(Debug, PartialEq, Eq)]
+pub struct DecompileError {
+    msg: String,
+    offset: usize,
+}
+
+impl DecompileError {
+    fn new(msg: impl Into<String>, offset: usize) -> Self {
+        Self {
+            msg: msg.into(),
+            offset,
+        }
+    }
+
+    pub fn msg(&self) -> &str {
+        &self.msg
+    }
+
+    pub fn offset(&self) -> usize {
+        self.offset
+    }
+}
+
+/// Decompiles `chunk`, which must have been verified.
+pub(crate) fn decompile(chunk: &Chunk) -> Result<String, DecompileError> {
+    let statements = Decompiler::new(chunk).script()?;
+
+    let mut source = String::new();
+    for statement in &statements {
+        statement.render(0, &mut source);
+    }
+    Ok(source)
+}
+
+#[derive(Debug)]
+enum Expr {
+    /// A constant, already written as Lox source.
+    Literal(String),
+    Variable(String),
+    Assign(String, Box<Expr>),
+    Unary(&'static str, Box<Expr>),
+    Binary(Box<Expr>, &'static str, Box<Expr>),
+    Logical(Box<Expr>, &'static str, Box<Expr>),
+}
+
+impl Expr {
+    fn precedence(&self) -> Precedence {
+        match self {
+            Expr::Literal(text) if text.starts_with('-') => Precedence::Unary,
+            Expr::Literal(_) | Expr::Variable(_) => Precedence::Primary,
+            Expr::Assign(..) => Precedence::Assignment,
+            Expr::Unary(..) => Precedence::Unary,
+            Expr::Binary(_, "==" | "!=", _) => Precedence::Equality,
+            Expr::Binary(_, "<" | "<=" | ">" | ">=", _) => Precedence::Comparison,
+            Expr::Binary(_, "+" | "-", _) => Precedence::Term,
+            Expr::Binary(..) => Precedence::Factor,
+            Expr::Logical(_, "and", _) => Precedence::And,
+            Expr::Logical(..) => Precedence::Or,
+        }
+    }
+
+    /// Writes the expression, in parentheses if it binds looser than `min`.
+    fn render(&self, min: Precedence) -> String {
+        let precedence = self.precedence();
+        let text = match self {
+            Expr::Literal(text) | Expr::Variable(text) => text.clone(),
+            Expr::Assign(name, value) => format!("{} = {}", name, value.render(precedence)),
+            Expr::Unary(op, operand) => {
+                let operand = operand.render(precedence);
+                // `--x` would not scan as two negations.
+                let space = if *op == "-" && operand.starts_with('-') {
+                    " "
+                } else {
+                    ""
+                };
+                format!("{}{}{}", op, space, operand)
+            }
+            // Binary operators are left-associative, `and` and `or` are parsed right-associative.
+            Expr::Binary(left, op, right) => format!(
+                "{} {} {}",
+                left.render(precedence),
+                op,
+                right.render(precedence.higher())
+            ),
+            Expr::Logical(left, op, right) => format!(
+                "{} {} {}",
+                left.render(precedence.higher()),
+                op,
+                right.render(precedence)
+            ),
+        };
+
+        if precedence < min {
+            format!("({})", text)
+        } else {
+            text
+        }
+    }
+}
+
+/// `!expr`, folded into the comparison the compiler negated with `OP_NOT`.
+fn not(expr: Expr) -> Expr {
+    match expr {
+        Expr::Binary(left, "==", right) => Expr::Binary(left, "!=", right),
+        expr => Expr::Unary("!", Box::new(expr)),
+    }
+}
+
+#[derive(Debug)]
+enum Stmt {
+    Print(Expr),
+    Expression(Expr),
+    Var(String, Expr),
+    Block(Vec<Stmt>),
+    If(Expr, Vec<Stmt>, Vec<Stmt>),
+    While(Expr, Vec<Stmt>),
+}
+
+impl Stmt {
+    fn render(&self, indent: usize, out: &mut String) {
+        out.push_str(&"  ".repeat(indent));
+        self.render_inline(indent, out);
+    }
+
+    /// Writes the statement without indenting its first line.
+    fn render_inline(&self, indent: usize, out: &mut String) {
+        match self {
+            Stmt::Print(expr) => writeln!(out, "print {};", expr.render(Precedence::Assignment)),
+            Stmt::Expression(expr) => writeln!(out, "{};", expr.render(Precedence::Assignment)),
+            Stmt::Var(name, Expr::Literal(value)) if value == "nil" => {
+                writeln!(out, "var {};", name)
+            }
+            Stmt::Var(name, value) => {
+                writeln!(
+                    out,
+                    "var {} = {};",
+                    name,
+                    value.render(Precedence::Assignment)
+                )
+            }
+            Stmt::Block(statements) => {
+                render_block(statements, indent, out);
+                writeln!(out)
+            }
+            Stmt::If(condition, then, otherwise) => {
+                write!(out, "if ({}) ", condition.render(Precedence::Assignment)).unwrap();
+                render_block(then, indent, out);
+                match &otherwise[..] {
+                    [] => writeln!(out),
+                    [statement @ Stmt::If(..)] => {
+                        out.push_str(" else ");
+                        statement.render_inline(indent, out);
+                        Ok(())
+                    }
+                    _ => {
+                        out.push_str(" else ");
+                        render_block(otherwise, indent, out);
+                        writeln!(out)
+                    }
+                }
+            }
+            Stmt::While(condition, body) => {
+                write!(out, "while ({}) ", condition.render(Precedence::Assignment)).unwrap();
+                render_block(body, indent, out);
+                writeln!(out)
+            }
+        }
+        .unwrap();
+    }
+}
+
+fn render_block(statements: &[Stmt], indent: usize, out: &mut String) {
+    if statements.is_empty() {
+        out.push_str("{}");
+        return;
+    }
+
+    out.push_str("{\n");
+    for statement in statements {
+        statement.render(indent + 1, out);
+    }
+    out.push_str(&"  ".repeat(indent));
+    out.push('}');
+}
+
+/// The block being decompiled.
+struct Scope {
+    /// Number of locals on the stack.
+    depth: usize,
+    /// Offset of the first `OP_POP` closing the block, or of the end of its code.
+    end: usize,
+    /// Locals declared in the block, popped at `end`.
+    locals: usize,
+    /// Whether a local popped right before `end` is declared in this block rather than in a
+    /// nested one. The two compile the same, but the script itself has no braces to put it in.
+    shared: bool,
+}
+
+struct Decompiler<'c> {
+    chunk: &'c Chunk,
+    /// The opcode starting at each offset, `None` for operand bytes.
+    opcodes: Vec<Option<OpCode>>,
+    /// Offsets `OP_LOOP` instructions jump back to, where a `while` starts.
+    loops: Vec<usize>,
+}
+
+impl<'c> Decompiler<'c> {
+    fn new(chunk: &'c Chunk) -> Self {
+        let code = chunk.code();
+        let mut opcodes = vec![None; code.len()];
+
+        let mut offset = 0;
+        while offset < code.len() {
+            let opcode = OpCode::from_repr(code[offset]).expect("verified code decodes");
+            opcodes[offset] = Some(opcode);
+            offset += 1 + opcode.operand_width();
+        }
+
+        let mut decompiler = Self {
+            chunk,
+            opcodes,
+            loops: Vec::new(),
+        };
+        for offset in 0..code.len() {
+            if decompiler.opcode(offset) == Some(OpCode::Loop) {
+                decompiler.loops.push(decompiler.target(offset));
+            }
+        }
+        decompiler
+    }
+
+    fn script(&self) -> Result<Vec<Stmt>, DecompileError> {
+        let end = self.chunk.len() - 1;
+        if self.opcode(end) != Some(OpCode::Return) {
+            return Err(DecompileError::new("expected OP_RETURN at the end.", end));
+        }
+
+        let mut scope = Scope {
+            depth: 0,
+            end,
+            locals: 0,
+            shared: false,
+        };
+        let (statements, _) = self.block(0, &mut scope)?;
+        Ok(statements)
+    }
+
+    /// Decompiles the statements of `scope` from `pc`, then checks the pops closing it. Returns
+    /// the statements and the offset after the block.
+    fn block(
+        &self,
+        mut pc: usize,
+        scope: &mut Scope,
+    ) -> Result<(Vec<Stmt>, usize), DecompileError> {
+        let mut statements = Vec::new();
+        while pc < scope.end {
+            pc = self.statement(pc, scope, &mut statements)?;
+        }
+        if pc != scope.end {
+            return Err(DecompileError::new(
+                "statement runs past the end of its block.",
+                pc,
+            ));
+        }
+
+        for _ in 0..scope.locals {
+            self.expect(pc, OpCode::Pop)?;
+            pc += 1;
+        }
+        Ok((statements, pc))
+    }
+
+    /// Decompiles the body of an `if` or `while`, which must span `start..end` exactly.
+    fn body(&self, start: usize, end: usize, depth: usize) -> Result<Vec<Stmt>, DecompileError> {
+        let mut scope = Scope {
+            depth,
+            end,
+            locals: 0,
+            shared: true,
+        };
+        let (statements, pc) = self.block(start, &mut scope)?;
+        if pc != end {
+            return Err(DecompileError::new(
+                "block runs past the end of its body.",
+                pc,
+            ));
+        }
+        Ok(statements)
+    }
+
+    /// Decompiles the statement at `pc` into `out`, returning the offset after it.
+    fn statement(
+        &self,
+        pc: usize,
+        scope: &mut Scope,
+        out: &mut Vec<Stmt>,
+    ) -> Result<usize, DecompileError> {
+        if self.loops.contains(&pc) {
+            return self.while_statement(pc, scope, out);
+        }
+
+        let (expr, pc) = self.expression(pc, scope.end, scope.depth)?;
+        if let Some(local) = self.declaration(pc, scope.depth) {
+            return self.declare(local, expr, scope, out);
+        }
+
+        match self.opcode(pc) {
+            Some(OpCode::Print) => {
+                out.push(Stmt::Print(expr));
+                Ok(pc + 1)
+            }
+            Some(OpCode::Pop) => {
+                out.push(Stmt::Expression(expr));
+                Ok(pc + 1)
+            }
+            Some(OpCode::DefineGlobal) => {
+                out.push(Stmt::Var(self.name(pc)?, expr));
+                Ok(pc + 2)
+            }
+            Some(OpCode::JumpIfFalse | OpCode::JumpIfTrue) => {
+                self.if_statement(expr, pc, scope, out)
+            }
+            _ => Err(self.unexpected(pc)),
+        }
+    }
+
+    /// `cond; JUMP_IF_FALSE else; POP; then; JUMP end; else: POP; otherwise; end:`
+    fn if_statement(
+        &self,
+        condition: Expr,
+        jump: usize,
+        scope: &Scope,
+        out: &mut Vec<Stmt>,
+    ) -> Result<usize, DecompileError> {
+        let condition = self.condition(condition, jump);
+        let otherwise = self.target(jump);
+        self.expect(jump + 3, OpCode::Pop)?;
+        self.expect(otherwise - 3, OpCode::Jump)?;
+        self.expect(otherwise, OpCode::Pop)?;
+
+        let end = self.target(otherwise - 3);
+        let then = self.body(jump + 4, otherwise - 3, scope.depth)?;
+        let otherwise = self.body(otherwise + 1, end, scope.depth)?;
+
+        out.push(Stmt::If(condition, then, otherwise));
+        Ok(end)
+    }
+
+    /// `start: cond; JUMP_IF_FALSE exit; POP; body; LOOP start; exit: POP`
+    fn while_statement(
+        &self,
+        start: usize,
+        scope: &Scope,
+        out: &mut Vec<Stmt>,
+    ) -> Result<usize, DecompileError> {
+        let (condition, jump) = self.expression(start, scope.end, scope.depth)?;
+        if !matches!(
+            self.opcode(jump),
+            Some(OpCode::JumpIfFalse | OpCode::JumpIfTrue)
+        ) {
+            return Err(DecompileError::new(
+                "expected the condition of a loop.",
+                jump,
+            ));
+        }
+
+        let condition = self.condition(condition, jump);
+        let exit = self.target(jump);
+        self.expect(jump + 3, OpCode::Pop)?;
+        self.expect(exit - 3, OpCode::Loop)?;
+        self.expect(exit, OpCode::Pop)?;
+        if self.target(exit - 3) != start {
+            return Err(DecompileError::new(
+                "loop does not jump back to its condition.",
+                exit - 3,
+            ));
+        }
+
+        let body = self.body(jump + 4, exit - 3, scope.depth)?;
+        out.push(Stmt::While(condition, body));
+        Ok(exit + 1)
+    }
+
+    /// Declares `local` with its initializer. It joins the enclosing block if its pop comes right
+    /// before the ones closing that block, otherwise it opens a block of its own.
+    fn declare(
+        &self,
+        local: &LocalInfo,
+        value: Expr,
+        scope: &mut Scope,
+        out: &mut Vec<Stmt>,
+    ) -> Result<usize, DecompileError> {
+        let var = Stmt::Var(local.name.clone(), value);
+
+        if scope.shared && local.end + 1 == scope.end {
+            out.push(var);
+            scope.depth += 1;
+            scope.end = local.end;
+            scope.locals += 1;
+            return Ok(local.start);
+        }
+
+        let mut block = Scope {
+            depth: scope.depth + 1,
+            end: local.end,
+            locals: 1,
+            shared: true,
+        };
+        let (mut statements, pc) = self.block(local.start, &mut block)?;
+        statements.insert(0, var);
+        out.push(Stmt::Block(statements));
+        Ok(pc)
+    }
+
+    /// Decompiles the expression starting at `pc`, stopping before `end` or the first
+    /// instruction that is not part of it. Returns the expression and the offset it stopped at.
+    fn expression(
+        &self,
+        mut pc: usize,
+        end: usize,
+        depth: usize,
+    ) -> Result<(Expr, usize), DecompileError> {
+        let mut stack = Vec::new();
+        let pop = |stack: &mut Vec<Expr>, pc: usize| {
+            stack.pop().map(Box::new).ok_or_else(|| self.unexpected(pc))
+        };
+
+        while pc < end {
+            // A value left on the stack where a local's scope starts is its initializer.
+            if stack.len() == 1 && self.declaration(pc, depth).is_some() {
+                break;
+            }
+
+            let opcode = self.opcode(pc).ok_or_else(|| self.unexpected(pc))?;
+            match opcode {
+                OpCode::AddConstant => stack.push(self.literal(pc)?),
+                OpCode::AddNil => stack.push(Expr::Literal("nil".into())),
+                OpCode::AddTrue => stack.push(Expr::Literal("true".into())),
+                OpCode::AddFalse => stack.push(Expr::Literal("false".into())),
+                OpCode::GetGlobal => stack.push(Expr::Variable(self.name(pc)?)),
+                OpCode::GetLocal => stack.push(Expr::Variable(self.local(pc)?)),
+                OpCode::SetGlobal => {
+                    let value = pop(&mut stack, pc)?;
+                    stack.push(Expr::Assign(self.name(pc)?, value));
+                }
+                OpCode::SetLocal => {
+                    let value = pop(&mut stack, pc)?;
+                    stack.push(Expr::Assign(self.local(pc)?, value));
+                }
+                OpCode::Negate => {
+                    let operand = pop(&mut stack, pc)?;
+                    stack.push(Expr::Unary("-", operand));
+                }
+                OpCode::Not => {
+                    let operand = pop(&mut stack, pc)?;
+                    stack.push(not(*operand));
+                }
+                OpCode::Equal
+                | OpCode::Greater
+                | OpCode::GreaterEqual
+                | OpCode::Less
+                | OpCode::LessEqual
+                | OpCode::Add
+                | OpCode::Substract
+                | OpCode::Multiply
+                | OpCode::Divide => {
+                    let right = pop(&mut stack, pc)?;
+                    let left = pop(&mut stack, pc)?;
+                    stack.push(Expr::Binary(left, binary_operator(opcode), right));
+                }
+                OpCode::JumpIfFalse | OpCode::JumpIfTrue if !stack.is_empty() => {
+                    match self.logical(pc, depth)? {
+                        Some((op, right, next)) => {
+                            let left = pop(&mut stack, pc)?;
+                            let left = self.condition(*left, pc);
+                            stack.push(Expr::Logical(Box::new(left), op, Box::new(right)));
+                            pc = next;
+                            continue;
+                        }
+                        None => break,
+                    }
+                }
+                _ => break,
+            }
+
+            pc += 1 + opcode.operand_width();
+        }
+
+        match stack.len() {
+            1 => Ok((stack.pop().unwrap(), pc)),
+            0 => Err(DecompileError::new("expected an expression.", pc)),
+            _ => Err(DecompileError::new(
+                "more than one value left by an expression.",
+                pc,
+            )),
+        }
+    }
+
+    /// Decompiles the right operand of the `and` or `or` whose conditional jump is at `jump`.
+    /// Returns `None` if the jump belongs to an `if` or `while` instead.
+    ///
+    /// * `and`: `left; JUMP_IF_FALSE end; POP; right; end:`
+    /// * `or`: `left; JUMP_IF_FALSE else; JUMP end; else: POP; right; end:`
+    fn logical(
+        &self,
+        jump: usize,
+        depth: usize,
+    ) -> Result<Option<(&'static str, Expr, usize)>, DecompileError> {
+        let target = self.target(jump);
+
+        let (op, start, end) = if self.opcode(jump) == Some(OpCode::JumpIfFalse)
+            && self.opcode(jump + 3) == Some(OpCode::Jump)
+            && target == jump + 6
+            && self.opcode(target) == Some(OpCode::Pop)
+        {
+            ("or", jump + 7, self.target(jump + 3))
+        } else if self.opcode(jump + 3) == Some(OpCode::Pop)
+            && !matches!(self.opcode(target - 3), Some(OpCode::Jump | OpCode::Loop))
+        {
+            ("and", jump + 4, target)
+        } else {
+            return Ok(None);
+        };
+
+        let (right, next) = self.expression(start, end, depth)?;
+        if next != end {
+            return Err(self.unexpected(next));
+        }
+        Ok(Some((op, right, end)))
+    }
+
+    /// The condition tested by the conditional jump at `jump`.
+    fn condition(&self, condition: Expr, jump: usize) -> Expr {
+        match self.opcode(jump) {
+            Some(OpCode::JumpIfTrue) => not(condition),
+            _ => condition,
+        }
+    }
+
+    /// The local whose scope starts at `pc` in slot `depth`, if any.
+    fn declaration(&self, pc: usize, depth: usize) -> Option<&'c LocalInfo> {
+        self.chunk.locals().iter().find(|local| {
+            local.start == pc && local.start < local.end && local.slot as usize == depth
+        })
+    }
+
+    /// The name of the local accessed by the instruction at `pc`.
+    fn local(&self, pc: usize) -> Result<String, DecompileError> {
+        let slot = self.chunk.code()[pc + 1];
+        self.chunk
+            .locals()
+            .iter()
+            .find(|local| local.slot == slot && local.start <= pc && pc < local.end)
+            .map(|local| local.name.clone())
+            .ok_or_else(|| {
+                DecompileError::new(format!("no debug information for local {}.", slot), pc)
+            })
+    }
+
+    /// The global named by the constant operand of the instruction at `pc`.
+    fn name(&self, pc: usize) -> Result<String, DecompileError> {
+        match self.constant(pc) {
+            Some(Value::String(name)) => Ok(name.to_string()),
+            _ => Err(DecompileError::new("global name is not a string.", pc)),
+        }
+    }
+
+    fn literal(&self, pc: usize) -> Result<Expr, DecompileError> {
+        let text = match self.constant(pc) {
+            Some(Value::Number(number)) => number_literal(*number),
+            Some(Value::String(string)) => {
+                let string = string.to_string();
+                if string.contains('"') {
+                    return Err(DecompileError::new(
+                        "string constant cannot be written as a Lox string.",
+                        pc,
+                    ));
+                }
+                format!("\"{}\"", string)
+            }
+            Some(value @ (Value::Boolean(_) | Value::Nil)) => value.to_string(),
+            _ => return Err(DecompileError::new("constant has no Lox literal.", pc)),
+        };
+        Ok(Expr::Literal(text))
+    }
+
+    fn constant(&self, pc: usize) -> Option<&Value> {
+        let index = self.chunk.code()[pc + 1] as usize;
+        self.chunk.constants().get(index)
+    }
+
+    fn opcode(&self, offset: usize) -> Option<OpCode> {
+        self.opcodes.get(offset).copied().flatten()
+    }
+
+    /// Where the jump at `offset` goes.
+    fn target(&self, offset: usize) -> usize {
+        let code = self.chunk.code();
+        let jump = u16::from_le_bytes([code[offset + 1], code[offset + 2]]) as usize;
+        match self.opcode(offset) {
+            Some(OpCode::Loop) => offset + 3 - jump,
+            _ => offset + 3 + jump,
+        }
+    }
+
+    fn expect(&self, offset: usize, opcode: OpCode) -> Result<(), DecompileError> {
+        if self.opcode(offset) == Some(opcode) {
+            Ok(())
+        } else {
+            Err(DecompileError::new(format!("expected {}.", opcode), offset))
+        }
+    }
+
+    fn unexpected(&self, offset: usize) -> DecompileError {
+        match self.opcode(offset) {
+            Some(opcode) => DecompileError::new(format!("unexpected {}.", opcode), offset),
+            None => DecompileError::new("unexpected end of code.", offset),
+        }
+    }
+}
+
+fn binary_operator(opcode: OpCode) -> &'static str {
+    match opcode {
+        OpCode::Equal => "==",
+        OpCode::Greater => ">",
+        OpCode::GreaterEqual => ">=",
+        OpCode::Less => "<",
+        OpCode::LessEqual => "<=",
+        OpCode::Add => "+",
+        OpCode::Substract => "-",
+        OpCode::Multiply => "*",
+        _ => "/",
+    }
+}
+
+/// Writes `number` so that it compiles back to the same constant.
+fn number_literal(number: f64) -> String {
+    if number.is_nan() {
+        "(0 / 0)".into()
+    } else if number.is_infinite() {
+        if number > 0.0 { "(1 / 0)" } else { "(-1 / 0)" }.into()
+    } else {
+        number.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compiler::{Compiler, CompilerOptions};
+    use crate::heap::Heap;
+    use crate::verifier;
+
+    fn compile(source: &str, opt_level: u8, heap: &mut Heap) -> Chunk {
+        let options = CompilerOptions {
+            opt_level,
+            ..Default::default()
+        };
+        Compiler::new(Some(&options)).compile(source, heap).unwrap()
+    }
+
+    /// Decompiles `source` and checks the result compiles back to the same code.
+    fn round_trip(source: &str, opt_level: u8) -> String {
+        let mut heap = Heap::new();
+        let chunk = compile(source, opt_level, &mut heap);
+        verifier::verify(&chunk).unwrap();
+        let decompiled = decompile(&chunk).unwrap();
+
+        let recompiled = compile(&decompiled, opt_level, &mut heap);
+        assert_eq!(
+            &chunk.code()[..],
+            &recompiled.code()[..],
+            "{}\n--\n{}",
+            source,
+            decompiled
+        );
+        let constants = |chunk: &Chunk| {
+            chunk
+                .constants()
+                .iter()
+                .map(|value| value.to_string())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(constants(&chunk), constants(&recompiled), "{}", decompiled);
+
+        decompiled
+    }
+
+    #[test]
+    fn decompiles_expressions() {
+        let source = round_trip(
+            "var a = 1; var b; print -a * (2 + a) - a / 4; \
+             print a != b and !(a >= 2) or a <= 3; \
+             a = b = \"s\"; print (a = 1) + 2; print a and (b or a);",
+            0,
+        );
+
+        assert_eq!(
+            "var a = 1;\nvar b;\nprint -a * (2 + a) - a / 4;\n\
+             print a != b and !(a >= 2) or a <= 3;\n\
+             a = b = \"s\";\nprint (a = 1) + 2;\nprint a and (b or a);\n",
+            source
+        );
+    }
+
+    #[test]
+    fn decompiles_control_flow() {
+        let source = round_trip(
+            "var i = 0; while (i < 3) { if (i == 1) print \"one\"; else if (i == 2) print 2; \
+             else { print i; } i = i + 1; } if (i) print i;",
+            0,
+        );
+
+        assert_eq!(
+            "var i = 0;\nwhile (i < 3) {\n  if (i == 1) {\n    print \"one\";\n  } \
+             else if (i == 2) {\n    print 2;\n  } else {\n    print i;\n  }\n  \
+             i = i + 1;\n}\nif (i) {\n  print i;\n}\n",
+            source
+        );
+    }
+
+    #[test]
+    fn recovers_local_names_and_scopes() {
+        let source = round_trip(
+            "{ var a = 1; var b = a; { var c = b; print c; } print a; } \
+             while (true) { var k = 2; print k; }",
+            0,
+        );
+
+        assert_eq!(
+            "{\n  var a = 1;\n  var b = a;\n  {\n    var c = b;\n    print c;\n  }\n  \
+             print a;\n}\nwhile (true) {\n  var k = 2;\n  print k;\n}\n",
+            source
+        );
+    }
+
+    #[test]
+    fn decompiles_optimized_code() {
+        let source = round_trip(
+            "var a = 0; while (!(a == 3)) { if (!a) print a; a = a + 1; } \
+             print !a and a;",
+            2,
+        );
+
+        assert!(source.contains("while (a != 3) {"), "{}", source);
+        assert!(source.contains("if (!a) {"), "{}", source);
+    }
+
+    #[test]
+    fn fails_without_debug_information() {
+        let mut heap = Heap::new();
+        let mut chunk = compile("{ var a = 1; print a; }", 0, &mut heap);
+        chunk.locals_mut()[0].start = chunk.len();
+
+        let error = decompile(&chunk).unwrap_err();
+        assert_eq!("no debug information for local 0.", error.msg());
+        assert_eq!(2, error.offset());
+    }
+}
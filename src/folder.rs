@@ -0,0 +1,194 @@
+//! Constant folding over the syntax tree.
+//!
+//! Operators whose operands are all literals are replaced by the literal they evaluate to before
+//! either code generator runs. Strings are folded as Rust strings, so only the final value of
+//! `"a" + "b" + "c"` is ever allocated in the heap.
+
+use crate::{
+    ast::{Expr, ExprKind, Literal, Program, Stmt, StmtKind},
+    scanner::TokenKind,
+};
+
+/// Folds the constant expressions of `program` in place.
+pub(crate) fn fold(program: &mut Program) {
+    for stmt in &mut program.statements {
+        statement(stmt);
+    }
+}
+
+fn statement(stmt: &mut Stmt) {
+    match &mut stmt.kind {
+        StmtKind::Print(expr) | StmtKind::Expression(expr) => expression(expr),
+        StmtKind::Var { initializer, .. } => {
+            if let Some(initializer) = initializer {
+                expression(initializer);
+            }
+        }
+        StmtKind::Block(statements) => {
+            for stmt in statements {
+                statement(stmt);
+            }
+        }
+        StmtKind::If {
+            condition,
+            then_branch,
+            else_branch,
+        } => {
+            expression(condition);
+            statement(then_branch);
+            if let Some(else_branch) = else_branch {
+                statement(else_branch);
+            }
+        }
+        StmtKind::While { condition, body } => {
+            expression(condition);
+            statement(body);
+        }
+    }
+}
+
+fn expression(expr: &mut Expr) {
+    let folded = match &mut expr.kind {
+        ExprKind::Literal(_) | ExprKind::Variable(_) => None,
+        ExprKind::Assign { value, .. } => {
+            expression(value);
+            None
+        }
+        ExprKind::Unary { operator, operand } => {
+            expression(operand);
+            literal(operand).and_then(|operand| fold_unary(operator.kind, operand))
+        }
+        ExprKind::Binary {
+            left,
+            operator,
+            right,
+        } => {
+            expression(left);
+            expression(right);
+            match (literal(left), literal(right)) {
+                (Some(left), Some(right)) => fold_binary(operator.kind, left, right),
+                _ => None,
+            }
+        }
+        // Short-circuiting is left to the generators, which jump over the right operand.
+        ExprKind::Logical { left, right, .. } => {
+            expression(left);
+            expression(right);
+            None
+        }
+        ExprKind::Grouping(inner) => {
+            expression(inner);
+            None
+        }
+    };
+
+    if let Some(folded) = folded {
+        expr.kind = ExprKind::Literal(folded);
+    }
+}
+
+/// The literal `expr` evaluates to, looking through parentheses.
+fn literal(expr: &Expr) -> Option<&Literal> {
+    match &expr.kind {
+        ExprKind::Literal(literal) => Some(literal),
+        ExprKind::Grouping(inner) => literal(inner),
+        _ => None,
+    }
+}
+
+/// Evaluates a binary operator over two literals.
+///
+/// Returns `None` when the operands would raise a type error at runtime, so the error still
+/// happens when (and if) the expression is executed.
+fn fold_binary(operator: TokenKind, left: &Literal, right: &Literal) -> Option<Literal> {
+    let folded = match (operator, left, right) {
+        (TokenKind::EqualEqual, left, right) => Literal::Boolean(left == right),
+        (TokenKind::BangEqual, left, right) => Literal::Boolean(left != right),
+        (operator, Literal::Number(left), Literal::Number(right)) => match operator {
+            TokenKind::Plus => Literal::Number(left + right),
+            TokenKind::Minus => Literal::Number(left - right),
+            TokenKind::Star => Literal::Number(left * right),
+            TokenKind::Slash => Literal::Number(left / right),
+            TokenKind::Greater => Literal::Boolean(left > right),
+            TokenKind::GreaterEqual => Literal::Boolean(left >= right),
+            TokenKind::Less => Literal::Boolean(left < right),
+            TokenKind::LessEqual => Literal::Boolean(left <= right),
+            _ => return None,
+        },
+        // Strings order by their bytes, as they do in the VM.
+        (operator, Literal::String(left), Literal::String(right)) => match operator {
+            TokenKind::Plus => Literal::String(format!("{}{}", left, right)),
+            TokenKind::Greater => Literal::Boolean(left > right),
+            TokenKind::GreaterEqual => Literal::Boolean(left >= right),
+            TokenKind::Less => Literal::Boolean(left < right),
+            TokenKind::LessEqual => Literal::Boolean(left <= right),
+            _ => return None,
+        },
+        _ => return None,
+    };
+
+    Some(folded)
+}
+
+/// Evaluates a unary operator over a literal.
+fn fold_unary(operator: TokenKind, operand: &Literal) -> Option<Literal> {
+    match (operator, operand) {
+        (TokenKind::Bang, operand) => Some(Literal::Boolean(matches!(
+            operand,
+            Literal::Nil | Literal::Boolean(false)
+        ))),
+        (TokenKind::Minus, Literal::Number(number)) => Some(Literal::Number(-number)),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::Parser;
+
+    fn folded(source: &str) -> ExprKind {
+        let mut program = Parser::new(source).parse().unwrap();
+        fold(&mut program);
+
+        match program.statements.remove(0).kind {
+            StmtKind::Expression(expr) => expr.kind,
+            kind => panic!("expected an expression statement, got {:?}", kind),
+        }
+    }
+
+    #[test]
+    fn folds_nested_operators() {
+        assert_eq!(
+            ExprKind::Literal(Literal::Number(-5.0)),
+            folded("1 + 2 * -(3);")
+        );
+        assert_eq!(
+            ExprKind::Literal(Literal::Boolean(true)),
+            folded("!(1 >= 2) == true;")
+        );
+    }
+
+    #[test]
+    fn folds_strings_without_a_heap() {
+        assert_eq!(
+            ExprKind::Literal(Literal::String("abc".to_string())),
+            folded("\"a\" + \"b\" + \"c\";")
+        );
+        assert_eq!(
+            ExprKind::Literal(Literal::Boolean(true)),
+            folded("\"ab\" < \"b\";")
+        );
+    }
+
+    #[test]
+    fn keeps_type_errors_and_variables() {
+        assert!(matches!(folded("1 + \"a\";"), ExprKind::Binary { .. }));
+        assert!(matches!(folded("-nil;"), ExprKind::Unary { .. }));
+        assert!(matches!(folded("a + (1 + 2);"), ExprKind::Binary { .. }));
+        assert!(matches!(
+            folded("(nil and 1) + 2;"),
+            ExprKind::Binary { .. }
+        ));
+    }
+}
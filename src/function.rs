@@ -1,10 +1,9 @@
 use std::fmt::Display;
 
-use crate::{bytecode::Chunk, object::Handle, string::String};
+use crate::{bytecode::Chunk, string::String};
 
 #[derive(Clone, Debug)]
 pub struct Function {
-    arity: usize,
     chunk: Option<Chunk>,
     name: Option<String>,
 }
@@ -21,11 +20,7 @@ impl Display for Function {
 
 impl Function {
     pub(crate) fn new(chunk: Option<Chunk>, name: Option<String>) -> Self {
-        Self {
-            arity: 0,
-            chunk,
-            name,
-        }
+        Self { chunk, name }
     }
 
     pub(crate) fn name(&self) -> &str {
@@ -35,6 +30,11 @@ impl Function {
         }
     }
 
+    /// The name the function was declared with, `None` for the top-level script.
+    pub(crate) fn declared_name(&self) -> Option<&str> {
+        self.name.as_ref().map(|name| name.as_str())
+    }
+
     pub(crate) fn chunk(&self) -> Option<&Chunk> {
         self.chunk.as_ref()
     }
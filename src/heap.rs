@@ -1,5 +1,6 @@
 use std::any::Any;
-use std::{collections::LinkedList, sync::Mutex};
+use std::fmt::{self, Display};
+use std::mem::size_of;
 
 use rlox_common::HashMap;
 
@@ -7,41 +8,102 @@ use crate::function::Function;
 use crate::object::Handle;
 use crate::string::String;
 
-pub(crate) struct Heap {
+/// Error returned when an allocation would take a heap past its limit.
+#[derive(Debug, PartialEq, Eq)]
+pub struct OutOfMemory;
+
+impl Display for OutOfMemory {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "out of memory.")
+    }
+}
+
+/// The objects owned by one `Vm`, and the table of its interned strings.
+///
+/// Hosts never name a heap: [`IntoLox`](crate::IntoLox) allocates in it on their behalf.
+pub struct Heap {
     objects: Vec<Box<dyn Any>>,
     strings: HashMap<String, Handle<String>>,
+    /// Approximate size of the objects allocated so far: their own size plus, for strings, the
+    /// bytes of their characters. Nothing is freed before the heap drops, so it never shrinks.
+    bytes_allocated: usize,
+    max_bytes: Option<usize>,
 }
 
 impl Heap {
+    #[cfg(test)]
     pub(crate) fn new() -> Self {
+        Self::with_limit(None)
+    }
+
+    /// Creates a heap that refuses to grow past `max_bytes`, or without limit when `None`.
+    pub(crate) fn with_limit(max_bytes: Option<usize>) -> Self {
         Self {
-            /// List of object handles for "GC" (Not a thing yet)
+            // List of object handles for "GC" (Not a thing yet)
             objects: Vec::new(),
-            /// Interned strings
+            // Interned strings
             strings: HashMap::new(),
+            bytes_allocated: 0,
+            max_bytes,
         }
     }
 
-    pub fn allocate<T: 'static>(&mut self, value: T) -> Handle<T> {
-        let mut object_ptr = Handle::new(value);
-        self.objects.push(Box::new(object_ptr.clone()));
-        object_ptr
+    #[cfg(test)]
+    pub(crate) fn bytes_allocated(&self) -> usize {
+        self.bytes_allocated
     }
 
-    pub fn allocate_string(&mut self, string: String) -> Handle<String> {
-        let new_string_handle = self.allocate(string.clone());
+    fn check(&self, bytes: usize) -> Result<(), OutOfMemory> {
+        let total = self.bytes_allocated.saturating_add(bytes);
+        match self.max_bytes {
+            Some(max_bytes) if total > max_bytes => Err(OutOfMemory),
+            _ => Ok(()),
+        }
+    }
 
-        match self.strings.get(&string) {
-            Some(string_handle) => *string_handle,
-            None => {
-                self.strings.insert(string, new_string_handle);
-                new_string_handle
-            }
+    /// Accounts for `bytes` more, failing if that would exceed the limit.
+    fn reserve(&mut self, bytes: usize) -> Result<(), OutOfMemory> {
+        self.check(bytes)?;
+        self.bytes_allocated += bytes;
+        Ok(())
+    }
+
+    pub(crate) fn allocate<T: 'static>(&mut self, value: T) -> Result<Handle<T>, OutOfMemory> {
+        self.reserve(size_of::<T>())?;
+        Ok(self.track(value))
+    }
+
+    pub(crate) fn allocate_string(
+        &mut self,
+        string: String,
+    ) -> Result<Handle<String>, OutOfMemory> {
+        if let Some(string_handle) = self.strings.get(&string) {
+            return Ok(*string_handle);
         }
+
+        self.reserve(size_of::<String>() + string.len())?;
+        let string_handle = self.track(string.clone());
+        self.strings.insert(string, string_handle);
+        Ok(string_handle)
+    }
+
+    /// Allocates `left + right`, checking the limit before building the new string.
+    pub(crate) fn concatenate(
+        &mut self,
+        left: &String,
+        right: &String,
+    ) -> Result<Handle<String>, OutOfMemory> {
+        self.check(size_of::<String>() + left.len() + right.len())?;
+        self.allocate_string(left + right)
+    }
+
+    fn track<T: 'static>(&mut self, value: T) -> Handle<T> {
+        let object_ptr = Handle::new(value);
+        self.objects.push(Box::new(object_ptr.clone()));
+        object_ptr
     }
 }
 
-// The WAT?
 impl Drop for Heap {
     fn drop(&mut self) {
         unsafe {
@@ -73,8 +135,8 @@ mod tests {
     #[test]
     fn store_multiple_types() {
         let mut heap = Heap::new();
-        let mut s = heap.allocate(String::new("Yo!"));
-        let mut f = heap.allocate(Function::new(None, None));
+        let s = heap.allocate(String::new("Yo!")).unwrap();
+        let f = heap.allocate(Function::new(None, None)).unwrap();
         let g = f;
         let h = g;
 
@@ -83,4 +145,29 @@ mod tests {
         println!("{}", *h);
         println!("{}", *g);
     }
+
+    #[test]
+    fn interned_strings_are_counted_once() {
+        let mut heap = Heap::new();
+        let a = heap.allocate_string(String::new("abc")).unwrap();
+        let bytes = heap.bytes_allocated();
+        let b = heap.allocate_string(String::new("abc")).unwrap();
+
+        assert_eq!(size_of::<String>() + 3, bytes);
+        assert_eq!(bytes, heap.bytes_allocated());
+        assert_eq!(a, b);
+    }
+
+    #[test]
+    fn limit_is_enforced() {
+        let limit = 2 * size_of::<String>() + 8;
+        let mut heap = Heap::with_limit(Some(limit));
+        let left = heap.allocate_string(String::new("abcd")).unwrap();
+
+        assert_eq!(Err(OutOfMemory), heap.concatenate(&left, &left).map(|_| ()));
+        assert_eq!(size_of::<String>() + 4, heap.bytes_allocated());
+        assert!(heap.allocate_string(String::new("efgh")).is_ok());
+        assert_eq!(limit, heap.bytes_allocated());
+        assert_eq!(Err(OutOfMemory), heap.allocate(0.0).map(|_| ()));
+    }
 }
@@ -0,0 +1,54 @@
+//! A bytecode interpreter for Lox, following "Crafting Interpreters".
+//!
+//! The [`Vm`] compiles and runs Lox source, and lets the host read, write and call its global
+//! variables. Rust values cross into Lox through [`IntoLox`] and come back through [`FromLox`].
+//! Tools that rewrite source can split it into tokens with [`scan_lossless`].
+//!
+//! ```
+//! use rlox::Vm;
+//!
+//! let mut vm = Vm::new(None);
+//! vm.set_global("width", 3.0).unwrap();
+//! vm.interpret("var area = width * width;".to_string()).unwrap();
+//!
+//! assert_eq!(Some(9.0), vm.get_global::<f64>("area"));
+//! ```
+
+mod assembler;
+mod ast;
+mod bytecode;
+mod cfg;
+mod codegen;
+mod compiler;
+mod decompiler;
+mod folder;
+mod function;
+mod heap;
+mod loxc;
+mod object;
+mod optimizer;
+mod parser;
+mod register;
+mod resolver;
+mod scanner;
+mod string;
+mod value;
+mod verifier;
+mod vm;
+
+pub use assembler::AssemblerError;
+pub use bytecode::DisassemblerOptions;
+pub use compiler::{CompilerError, CompilerOptions};
+pub use decompiler::DecompileError;
+pub use heap::OutOfMemory;
+pub use loxc::{is_loxc, LoadError};
+pub use resolver::{Lint, LintLevel, Lints, Warning};
+pub use scanner::{
+    scan_lossless, LosslessToken, ScannerError, Token, TokenKind, Trivia, TriviaKind,
+};
+pub use value::{FromLox, IntoLox, LoxValue, TypeError};
+pub use verifier::VerifierError;
+pub use vm::{
+    Backend, InterpretResult, InterruptHandle, RuntimeError, Vm, VmError, VmOptions,
+    DEFAULT_MAX_STACK,
+};
@@ -0,0 +1,433 @@
+//! The `.loxc` format: compiled chunks on disk.
+//!
+//! A file starts with a fixed header:
+//!
+//! | bytes | contents                                          |
+//! |-------|---------------------------------------------------|
+//! | 4     | the magic number `LOXC`                           |
+//! | 2     | the format version                                |
+//! | 4     | FNV-1a checksum of everything after the header    |
+//!
+//! followed by the top-level chunk. A chunk is its constant pool, the number of inline caches
+//! it uses, its code, its line table as runs of a line and a byte count, and the names and scopes
+//! of its local variables. Every integer, including jump operands inside the code, is
+//! little-endian. Counts and lengths are `u32`.
+//!
+//! Constants start with a tag byte: nil, booleans and numbers are stored inline, strings as
+//! UTF-8 and functions as an optional name followed by an optional nested chunk.
+
+use crate::bytecode::{Chunk, LocalInfo};
+use crate::function::Function;
+use crate::heap::Heap;
+use crate::string::String;
+use crate::value::Value;
+
+const MAGIC: [u8; 4] = *b"LOXC";
+const VERSION: u16 = 4;
+
+const HEADER_LEN: usize = 10;
+/// Deepest nesting of function constants a file may contain.
+const MAX_DEPTH: usize = 64;
+
+const TAG_NIL: u8 = 0;
+const TAG_BOOLEAN: u8 = 1;
+const TAG_NUMBER: u8 = 2;
+const TAG_STRING: u8 = 3;
+const TAG_FUNCTION: u8 = 4;
+
+/// Why a `.loxc` file could not be loaded.
+#[derive(Debug, PartialEq, Eq)]
+pub struct LoadError {
+    msg: std::string::String,
+}
+
+impl LoadError {
+    pub(crate) fn new(msg: &str) -> Self {
+        Self {
+            msg: msg.to_string(),
+        }
+    }
+
+    pub fn msg(&self) -> &str {
+        &self.msg
+    }
+}
+
+/// Whether `bytes` start like a `.loxc` file, whatever the name of the file they came from.
+pub fn is_loxc(bytes: &[u8]) -> bool {
+    bytes.starts_with(&MAGIC)
+}
+
+/// Serializes `chunk`, header included.
+pub(crate) fn encode(chunk: &Chunk) -> Vec<u8> {
+    let mut body = Vec::new();
+    write_chunk(&mut body, chunk);
+
+    let mut bytes = Vec::with_capacity(HEADER_LEN + body.len());
+    bytes.extend_from_slice(&MAGIC);
+    bytes.extend_from_slice(&VERSION.to_le_bytes());
+    bytes.extend_from_slice(&checksum(&body).to_le_bytes());
+    bytes.extend_from_slice(&body);
+    bytes
+}
+
+/// Deserializes a chunk written by `encode`, allocating its strings and functions in `heap`.
+///
+/// Only the container is checked here; the code itself still has to go through the verifier.
+pub(crate) fn decode(bytes: &[u8], heap: &mut Heap) -> Result<Chunk, LoadError> {
+    if bytes.len() < HEADER_LEN || bytes[..4] != MAGIC {
+        return Err(LoadError::new("not a loxc file."));
+    }
+
+    let version = u16::from_le_bytes([bytes[4], bytes[5]]);
+    if version != VERSION {
+        return Err(LoadError::new(&format!(
+            "unsupported format version {}, expected {}.",
+            version, VERSION
+        )));
+    }
+
+    let body = &bytes[HEADER_LEN..];
+    let expected = u32::from_le_bytes([bytes[6], bytes[7], bytes[8], bytes[9]]);
+    if checksum(body) != expected {
+        return Err(LoadError::new("checksum mismatch, the file is corrupted."));
+    }
+
+    let mut reader = Reader { bytes: body, heap };
+    let chunk = reader.chunk(0)?;
+    if !reader.bytes.is_empty() {
+        return Err(LoadError::new("trailing bytes after the chunk."));
+    }
+
+    Ok(chunk)
+}
+
+/// 32-bit FNV-1a.
+fn checksum(bytes: &[u8]) -> u32 {
+    bytes.iter().fold(0x811c9dc5, |hash, byte| {
+        (hash ^ *byte as u32).wrapping_mul(0x01000193)
+    })
+}
+
+fn write_u32(out: &mut Vec<u8>, value: usize) {
+    let value = u32::try_from(value).expect("chunk too large for the loxc format");
+    out.extend_from_slice(&value.to_le_bytes());
+}
+
+fn write_str(out: &mut Vec<u8>, string: &str) {
+    write_u32(out, string.len());
+    out.extend_from_slice(string.as_bytes());
+}
+
+fn write_chunk(out: &mut Vec<u8>, chunk: &Chunk) {
+    write_u32(out, chunk.constants().len());
+    for constant in chunk.constants().iter() {
+        write_value(out, constant);
+    }
+
+    write_u32(out, chunk.caches().len());
+
+    write_u32(out, chunk.len());
+    out.extend_from_slice(chunk.code());
+
+    write_u32(out, chunk.lines().runs().count());
+    for (line, count) in chunk.lines().runs() {
+        write_u32(out, line);
+        write_u32(out, count);
+    }
+
+    write_u32(out, chunk.locals().len());
+    for local in chunk.locals() {
+        write_str(out, &local.name);
+        out.push(local.slot);
+        write_u32(out, local.start);
+        write_u32(out, local.end);
+    }
+}
+
+fn write_value(out: &mut Vec<u8>, value: &Value) {
+    match value {
+        Value::Nil => out.push(TAG_NIL),
+        Value::Boolean(boolean) => {
+            out.push(TAG_BOOLEAN);
+            out.push(*boolean as u8);
+        }
+        Value::Number(number) => {
+            out.push(TAG_NUMBER);
+            out.extend_from_slice(&number.to_le_bytes());
+        }
+        Value::String(string) => {
+            out.push(TAG_STRING);
+            write_str(out, string);
+        }
+        Value::Function(function) => {
+            out.push(TAG_FUNCTION);
+            match function.declared_name() {
+                Some(name) => {
+                    out.push(1);
+                    write_str(out, name);
+                }
+                None => out.push(0),
+            }
+            match function.chunk() {
+                Some(chunk) => {
+                    out.push(1);
+                    write_chunk(out, chunk);
+                }
+                None => out.push(0),
+            }
+        }
+    }
+}
+
+struct Reader<'b, 'h> {
+    bytes: &'b [u8],
+    heap: &'h mut Heap,
+}
+
+impl<'b, 'h> Reader<'b, 'h> {
+    fn take(&mut self, len: usize) -> Result<&'b [u8], LoadError> {
+        if len > self.bytes.len() {
+            return Err(LoadError::new("unexpected end of file."));
+        }
+
+        let (taken, rest) = self.bytes.split_at(len);
+        self.bytes = rest;
+        Ok(taken)
+    }
+
+    fn u8(&mut self) -> Result<u8, LoadError> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u32(&mut self) -> Result<usize, LoadError> {
+        let bytes = self.take(4)?;
+        Ok(u32::from_le_bytes(bytes.try_into().unwrap()) as usize)
+    }
+
+    fn flag(&mut self) -> Result<bool, LoadError> {
+        match self.u8()? {
+            0 => Ok(false),
+            1 => Ok(true),
+            _ => Err(LoadError::new("invalid flag byte.")),
+        }
+    }
+
+    fn str(&mut self) -> Result<&'b str, LoadError> {
+        let len = self.u32()?;
+        std::str::from_utf8(self.take(len)?).map_err(|_| LoadError::new("invalid UTF-8 string."))
+    }
+
+    fn chunk(&mut self, depth: usize) -> Result<Chunk, LoadError> {
+        if depth > MAX_DEPTH {
+            return Err(LoadError::new("functions nested too deeply."));
+        }
+
+        let mut chunk = Chunk::new();
+        for _ in 0..self.u32()? {
+            let constant = self.value(depth)?;
+            chunk.add_constant(constant);
+        }
+
+        // Caches take no space in the file, so bound them by what an operand can address.
+        let caches = self.u32()?;
+        if caches > u8::MAX as usize + 1 {
+            return Err(LoadError::new("too many inline caches."));
+        }
+        for _ in 0..caches {
+            chunk.add_cache();
+        }
+
+        let code_len = self.u32()?;
+        let mut code = self.take(code_len)?.iter();
+        for _ in 0..self.u32()? {
+            let line = self.u32()?;
+            let count = self.u32()?;
+            if count > code.len() {
+                return Err(LoadError::new("line table does not match the code."));
+            }
+            for byte in code.by_ref().take(count) {
+                chunk.write(*byte, line);
+            }
+        }
+        if code.len() > 0 {
+            return Err(LoadError::new("line table does not match the code."));
+        }
+
+        for _ in 0..self.u32()? {
+            let local = LocalInfo {
+                name: self.str()?.to_string(),
+                slot: self.u8()?,
+                start: self.u32()?,
+                end: self.u32()?,
+            };
+            if local.start > local.end || local.end > chunk.len() {
+                return Err(LoadError::new("local variable scope outside the code."));
+            }
+            chunk.add_local(local);
+        }
+
+        Ok(chunk)
+    }
+
+    fn value(&mut self, depth: usize) -> Result<Value, LoadError> {
+        let out_of_memory = |_| LoadError::new("out of memory.");
+
+        match self.u8()? {
+            TAG_NIL => Ok(Value::Nil),
+            TAG_BOOLEAN => Ok(Value::Boolean(self.flag()?)),
+            TAG_NUMBER => {
+                let bytes = self.take(8)?;
+                Ok(Value::Number(f64::from_le_bytes(bytes.try_into().unwrap())))
+            }
+            TAG_STRING => {
+                let string = String::new(self.str()?);
+                let handle = self.heap.allocate_string(string).map_err(out_of_memory)?;
+                Ok(Value::String(handle))
+            }
+            TAG_FUNCTION => {
+                let name = match self.flag()? {
+                    true => Some(String::new(self.str()?)),
+                    false => None,
+                };
+                let chunk = match self.flag()? {
+                    true => Some(self.chunk(depth + 1)?),
+                    false => None,
+                };
+                let function = Function::new(chunk, name);
+                let handle = self.heap.allocate(function).map_err(out_of_memory)?;
+                Ok(Value::Function(handle))
+            }
+            tag => Err(LoadError::new(&format!("invalid constant tag {}.", tag))),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bytecode::OpCode;
+    use crate::compiler::Compiler;
+
+    fn compile(source: &str, heap: &mut Heap) -> Chunk {
+        Compiler::new(None).compile(source, heap).unwrap()
+    }
+
+    #[test]
+    fn round_trips_compiled_code() {
+        let mut heap = Heap::new();
+        let source =
+            "var a = \"x\";\n{ var b = 1.5; while (b < 3) { b = b + 1; } print a + \"y\"; }";
+        let chunk = compile(source, &mut heap);
+
+        let mut other_heap = Heap::new();
+        let decoded = decode(&encode(&chunk), &mut other_heap).unwrap();
+
+        assert_eq!(&chunk.code()[..], &decoded.code()[..]);
+        assert_eq!(chunk.lines(), decoded.lines());
+        assert_eq!(&chunk.constants()[..], &decoded.constants()[..]);
+        assert_eq!(chunk.caches().len(), decoded.caches().len());
+        assert_eq!(chunk.locals(), decoded.locals());
+    }
+
+    #[test]
+    fn round_trips_every_constant() {
+        let mut heap = Heap::new();
+        let mut inner = Chunk::new();
+        inner.write(OpCode::Return as u8, 3);
+
+        let mut chunk = Chunk::new();
+        chunk.add_constant(Value::Nil);
+        chunk.add_constant(Value::Boolean(true));
+        chunk.add_constant(Value::Number(-0.25));
+        let string = heap.allocate_string(String::new("héllo")).unwrap();
+        chunk.add_constant(Value::String(string));
+        let function = Function::new(Some(inner), Some(String::new("f")));
+        chunk.add_constant(Value::Function(heap.allocate(function).unwrap()));
+        chunk.write(OpCode::Return as u8, 1);
+
+        let decoded = decode(&encode(&chunk), &mut heap).unwrap();
+
+        assert_eq!(&chunk.constants()[..4], &decoded.constants()[..4]);
+        let Value::Function(function) = decoded.constants()[4] else {
+            panic!("expected a function constant");
+        };
+        assert_eq!(Some("f"), function.declared_name());
+        assert_eq!(3, function.chunk().unwrap().line_for_offset(0));
+    }
+
+    #[test]
+    fn operands_are_little_endian() {
+        let mut heap = Heap::new();
+        let chunk = compile("while (false) {}", &mut heap);
+        let code = chunk.code();
+
+        // OP_LOOP is followed by the OP_POP of the loop exit and OP_RETURN. The loop starts at
+        // offset 0, so it jumps back over everything up to its own end.
+        let loop_at = code.len() - 5;
+        assert_eq!(OpCode::Loop as u8, code[loop_at]);
+        let distance = (loop_at + 3) as u16;
+        assert_eq!(distance.to_le_bytes(), code[loop_at + 1..loop_at + 3]);
+
+        // Code is stored verbatim.
+        let bytes = encode(&chunk);
+        assert!(bytes.windows(code.len()).any(|window| window == &code[..]));
+    }
+
+    #[test]
+    fn rejects_malformed_files() {
+        let mut heap = Heap::new();
+        let bytes = encode(&compile("print 1;", &mut heap));
+        let error = |bytes: &[u8]| decode(bytes, &mut Heap::new()).unwrap_err();
+
+        assert_eq!("not a loxc file.", error(b"print 1;").msg());
+
+        let mut future = bytes.clone();
+        future[4] = 5;
+        assert_eq!(
+            "unsupported format version 5, expected 4.",
+            error(&future).msg()
+        );
+
+        let mut corrupted = bytes.clone();
+        *corrupted.last_mut().unwrap() ^= 1;
+        assert_eq!(
+            "checksum mismatch, the file is corrupted.",
+            error(&corrupted).msg()
+        );
+
+        let mut truncated = bytes[..bytes.len() - 1].to_vec();
+        let body = checksum(&truncated[HEADER_LEN..]);
+        truncated[6..10].copy_from_slice(&body.to_le_bytes());
+        assert_eq!("unexpected end of file.", error(&truncated).msg());
+    }
+
+    #[test]
+    fn rejects_local_scopes_outside_the_code() {
+        let mut heap = Heap::new();
+        let mut error = |start: usize, end: usize| {
+            let mut chunk = compile("{ var a = 1; }", &mut heap);
+            chunk.add_local(LocalInfo {
+                name: "b".to_string(),
+                slot: 1,
+                start,
+                end,
+            });
+            decode(&encode(&chunk), &mut Heap::new()).unwrap_err()
+        };
+
+        assert_eq!("local variable scope outside the code.", error(3, 2).msg());
+        assert_eq!(
+            "local variable scope outside the code.",
+            error(0, 100).msg()
+        );
+    }
+
+    #[test]
+    fn recognizes_files_by_their_magic() {
+        let mut heap = Heap::new();
+        assert!(is_loxc(&encode(&compile("print 1;", &mut heap))));
+        assert!(!is_loxc(b"print 1;"));
+        assert!(!is_loxc(b"LOX"));
+    }
+}
@@ -1,85 +1,309 @@
-#![allow(unused)]
-
-mod bytecode;
-mod compiler;
-mod function;
-mod heap;
-mod object;
-mod scanner;
-mod string;
-mod value;
-mod vm;
-
 use std::{
-    fs::File,
+    fs,
     io::prelude::*,
     path::{Path, PathBuf},
     process::exit,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
 };
 
-use clap::Parser;
+use clap::{Parser, Subcommand};
 
-use crate::compiler::CompilerOptions;
-use rlox_common::hashmap::HashMap;
-use vm::Vm;
+use rlox::{
+    is_loxc, Backend, CompilerOptions, DisassemblerOptions, Lint, LintLevel, Lints, Vm, VmError,
+    VmOptions, DEFAULT_MAX_STACK,
+};
 
 #[derive(Parser, Debug)]
 #[clap(author, version, about, long_about = None)]
 struct Args {
-    #[clap(short, long, value_parser)]
+    #[clap(subcommand)]
+    command: Option<Command>,
+
+    #[clap(short, long, global = true, value_parser)]
     trace_execution: bool,
-    #[clap(short, long, value_parser)]
+    #[clap(short, long, global = true, value_parser)]
     print_code: bool,
+    /// Print the syntax tree of the script
+    #[clap(long, global = true, value_parser)]
+    dump_ast: bool,
+    /// Bytecode optimization level (0-2)
+    #[clap(short = 'O', long, global = true, value_parser = clap::value_parser!(u8).range(0..=2), default_value_t = 0)]
+    opt_level: u8,
+    /// Execution backend: "stack" or "register" (experimental)
+    #[clap(long, global = true, value_parser, default_value = "stack")]
+    backend: Backend,
+    /// Size of the value stack
+    #[clap(long, global = true, value_parser, default_value_t = DEFAULT_MAX_STACK)]
+    max_stack: usize,
+    /// Print inline cache hit and miss counters after running
+    #[clap(long, global = true, value_parser)]
+    profile: bool,
+    /// Ignore a lint, e.g. "unused-variable"
+    #[clap(short = 'A', long, global = true, value_parser, value_name = "LINT")]
+    allow: Vec<Lint>,
+    /// Report a lint as a warning, the default for every lint
+    #[clap(short = 'W', long, global = true, value_parser, value_name = "LINT")]
+    warn: Vec<Lint>,
+    /// Reject scripts a lint finds something in
+    #[clap(short = 'D', long, global = true, value_parser, value_name = "LINT")]
+    deny: Vec<Lint>,
 
-    // Lox source code file path
+    /// Lox source code or .loxc bytecode file to run. Subcommand names take precedence, so run
+    /// a script called e.g. "compile" as "./compile"
     file_path: Option<PathBuf>,
 }
 
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Compile a script to a .loxc bytecode file
+    Compile {
+        file_path: PathBuf,
+        /// Output path, defaults to the script path with a .loxc extension
+        #[clap(short, long, value_parser)]
+        output: Option<PathBuf>,
+    },
+    /// Assemble textual bytecode to a .loxc bytecode file
+    Asm {
+        file_path: PathBuf,
+        /// Output path, defaults to the assembly path with a .loxc extension
+        #[clap(short, long, value_parser)]
+        output: Option<PathBuf>,
+    },
+    /// List the bytecode of a script or a .loxc bytecode file
+    Disasm {
+        file_path: PathBuf,
+        /// Show the bytes of each instruction
+        #[clap(long, value_parser)]
+        raw_bytes: bool,
+        /// Name jump targets with labels
+        #[clap(long, value_parser)]
+        labels: bool,
+        /// List the constant pool
+        #[clap(long, value_parser)]
+        constants: bool,
+        /// List the chunks of function constants
+        #[clap(long, value_parser)]
+        functions: bool,
+        /// Print the control-flow graph in Graphviz DOT instead
+        #[clap(long, value_parser)]
+        cfg: bool,
+    },
+    /// Write a script or a .loxc bytecode file back as Lox source
+    Decompile { file_path: PathBuf },
+}
+
 fn main() -> std::io::Result<()> {
     let args = Args::parse();
-    let vm_opts = vm::VmOptions {
+
+    let mut lints = Lints::default();
+    for (names, level) in [
+        (&args.allow, LintLevel::Allow),
+        (&args.warn, LintLevel::Warn),
+        (&args.deny, LintLevel::Deny),
+    ] {
+        for lint in names {
+            lints.set(*lint, level);
+        }
+    }
+
+    let vm_opts = VmOptions {
         trace_execution: args.trace_execution,
         compiler: CompilerOptions {
             print_code: args.print_code,
+            dump_ast: args.dump_ast,
+            opt_level: args.opt_level,
+            lints,
         },
+        backend: args.backend,
+        profile: args.profile,
+        max_stack: args.max_stack,
+        max_heap_bytes: None,
+        fuel: None,
+        output: None,
     };
 
-    if let Some(ref file_path) = args.file_path {
-        run_file(file_path, Some(vm_opts))?;
-    } else {
-        repl(Some(vm_opts))?;
+    match args.command {
+        Some(Command::Compile { file_path, output }) => {
+            let output = output.unwrap_or_else(|| file_path.with_extension("loxc"));
+            compile_file(&file_path, &output, Some(vm_opts))?;
+        }
+        Some(Command::Asm { file_path, output }) => {
+            let output = output.unwrap_or_else(|| file_path.with_extension("loxc"));
+            assemble_file(&file_path, &output, Some(vm_opts))?;
+        }
+        Some(Command::Disasm {
+            file_path,
+            raw_bytes,
+            labels,
+            constants,
+            functions,
+            cfg,
+        }) => {
+            let options = DisassemblerOptions {
+                raw_bytes,
+                labels,
+                constants,
+                functions,
+                cfg,
+            };
+            disassemble_file(&file_path, options, Some(vm_opts))?;
+        }
+        Some(Command::Decompile { file_path }) => decompile_file(&file_path, Some(vm_opts))?,
+        None => match args.file_path {
+            Some(ref file_path) => run_file(file_path, Some(vm_opts))?,
+            None => repl(Some(vm_opts))?,
+        },
     }
 
     Ok(())
 }
 
-fn run_file(file_path: &Path, vm_opts: Option<vm::VmOptions>) -> std::io::Result<()> {
-    let mut file = File::open(file_path)?;
-    let mut source = String::new();
-    file.read_to_string(&mut source)?;
+fn compile_file(
+    file_path: &Path,
+    output: &Path,
+    vm_opts: Option<VmOptions>,
+) -> std::io::Result<()> {
+    let source = fs::read_to_string(file_path)?;
 
     let mut vm = Vm::new(vm_opts);
-    let result = vm.interpret(source);
+    let bytes = vm.compile_bytecode(source);
+    print_warnings(&vm);
 
-    match result {
-        Ok(_) => exit(0),
-        Err(error) => {
-            eprintln!("{}", error);
+    match bytes {
+        Ok(bytes) => fs::write(output, bytes),
+        Err(error) => fail(error),
+    }
+}
 
-            let exit_code = match error {
-                vm::VmError::Compile(_) => 65,
-                vm::VmError::Runtime(_) => 70,
-            };
+fn assemble_file(
+    file_path: &Path,
+    output: &Path,
+    vm_opts: Option<VmOptions>,
+) -> std::io::Result<()> {
+    let source = fs::read_to_string(file_path)?;
+
+    let mut vm = Vm::new(vm_opts);
+    match vm.assemble_bytecode(&source) {
+        Ok(bytes) => fs::write(output, bytes),
+        Err(error) => fail(error),
+    }
+}
+
+fn disassemble_file(
+    file_path: &Path,
+    options: DisassemblerOptions,
+    vm_opts: Option<VmOptions>,
+) -> std::io::Result<()> {
+    let mut vm = Vm::new(vm_opts);
+    let listing = match read_script(file_path)? {
+        Script::Bytecode(bytes) => vm.disassemble_bytecode(&bytes, options),
+        Script::Source(source) => vm.disassemble(source, options),
+    };
+    print_warnings(&vm);
+
+    match listing {
+        Ok(listing) => {
+            print!("{}", listing);
+            Ok(())
+        }
+        Err(error) => fail(error),
+    }
+}
 
-            exit(exit_code);
+fn decompile_file(file_path: &Path, vm_opts: Option<VmOptions>) -> std::io::Result<()> {
+    let mut vm = Vm::new(vm_opts);
+    let source = match read_script(file_path)? {
+        Script::Bytecode(bytes) => vm.decompile_bytecode(&bytes),
+        Script::Source(source) => vm.decompile(source),
+    };
+    print_warnings(&vm);
+
+    match source {
+        Ok(source) => {
+            print!("{}", source);
+            Ok(())
         }
+        Err(error) => fail(error),
     }
 }
 
-fn repl(vm_opts: Option<vm::VmOptions>) -> std::io::Result<()> {
+/// The contents of a script file.
+enum Script {
+    Bytecode(Vec<u8>),
+    Source(String),
+}
+
+/// Reads a script, telling bytecode from source by the magic number `.loxc` files start with
+/// rather than by the name of the file.
+fn read_script(file_path: &Path) -> std::io::Result<Script> {
+    let bytes = fs::read(file_path)?;
+    if is_loxc(&bytes) {
+        return Ok(Script::Bytecode(bytes));
+    }
+
+    String::from_utf8(bytes)
+        .map(Script::Source)
+        .map_err(|error| std::io::Error::new(std::io::ErrorKind::InvalidData, error))
+}
+
+fn run_file(file_path: &Path, vm_opts: Option<VmOptions>) -> std::io::Result<()> {
+    let mut vm = Vm::new(vm_opts);
+    let prepared = match read_script(file_path)? {
+        Script::Bytecode(bytes) => vm.prepare_bytecode(&bytes),
+        Script::Source(source) => vm.prepare(source),
+    };
+    print_warnings(&vm);
+
+    match prepared.and_then(|_| vm.resume().map(|_| ())) {
+        Ok(_) => exit(0),
+        Err(error) => fail(error),
+    }
+}
+
+fn print_warnings(vm: &Vm) {
+    for warning in vm.warnings() {
+        eprintln!("{}", warning);
+    }
+}
+
+fn fail(error: VmError) -> ! {
+    eprintln!("{}", error);
+
+    let exit_code = match error {
+        VmError::Compile(_)
+        | VmError::Assemble(_)
+        | VmError::Load(_)
+        | VmError::Verify(_)
+        | VmError::Decompile(_) => 65,
+        VmError::Runtime(_) | VmError::Call(_) | VmError::OutOfFuel | VmError::Interrupted => 70,
+    };
+
+    exit(exit_code);
+}
+
+fn repl(vm_opts: Option<VmOptions>) -> std::io::Result<()> {
     let stdin = std::io::stdin();
     let mut vm = Vm::new(vm_opts);
 
+    // Ctrl-C stops the running script instead of the whole REPL, and exits at the prompt.
+    let interrupt = vm.interrupt_handle();
+    let handler_interrupt = interrupt.clone();
+    let running = Arc::new(AtomicBool::new(false));
+    let handler_running = Arc::clone(&running);
+    let handler = move || {
+        if handler_running.load(Ordering::SeqCst) {
+            handler_interrupt.interrupt();
+        } else {
+            exit(130);
+        }
+    };
+    if let Err(err) = ctrlc::set_handler(handler) {
+        eprintln!("cannot handle Ctrl-C: {}", err);
+    }
+
     print!("> ");
     std::io::stdout().flush()?;
     for line in stdin.lock().lines() {
@@ -88,7 +312,15 @@ fn repl(vm_opts: Option<vm::VmOptions>) -> std::io::Result<()> {
             exit(0);
         }
 
-        if let Err(err) = vm.interpret(line) {
+        let prepared = vm.prepare(line);
+        print_warnings(&vm);
+
+        running.store(true, Ordering::SeqCst);
+        let result = prepared.and_then(|_| vm.resume().map(|_| ()));
+        running.store(false, Ordering::SeqCst);
+        // A Ctrl-C the script finished before honoring must not stop the next line.
+        interrupt.clear();
+        if let Err(err) = result {
             println!("{}", err)
         }
 
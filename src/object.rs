@@ -1,14 +1,13 @@
 use std::{
-    borrow::Borrow,
-    fmt::{self, Debug, Display},
-    ops::{Add, Deref, DerefMut},
+    fmt::{self, Debug},
+    ops::{Deref, DerefMut},
     ptr::NonNull,
-    string::String as RustString,
 };
 
-use crate::{string::String, value::Value, vm::HEAP};
-
 /// A pointer to values in the Lox heap.
+///
+/// Handles are neither `Send` nor `Sync`: copies of a handle alias one object without any
+/// synchronization. Only a whole `Vm`, which owns the heap they point into, may change threads.
 pub struct Handle<T> {
     raw: NonNull<T>,
 }
@@ -24,6 +23,11 @@ impl<T> Handle<T> {
         }
     }
 
+    /// Whether both handles point to the same object.
+    pub(crate) fn ptr_eq(this: &Self, other: &Self) -> bool {
+        this.raw == other.raw
+    }
+
     pub unsafe fn as_ptr(&mut self) -> *mut T {
         self.raw.as_ptr()
     }
@@ -56,8 +60,6 @@ impl<T> Clone for Handle<T> {
 }
 
 impl<T: Clone> Copy for Handle<T> {}
-unsafe impl<T> Sync for Handle<T> where T: Sync {}
-unsafe impl<T> Send for Handle<T> where T: Send {}
 
 impl<T: Debug> Debug for Handle<T> {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
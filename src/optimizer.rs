@@ -0,0 +1,492 @@
+use crate::bytecode::{Chunk, OpCode};
+
+/// A decoded instruction.
+///
+/// Jump operands are kept as the index of the target instruction so the code can be rewritten
+/// without worrying about offsets, and encoded again once all the passes are done.
+#[derive(Debug)]
+struct Instruction {
+    /// Offset in the code before optimizing.
+    offset: usize,
+    opcode: OpCode,
+    operands: [u8; 2],
+    target: Option<usize>,
+    line: usize,
+    removed: bool,
+}
+
+/// A peephole optimizer over a `Chunk`.
+///
+/// Level 1 removes jumps to the next instruction, collapses `Not; JumpIfFalse` into `JumpIfTrue`
+/// and drops constants that are immediately popped. Level 2 also removes unreachable code after
+/// unconditional jumps.
+///
+/// A script's result is the last value it popped, so a pop is only removed or changed when
+/// another statement is sure to pop a value after it.
+pub(crate) struct Optimizer {
+    instructions: Vec<Instruction>,
+    level: u8,
+}
+
+impl Optimizer {
+    /// Optimizes `chunk` in place. Code that cannot be decoded is left untouched.
+    pub fn optimize(chunk: &mut Chunk, level: u8) {
+        if level == 0 {
+            return;
+        }
+
+        if let Some(instructions) = decode(chunk) {
+            let mut optimizer = Self {
+                instructions,
+                level,
+            };
+
+            while optimizer.run_passes() {}
+            optimizer.encode(chunk);
+        }
+    }
+
+    /// Runs every pass once, returns `true` if any of them changed the code.
+    fn run_passes(&mut self) -> bool {
+        let mut changed = false;
+
+        changed |= self.remove_jumps_to_next();
+        changed |= self.collapse_not_jump_if_false();
+        changed |= self.remove_popped_constants();
+        if self.level >= 2 {
+            changed |= self.remove_dead_code();
+        }
+
+        changed
+    }
+
+    fn remove_jumps_to_next(&mut self) -> bool {
+        let mut changed = false;
+
+        for index in self.live() {
+            let instruction = &self.instructions[index];
+            if instruction.opcode == OpCode::Jump
+                && self.resolve(instruction.target.unwrap()) == self.next_live(index)
+            {
+                self.instructions[index].removed = true;
+                changed = true;
+            }
+        }
+
+        changed
+    }
+
+    /// `Not; JumpIfFalse` becomes `JumpIfTrue`, but only when both successors of the jump pop the
+    /// condition. Otherwise the negated value would be observable, as in `!a and b`.
+    fn collapse_not_jump_if_false(&mut self) -> bool {
+        let mut changed = false;
+        let targets = self.jump_targets();
+        let overwritten = self.result_overwritten();
+
+        for not in self.live() {
+            if self.instructions[not].opcode != OpCode::Not {
+                continue;
+            }
+
+            let jump = self.next_live(not);
+            if jump >= self.instructions.len()
+                || self.instructions[jump].opcode != OpCode::JumpIfFalse
+                || targets[jump]
+            {
+                continue;
+            }
+
+            let fallthrough = self.next_live(jump);
+            let target = self.resolve(self.instructions[jump].target.unwrap());
+            if self.is_pop(fallthrough)
+                && self.is_pop(target)
+                && overwritten[self.next_live(fallthrough)]
+                && overwritten[self.next_live(target)]
+            {
+                self.instructions[not].removed = true;
+                self.instructions[jump].opcode = OpCode::JumpIfTrue;
+                changed = true;
+            }
+        }
+
+        changed
+    }
+
+    fn remove_popped_constants(&mut self) -> bool {
+        let mut changed = false;
+        let targets = self.jump_targets();
+        let overwritten = self.result_overwritten();
+
+        for constant in self.live() {
+            if self.instructions[constant].removed
+                || !matches!(
+                    self.instructions[constant].opcode,
+                    OpCode::AddConstant | OpCode::AddNil | OpCode::AddTrue | OpCode::AddFalse
+                )
+            {
+                continue;
+            }
+
+            let pop = self.next_live(constant);
+            if self.is_pop(pop) && !targets[pop] && overwritten[self.next_live(pop)] {
+                self.instructions[constant].removed = true;
+                self.instructions[pop].removed = true;
+                changed = true;
+            }
+        }
+
+        changed
+    }
+
+    fn remove_dead_code(&mut self) -> bool {
+        let mut changed = false;
+        let targets = self.jump_targets();
+        let mut reachable = true;
+
+        for index in self.live() {
+            if targets[index] {
+                reachable = true;
+            }
+
+            if !reachable {
+                self.instructions[index].removed = true;
+                changed = true;
+                continue;
+            }
+
+            if let OpCode::Jump | OpCode::Loop | OpCode::Return = self.instructions[index].opcode {
+                reachable = false;
+            }
+        }
+
+        changed
+    }
+
+    /// Marks, for every instruction (plus the end of the code), whether every path from it pops a
+    /// statement's value before returning, which replaces the result of the script.
+    fn result_overwritten(&self) -> Vec<bool> {
+        let end = self.instructions.len();
+        let mut overwritten = vec![true; end + 1];
+        overwritten[end] = false;
+
+        let live = self.live();
+        let mut changed = true;
+        while changed {
+            changed = false;
+
+            for &index in live.iter().rev() {
+                let instruction = &self.instructions[index];
+                let next = overwritten[self.next_live(index)];
+                let value = match instruction.opcode {
+                    OpCode::Pop | OpCode::Print | OpCode::DefineGlobal => true,
+                    OpCode::Return => false,
+                    OpCode::Jump | OpCode::Loop => {
+                        overwritten[self.resolve(instruction.target.unwrap())]
+                    }
+                    OpCode::JumpIfFalse | OpCode::JumpIfTrue => {
+                        next && overwritten[self.resolve(instruction.target.unwrap())]
+                    }
+                    _ => next,
+                };
+
+                if value != overwritten[index] {
+                    overwritten[index] = value;
+                    changed = true;
+                }
+            }
+        }
+
+        overwritten
+    }
+
+    /// Indices of the instructions that have not been removed.
+    fn live(&self) -> Vec<usize> {
+        (0..self.instructions.len())
+            .filter(|index| !self.instructions[*index].removed)
+            .collect()
+    }
+
+    /// Index of the first live instruction after `index`, or the end of the code.
+    fn next_live(&self, index: usize) -> usize {
+        self.resolve(index + 1)
+    }
+
+    /// Index of the first live instruction at or after `index`, or the end of the code.
+    fn resolve(&self, mut index: usize) -> usize {
+        while index < self.instructions.len() && self.instructions[index].removed {
+            index += 1;
+        }
+
+        index
+    }
+
+    fn is_pop(&self, index: usize) -> bool {
+        index < self.instructions.len() && self.instructions[index].opcode == OpCode::Pop
+    }
+
+    /// Marks, for every instruction (plus the end of the code), whether a live jump lands on it.
+    fn jump_targets(&self) -> Vec<bool> {
+        let mut targets = vec![false; self.instructions.len() + 1];
+
+        for index in self.live() {
+            if let Some(target) = self.instructions[index].target {
+                targets[self.resolve(target)] = true;
+            }
+        }
+
+        targets
+    }
+
+    fn encode(&self, chunk: &mut Chunk) {
+        let mut offsets = Vec::with_capacity(self.instructions.len() + 1);
+        let mut offset = 0;
+        for instruction in &self.instructions {
+            offsets.push(offset);
+            if !instruction.removed {
+                offset += 1 + instruction.opcode.operand_width();
+            }
+        }
+        offsets.push(offset);
+
+        // Debug information points at instructions too. A removed instruction maps to the next
+        // live one.
+        let end = self.instructions.len();
+        for local in chunk.locals_mut() {
+            for old in [&mut local.start, &mut local.end] {
+                let index = self
+                    .instructions
+                    .binary_search_by_key(old, |instruction| instruction.offset)
+                    .unwrap_or(end);
+                *old = offsets[index];
+            }
+        }
+
+        chunk.truncate(0);
+        for instruction in self.instructions.iter().filter(|i| !i.removed) {
+            let start = chunk.len();
+            chunk.write(instruction.opcode as u8, instruction.line);
+
+            if let Some(target) = instruction.target {
+                let target = offsets[self.resolve(target)];
+                let jump = match instruction.opcode {
+                    OpCode::Loop => start + 3 - target,
+                    _ => target - (start + 3),
+                };
+
+                for byte in (jump as u16).to_le_bytes() {
+                    chunk.write(byte, instruction.line);
+                }
+            } else {
+                for operand in &instruction.operands[..instruction.opcode.operand_width()] {
+                    chunk.write(*operand, instruction.line);
+                }
+            }
+        }
+    }
+}
+
+/// Decodes `chunk` into instructions. Returns `None` if the code is malformed.
+fn decode(chunk: &Chunk) -> Option<Vec<Instruction>> {
+    let code = chunk.code();
+    let mut instructions = Vec::new();
+    let mut starts = Vec::new();
+    let mut jumps = Vec::new();
+    let mut offset = 0;
+
+    while offset < code.len() {
+        let opcode = OpCode::from_repr(code[offset])?;
+        let next = offset + 1 + opcode.operand_width();
+        if next > code.len() {
+            return None;
+        }
+
+        let mut operands = [0; 2];
+        operands[..opcode.operand_width()].copy_from_slice(&code[offset + 1..next]);
+
+        let target = if opcode.is_jump() {
+            let jump = u16::from_le_bytes(operands) as usize;
+            jumps.push(instructions.len());
+            match opcode {
+                OpCode::Loop => Some(next.checked_sub(jump)?),
+                _ => Some(next + jump),
+            }
+        } else {
+            None
+        };
+
+        starts.push(offset);
+        instructions.push(Instruction {
+            offset,
+            opcode,
+            operands,
+            target,
+            line: chunk.line_for_offset(offset),
+            removed: false,
+        });
+        offset = next;
+    }
+    starts.push(offset);
+
+    // Jump targets are offsets so far, they need to land on an instruction to become indices.
+    for index in jumps {
+        let target = instructions[index].target?;
+        instructions[index].target = Some(starts.binary_search(&target).ok()?);
+    }
+
+    Some(instructions)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compiler::{Compiler, CompilerOptions};
+    use crate::heap::Heap;
+    use crate::value::LoxValue;
+
+    fn compile(source: &str, level: u8) -> Chunk {
+        let options = CompilerOptions {
+            opt_level: level,
+            ..Default::default()
+        };
+        Compiler::new(Some(&options))
+            .compile(source, &mut Heap::new())
+            .unwrap()
+    }
+
+    fn opcodes(chunk: &Chunk) -> Vec<OpCode> {
+        decode(chunk)
+            .unwrap()
+            .into_iter()
+            .map(|instruction| instruction.opcode)
+            .collect()
+    }
+
+    #[test]
+    fn level_zero_leaves_code_alone() {
+        let opcodes = opcodes(&compile("var a = 1; if (!a) print a; 1;", 0));
+
+        assert!(opcodes.contains(&OpCode::Not));
+        assert!(opcodes.contains(&OpCode::AddConstant));
+    }
+
+    #[test]
+    fn removes_popped_constants() {
+        let chunk = compile("1; \"two\"; nil; true; print 3;", 1);
+
+        assert_eq!(
+            vec![OpCode::AddConstant, OpCode::Print, OpCode::Return],
+            opcodes(&chunk)
+        );
+        assert_eq!(chunk.len(), chunk.lines().len());
+    }
+
+    #[test]
+    fn keeps_the_result_of_the_script() {
+        for level in 1..=2 {
+            let options = crate::vm::VmOptions {
+                compiler: CompilerOptions {
+                    opt_level: level,
+                    ..Default::default()
+                },
+                ..Default::default()
+            };
+            let mut vm = crate::vm::Vm::new(Some(options));
+
+            let result = vm.interpret("1 + 1;".to_string());
+            assert_eq!(LoxValue::Number(2.0), result.unwrap());
+            let result = vm.interpret("var a = true; if (!a) 1;".to_string());
+            assert_eq!(LoxValue::Boolean(false), result.unwrap());
+        }
+    }
+
+    #[test]
+    fn collapses_not_jump_if_false() {
+        let chunk = compile("var a = 1; if (!a) print a; print 2;", 1);
+        let opcodes = opcodes(&chunk);
+
+        assert!(!opcodes.contains(&OpCode::Not));
+        assert!(opcodes.contains(&OpCode::JumpIfTrue));
+    }
+
+    #[test]
+    fn keeps_not_when_the_value_is_used() {
+        let chunk = compile("var a = 1; print !a and a;", 1);
+
+        assert!(opcodes(&chunk).contains(&OpCode::Not));
+    }
+
+    #[test]
+    fn removes_jumps_to_next() {
+        let mut chunk = Chunk::new();
+        chunk.write(OpCode::Jump as u8, 1);
+        chunk.write(0, 1);
+        chunk.write(0, 1);
+        chunk.write(OpCode::Return as u8, 2);
+
+        Optimizer::optimize(&mut chunk, 1);
+
+        assert_eq!(&[OpCode::Return as u8], &chunk.code()[..]);
+        assert_eq!(vec![2], chunk.lines().iter().collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn removes_dead_code_and_repatches_jumps() {
+        let mut chunk = Chunk::new();
+        // 0: JUMP -> 5, 3: NIL, 4: POP (dead), 5: TRUE, 6: JUMP_IF_FALSE -> 11, 9: NIL, 10: PRINT,
+        // 11: RETURN
+        let code = [
+            OpCode::Jump as u8,
+            2,
+            0,
+            OpCode::AddNil as u8,
+            OpCode::Print as u8,
+            OpCode::AddTrue as u8,
+            OpCode::JumpIfFalse as u8,
+            2,
+            0,
+            OpCode::AddNil as u8,
+            OpCode::Print as u8,
+            OpCode::Return as u8,
+        ];
+        for (line, byte) in code.iter().enumerate() {
+            chunk.write(*byte, line);
+        }
+
+        Optimizer::optimize(&mut chunk, 1);
+        assert_eq!(code.len(), chunk.len());
+
+        Optimizer::optimize(&mut chunk, 2);
+        assert_eq!(
+            &[
+                OpCode::AddTrue as u8,
+                OpCode::JumpIfFalse as u8,
+                2,
+                0,
+                OpCode::AddNil as u8,
+                OpCode::Print as u8,
+                OpCode::Return as u8,
+            ],
+            &chunk.code()[..]
+        );
+        assert_eq!(
+            vec![5, 6, 6, 6, 9, 10, 11],
+            chunk.lines().iter().collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn optimized_loops_still_run() {
+        let options = crate::vm::VmOptions {
+            compiler: CompilerOptions {
+                opt_level: 2,
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let mut vm = crate::vm::Vm::new(Some(options));
+
+        let result = vm.interpret("var a = 0; while (!(a == 10)) { a = a + 1; } a;".to_string());
+
+        assert_eq!(LoxValue::Number(10.0), result.unwrap());
+    }
+}
@@ -0,0 +1,623 @@
+use std::str::FromStr;
+
+use strum::FromRepr;
+
+use crate::{
+    ast::{Expr, ExprKind, Identifier, Literal, Operator, Program, Span, Stmt, StmtKind},
+    compiler::CompilerError,
+    scanner::{Scanner, Token, TokenKind},
+};
+
+pub(crate) type PrefixFn = fn(&mut Parser, bool) -> Result<Expr, CompilerError>;
+pub(crate) type InfixFn = fn(&mut Parser, Expr) -> Result<Expr, CompilerError>;
+
+#[derive(Copy, Clone, Default)]
+pub(crate) struct ParseRule(Option<PrefixFn>, Option<InfixFn>, Precedence);
+
+impl ParseRule {
+    fn prefix(&self) -> Option<PrefixFn> {
+        self.0
+    }
+
+    fn infix(&self) -> Option<InfixFn> {
+        self.1
+    }
+
+    fn precedence(&self) -> Precedence {
+        self.2
+    }
+}
+
+/// From lowest to higest precedence.
+#[derive(Copy, FromRepr, Clone, Debug, Default, PartialEq, PartialOrd)]
+#[repr(u8)]
+pub(crate) enum Precedence {
+    #[default]
+    None = 0,
+    Assignment = 1,
+    Or = 2,
+    And = 3,
+    Equality = 4,
+    Comparison = 5,
+    Term = 6,
+    Factor = 7,
+    Unary = 8,
+    Call = 9,
+    Primary = 10,
+}
+
+impl Precedence {
+    pub(crate) fn higher(self) -> Precedence {
+        match self {
+            Precedence::Primary => Precedence::Primary,
+            _ => Precedence::from_repr(self as u8 + 1)
+                .expect("could not find a precedence with for the provided u8"),
+        }
+    }
+}
+
+/// A Pratt parser from source to an `ast::Program`.
+///
+/// After an error it skips to the next statement and carries on, so one pass reports every
+/// statement that is wrong.
+pub(crate) struct Parser<'source> {
+    previous: Token<'source>,
+    current: Token<'source>,
+    scanner: Scanner<'source>,
+    errors: Vec<CompilerError>,
+    /// Set by an error until the parser gets back to a statement boundary. Errors in the
+    /// meantime are most likely caused by the first one, so they are not reported.
+    panic_mode: bool,
+}
+
+impl<'source> Parser<'source> {
+    pub fn new(source: &'source str) -> Self {
+        Self {
+            previous: Token::dummy(),
+            current: Token::dummy(),
+            scanner: Scanner::new(source),
+            errors: Vec::new(),
+            panic_mode: false,
+        }
+    }
+
+    /// Parses the whole source, or returns every error found in it in source order.
+    pub fn parse(mut self) -> Result<Program, Vec<CompilerError>> {
+        let mut statements = Vec::new();
+
+        advance(&mut self);
+        while !matches(&mut self, TokenKind::Eof) {
+            statements.extend(declaration(&mut self));
+        }
+
+        if !self.errors.is_empty() {
+            return Err(self.errors);
+        }
+
+        let eof = Span::of(&self.previous);
+        Ok(Program {
+            statements,
+            span: Span {
+                start: 0,
+                line: 1,
+                ..eof
+            },
+        })
+    }
+}
+
+/// Parses a declaration, or records why it could not and skips to the next one.
+fn declaration(parser: &mut Parser) -> Option<Stmt> {
+    let result = if matches(parser, TokenKind::Var) {
+        var_declaration(parser)
+    } else {
+        statement(parser)
+    };
+
+    if let Err(error) = &result {
+        report(parser, error.msg(), error.line());
+    }
+    if parser.panic_mode {
+        synchronize(parser);
+    }
+
+    result.ok()
+}
+
+fn report(parser: &mut Parser, msg: &str, line: usize) {
+    if parser.panic_mode {
+        return;
+    }
+
+    parser.panic_mode = true;
+    parser.errors.push(CompilerError::new(msg, line));
+}
+
+/// Skips tokens until the end of the statement that failed to parse.
+fn synchronize(parser: &mut Parser) {
+    parser.panic_mode = false;
+
+    while !check(parser, TokenKind::Eof) {
+        if parser.previous.kind == TokenKind::Semicolon {
+            return;
+        }
+
+        match parser.current.kind {
+            TokenKind::Class
+            | TokenKind::Fun
+            | TokenKind::Var
+            | TokenKind::For
+            | TokenKind::If
+            | TokenKind::While
+            | TokenKind::Print
+            | TokenKind::Return => return,
+            _ => advance(parser),
+        }
+    }
+}
+
+fn statement(parser: &mut Parser) -> Result<Stmt, CompilerError> {
+    if matches(parser, TokenKind::Print) {
+        print_statement(parser)
+    } else if matches(parser, TokenKind::If) {
+        if_statement(parser)
+    } else if matches(parser, TokenKind::While) {
+        while_statement(parser)
+    } else if matches(parser, TokenKind::LeftBrace) {
+        block(parser)
+    } else {
+        expression_statement(parser)
+    }
+}
+
+fn var_declaration(parser: &mut Parser) -> Result<Stmt, CompilerError> {
+    let start = Span::of(&parser.previous);
+    consume(parser, TokenKind::Identifier, "expect variable name.")?;
+    let name = identifier(&parser.previous);
+
+    let initializer = if matches(parser, TokenKind::Equal) {
+        Some(expression(parser)?)
+    } else {
+        None
+    };
+
+    consume(
+        parser,
+        TokenKind::Semicolon,
+        "expect ';' after variable declaration.",
+    )?;
+
+    Ok(Stmt {
+        kind: StmtKind::Var { name, initializer },
+        span: start.to(Span::of(&parser.previous)),
+    })
+}
+
+fn print_statement(parser: &mut Parser) -> Result<Stmt, CompilerError> {
+    let start = Span::of(&parser.previous);
+    let expr = expression(parser)?;
+    consume(parser, TokenKind::Semicolon, "expect ';' after value.")?;
+
+    Ok(Stmt {
+        kind: StmtKind::Print(expr),
+        span: start.to(Span::of(&parser.previous)),
+    })
+}
+
+fn expression_statement(parser: &mut Parser) -> Result<Stmt, CompilerError> {
+    let expr = expression(parser)?;
+    consume(parser, TokenKind::Semicolon, "expect ';' after expression.")?;
+
+    Ok(Stmt {
+        span: expr.span.to(Span::of(&parser.previous)),
+        kind: StmtKind::Expression(expr),
+    })
+}
+
+fn if_statement(parser: &mut Parser) -> Result<Stmt, CompilerError> {
+    let start = Span::of(&parser.previous);
+    consume(parser, TokenKind::LeftParen, "expect '(' after 'if'.")?;
+    let condition = expression(parser)?;
+    consume(parser, TokenKind::RightParen, "expect ')' after condition.")?;
+
+    let then_branch = Box::new(statement(parser)?);
+    let else_branch = if matches(parser, TokenKind::Else) {
+        Some(Box::new(statement(parser)?))
+    } else {
+        None
+    };
+
+    Ok(Stmt {
+        span: start.to(Span::of(&parser.previous)),
+        kind: StmtKind::If {
+            condition,
+            then_branch,
+            else_branch,
+        },
+    })
+}
+
+fn while_statement(parser: &mut Parser) -> Result<Stmt, CompilerError> {
+    let start = Span::of(&parser.previous);
+    consume(parser, TokenKind::LeftParen, "expect '(' after 'while'.")?;
+    let condition = expression(parser)?;
+    consume(parser, TokenKind::RightParen, "expect ')' after condition.")?;
+    let body = Box::new(statement(parser)?);
+
+    Ok(Stmt {
+        span: start.to(Span::of(&parser.previous)),
+        kind: StmtKind::While { condition, body },
+    })
+}
+
+fn block(parser: &mut Parser) -> Result<Stmt, CompilerError> {
+    let start = Span::of(&parser.previous);
+    let mut statements = Vec::new();
+    while !check(parser, TokenKind::RightBrace) && !check(parser, TokenKind::Eof) {
+        statements.extend(declaration(parser));
+    }
+
+    consume(parser, TokenKind::RightBrace, "expect '}' after block.")?;
+
+    Ok(Stmt {
+        kind: StmtKind::Block(statements),
+        span: start.to(Span::of(&parser.previous)),
+    })
+}
+
+fn expression(parser: &mut Parser) -> Result<Expr, CompilerError> {
+    parse_precedence(parser, Precedence::Assignment)
+}
+
+fn parse_precedence(parser: &mut Parser, precedence: Precedence) -> Result<Expr, CompilerError> {
+    advance(parser);
+
+    let can_assign = precedence <= Precedence::Assignment;
+    let Some(prefix_fn) = get_parse_rule(parser.previous.kind).prefix() else {
+        return Err(CompilerError::new(
+            "expect expression.",
+            parser.previous.line,
+        ));
+    };
+    let mut expr = prefix_fn(parser, can_assign)?;
+
+    while precedence <= get_parse_rule(parser.current.kind).precedence() {
+        advance(parser);
+
+        if let Some(infix_fn) = get_parse_rule(parser.previous.kind).infix() {
+            expr = infix_fn(parser, expr)?;
+        }
+    }
+
+    if can_assign && matches(parser, TokenKind::Equal) {
+        return Err(CompilerError::new(
+            "invalid assignment target.",
+            parser.current.line,
+        ));
+    }
+
+    Ok(expr)
+}
+
+fn grouping(parser: &mut Parser, _can_assign: bool) -> Result<Expr, CompilerError> {
+    let start = Span::of(&parser.previous);
+    let inner = expression(parser)?;
+    consume(
+        parser,
+        TokenKind::RightParen,
+        "expect ')' after expression.",
+    )?;
+
+    Ok(Expr {
+        kind: ExprKind::Grouping(Box::new(inner)),
+        span: start.to(Span::of(&parser.previous)),
+    })
+}
+
+fn unary(parser: &mut Parser, _can_assign: bool) -> Result<Expr, CompilerError> {
+    let operator = operator(&parser.previous);
+    let operand = parse_precedence(parser, Precedence::Unary)?;
+
+    Ok(Expr {
+        span: operator.span.to(operand.span),
+        kind: ExprKind::Unary {
+            operator,
+            operand: Box::new(operand),
+        },
+    })
+}
+
+fn binary(parser: &mut Parser, left: Expr) -> Result<Expr, CompilerError> {
+    let operator = operator(&parser.previous);
+    let rule = get_parse_rule(operator.kind);
+    let right = parse_precedence(parser, rule.precedence().higher())?;
+
+    Ok(Expr {
+        span: left.span.to(right.span),
+        kind: ExprKind::Binary {
+            left: Box::new(left),
+            operator,
+            right: Box::new(right),
+        },
+    })
+}
+
+/// `and` and `or` parse their right operand at their own precedence, so they group to the right.
+fn logical(parser: &mut Parser, left: Expr) -> Result<Expr, CompilerError> {
+    let operator = operator(&parser.previous);
+    let rule = get_parse_rule(operator.kind);
+    let right = parse_precedence(parser, rule.precedence())?;
+
+    Ok(Expr {
+        span: left.span.to(right.span),
+        kind: ExprKind::Logical {
+            left: Box::new(left),
+            operator,
+            right: Box::new(right),
+        },
+    })
+}
+
+fn number(parser: &mut Parser, _can_assign: bool) -> Result<Expr, CompilerError> {
+    let number = f64::from_str(parser.previous.lexeme()).unwrap();
+    Ok(literal_expr(&parser.previous, Literal::Number(number)))
+}
+
+fn string(parser: &mut Parser, _can_assign: bool) -> Result<Expr, CompilerError> {
+    let lexeme = parser.previous.lexeme();
+    let chars = &lexeme[1..lexeme.len() - 1];
+    Ok(literal_expr(
+        &parser.previous,
+        Literal::String(chars.into()),
+    ))
+}
+
+fn literal(parser: &mut Parser, _can_assign: bool) -> Result<Expr, CompilerError> {
+    let literal = match parser.previous.kind {
+        TokenKind::False => Literal::Boolean(false),
+        TokenKind::Nil => Literal::Nil,
+        TokenKind::True => Literal::Boolean(true),
+        _ => unreachable!(),
+    };
+
+    Ok(literal_expr(&parser.previous, literal))
+}
+
+fn variable(parser: &mut Parser, can_assign: bool) -> Result<Expr, CompilerError> {
+    let name = identifier(&parser.previous);
+
+    if can_assign && matches(parser, TokenKind::Equal) {
+        let value = expression(parser)?;
+        return Ok(Expr {
+            span: name.span.to(value.span),
+            kind: ExprKind::Assign {
+                name,
+                value: Box::new(value),
+            },
+        });
+    }
+
+    Ok(Expr {
+        span: name.span,
+        kind: ExprKind::Variable(name),
+    })
+}
+
+fn literal_expr(token: &Token, literal: Literal) -> Expr {
+    Expr {
+        kind: ExprKind::Literal(literal),
+        span: Span::of(token),
+    }
+}
+
+fn identifier(token: &Token) -> Identifier {
+    Identifier {
+        name: token.lexeme().to_string(),
+        span: Span::of(token),
+    }
+}
+
+fn operator(token: &Token) -> Operator {
+    Operator {
+        kind: token.kind,
+        span: Span::of(token),
+    }
+}
+
+/// Moves to the next token, reporting and skipping the ones the scanner could not make sense of.
+fn advance(parser: &mut Parser) {
+    parser.previous = parser.current;
+    loop {
+        match parser.scanner.scan_token() {
+            Ok(token) if token.kind == TokenKind::Error => {
+                let msg = format!("unexpected character '{}'.", token.lexeme());
+                report(parser, &msg, token.line);
+            }
+            Ok(token) if token.kind == TokenKind::Comment => {}
+            Ok(token) => {
+                parser.current = token;
+                return;
+            }
+            Err(error) => report(parser, error.msg(), error.line()),
+        }
+    }
+}
+
+fn matches(parser: &mut Parser, token_kind: TokenKind) -> bool {
+    if !check(parser, token_kind) {
+        return false;
+    }
+
+    advance(parser);
+    true
+}
+
+fn check(parser: &Parser, token_kind: TokenKind) -> bool {
+    parser.current.kind == token_kind
+}
+
+fn consume(
+    parser: &mut Parser,
+    token_kind: TokenKind,
+    error_msg: &str,
+) -> Result<(), CompilerError> {
+    if parser.current.kind == token_kind {
+        advance(parser);
+        return Ok(());
+    }
+
+    Err(CompilerError::new(error_msg, parser.current.line))
+}
+
+fn get_parse_rule(token_kind: TokenKind) -> ParseRule {
+    assert_ne!(token_kind, TokenKind::Dummy);
+
+    match token_kind {
+        TokenKind::LeftParen => ParseRule(Some(grouping), None, Precedence::None),
+        TokenKind::RightParen => ParseRule(None, None, Precedence::None),
+        TokenKind::LeftBrace => ParseRule(None, None, Precedence::None),
+        TokenKind::RightBrace => ParseRule(None, None, Precedence::None),
+        TokenKind::Comma => ParseRule(None, None, Precedence::None),
+        TokenKind::Dot => ParseRule(None, None, Precedence::None),
+        TokenKind::Minus => ParseRule(Some(unary), Some(binary), Precedence::Term),
+        TokenKind::Plus => ParseRule(None, Some(binary), Precedence::Term),
+        TokenKind::Semicolon => ParseRule(None, None, Precedence::None),
+        TokenKind::Slash => ParseRule(None, Some(binary), Precedence::Factor),
+        TokenKind::Star => ParseRule(None, Some(binary), Precedence::Factor),
+        TokenKind::Bang => ParseRule(Some(unary), None, Precedence::None),
+        TokenKind::BangEqual => ParseRule(None, Some(binary), Precedence::Equality),
+        TokenKind::Equal => ParseRule(None, None, Precedence::None),
+        TokenKind::EqualEqual => ParseRule(None, Some(binary), Precedence::Equality),
+        TokenKind::Greater => ParseRule(None, Some(binary), Precedence::Comparison),
+        TokenKind::GreaterEqual => ParseRule(None, Some(binary), Precedence::Comparison),
+        TokenKind::Less => ParseRule(None, Some(binary), Precedence::Comparison),
+        TokenKind::LessEqual => ParseRule(None, Some(binary), Precedence::Comparison),
+        TokenKind::Identifier => ParseRule(Some(variable), None, Precedence::None),
+        TokenKind::String => ParseRule(Some(string), None, Precedence::None),
+        TokenKind::Number => ParseRule(Some(number), None, Precedence::None),
+        TokenKind::And => ParseRule(None, Some(logical), Precedence::And),
+        TokenKind::Class => ParseRule(None, None, Precedence::None),
+        TokenKind::Else => ParseRule(None, None, Precedence::None),
+        TokenKind::False => ParseRule(Some(literal), None, Precedence::None),
+        TokenKind::For => ParseRule(None, None, Precedence::None),
+        TokenKind::Fun => ParseRule(None, None, Precedence::None),
+        TokenKind::If => ParseRule(None, None, Precedence::None),
+        TokenKind::Nil => ParseRule(Some(literal), None, Precedence::None),
+        TokenKind::Or => ParseRule(None, Some(logical), Precedence::Or),
+        TokenKind::Print => ParseRule(None, None, Precedence::None),
+        TokenKind::Return => ParseRule(None, None, Precedence::None),
+        TokenKind::Super => ParseRule(None, None, Precedence::None),
+        TokenKind::This => ParseRule(None, None, Precedence::None),
+        TokenKind::True => ParseRule(Some(literal), None, Precedence::None),
+        TokenKind::Var => ParseRule(None, None, Precedence::None),
+        TokenKind::While => ParseRule(None, None, Precedence::None),
+        TokenKind::Comment => ParseRule(None, None, Precedence::None),
+        TokenKind::Error => ParseRule(None, None, Precedence::None),
+        TokenKind::Eof => ParseRule(None, None, Precedence::None),
+        TokenKind::Dummy => ParseRule(None, None, Precedence::None),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ast;
+
+    fn parse(source: &str) -> Program {
+        Parser::new(source).parse().unwrap()
+    }
+
+    #[test]
+    fn builds_expressions_with_precedence() {
+        let program = parse("a = 1 + 2 * -b or c and d;");
+
+        assert_eq!(
+            "Program [1:0..26]\n\
+             \x20 Expression [1:0..26]\n\
+             \x20   Assign a [1:0..25]\n\
+             \x20     Logical or [1:4..25]\n\
+             \x20       Binary + [1:4..14]\n\
+             \x20         Literal 1 [1:4..5]\n\
+             \x20         Binary * [1:8..14]\n\
+             \x20           Literal 2 [1:8..9]\n\
+             \x20           Unary - [1:12..14]\n\
+             \x20             Variable b [1:13..14]\n\
+             \x20       Logical and [1:18..25]\n\
+             \x20         Variable c [1:18..19]\n\
+             \x20         Variable d [1:24..25]\n",
+            ast::dump(&program)
+        );
+    }
+
+    #[test]
+    fn builds_statements_with_spans() {
+        let program = parse("var a;\nif (a) {\n  print a;\n} else while (false) a = 1;\n");
+        let [var, if_stmt] = &program.statements[..] else {
+            panic!("{:?}", program);
+        };
+
+        assert_eq!(
+            Span {
+                start: 0,
+                end: 6,
+                line: 1,
+                end_line: 1
+            },
+            var.span
+        );
+        let StmtKind::If {
+            then_branch,
+            else_branch: Some(else_branch),
+            ..
+        } = &if_stmt.kind
+        else {
+            panic!("{:?}", if_stmt);
+        };
+        assert!(matches!(then_branch.kind, StmtKind::Block(_)));
+        assert!(matches!(else_branch.kind, StmtKind::While { .. }));
+        assert_eq!((2, 4), (if_stmt.span.line, if_stmt.span.end_line));
+        assert_eq!(5, program.span.end_line);
+    }
+
+    #[test]
+    fn reports_an_error_per_statement() {
+        let errors = Parser::new("print 1;\nprint (2;\nprint ;\nvar = 3; print 4;")
+            .parse()
+            .unwrap_err();
+        let errors: Vec<_> = errors
+            .iter()
+            .map(|error| (error.msg().as_str(), error.line()))
+            .collect();
+
+        assert_eq!(
+            vec![
+                ("expect ')' after expression.", 2),
+                ("expect expression.", 3),
+                ("expect variable name.", 4),
+            ],
+            errors
+        );
+    }
+
+    #[test]
+    fn reports_unexpected_characters() {
+        let errors = Parser::new("print 1 @ 2;\nprint 2 # 3;\nprint ( ;\nprint 4 % %;")
+            .parse()
+            .unwrap_err();
+        let errors: Vec<_> = errors
+            .iter()
+            .map(|error| (error.msg().as_str(), error.line()))
+            .collect();
+
+        // The rest of a statement after a stray character is not reported on.
+        assert_eq!(
+            vec![
+                ("unexpected character '@'.", 1),
+                ("unexpected character '#'.", 2),
+                ("expect expression.", 3),
+                ("unexpected character '%'.", 4),
+            ],
+            errors
+        );
+    }
+}
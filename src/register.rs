@@ -0,0 +1,814 @@
+use std::fmt::{self, Display, Write};
+
+use rlox_common::Array;
+
+use crate::ast::{Expr, ExprKind, Identifier, Literal, Program, Stmt, StmtKind};
+use crate::bytecode::{InlineCache, LineTable};
+use crate::codegen::name_constant;
+use crate::compiler::CompilerError;
+use crate::heap::Heap;
+use crate::object::Handle;
+use crate::scanner::TokenKind;
+use crate::string::String;
+use crate::value::Value;
+
+/// A frame-relative register index.
+pub(crate) type Register = u8;
+
+/// A three-address instruction for the register backend.
+///
+/// Jump targets are absolute instruction indices.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) enum Instruction {
+    LoadConstant {
+        dst: Register,
+        constant: u8,
+    },
+    LoadNil {
+        dst: Register,
+    },
+    LoadTrue {
+        dst: Register,
+    },
+    LoadFalse {
+        dst: Register,
+    },
+    Move {
+        dst: Register,
+        src: Register,
+    },
+    Negate {
+        dst: Register,
+        src: Register,
+    },
+    Not {
+        dst: Register,
+        src: Register,
+    },
+    Add {
+        dst: Register,
+        left: Register,
+        right: Register,
+    },
+    Substract {
+        dst: Register,
+        left: Register,
+        right: Register,
+    },
+    Multiply {
+        dst: Register,
+        left: Register,
+        right: Register,
+    },
+    Divide {
+        dst: Register,
+        left: Register,
+        right: Register,
+    },
+    Equal {
+        dst: Register,
+        left: Register,
+        right: Register,
+    },
+    Greater {
+        dst: Register,
+        left: Register,
+        right: Register,
+    },
+    GreaterEqual {
+        dst: Register,
+        left: Register,
+        right: Register,
+    },
+    Less {
+        dst: Register,
+        left: Register,
+        right: Register,
+    },
+    LessEqual {
+        dst: Register,
+        left: Register,
+        right: Register,
+    },
+    Print {
+        src: Register,
+    },
+    /// Discards a value, remembering it as the last value produced by the script.
+    Discard {
+        src: Register,
+    },
+    DefineGlobal {
+        name: u8,
+        src: Register,
+    },
+    /// `cache` indexes the inline caches of the `RegisterChunk`.
+    GetGlobal {
+        dst: Register,
+        name: u8,
+        cache: u8,
+    },
+    SetGlobal {
+        name: u8,
+        src: Register,
+        cache: u8,
+    },
+    JumpIfFalse {
+        cond: Register,
+        target: usize,
+    },
+    JumpIfTrue {
+        cond: Register,
+        target: usize,
+    },
+    Jump {
+        target: usize,
+    },
+    Loop {
+        target: usize,
+    },
+    Return,
+}
+
+impl Display for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            Instruction::LoadConstant { dst, constant } => {
+                write!(f, "{:<16} r{} k{}", "R_CONSTANT", dst, constant)
+            }
+            Instruction::LoadNil { dst } => write!(f, "{:<16} r{}", "R_NIL", dst),
+            Instruction::LoadTrue { dst } => write!(f, "{:<16} r{}", "R_TRUE", dst),
+            Instruction::LoadFalse { dst } => write!(f, "{:<16} r{}", "R_FALSE", dst),
+            Instruction::Move { dst, src } => write!(f, "{:<16} r{} r{}", "R_MOVE", dst, src),
+            Instruction::Negate { dst, src } => write!(f, "{:<16} r{} r{}", "R_NEGATE", dst, src),
+            Instruction::Not { dst, src } => write!(f, "{:<16} r{} r{}", "R_NOT", dst, src),
+            Instruction::Add { dst, left, right } => binary(f, "R_ADD", dst, left, right),
+            Instruction::Substract { dst, left, right } => {
+                binary(f, "R_SUBSTRACT", dst, left, right)
+            }
+            Instruction::Multiply { dst, left, right } => binary(f, "R_MULTIPLY", dst, left, right),
+            Instruction::Divide { dst, left, right } => binary(f, "R_DIVIDE", dst, left, right),
+            Instruction::Equal { dst, left, right } => binary(f, "R_EQUAL", dst, left, right),
+            Instruction::Greater { dst, left, right } => binary(f, "R_GREATER", dst, left, right),
+            Instruction::GreaterEqual { dst, left, right } => {
+                binary(f, "R_GREATER_EQUAL", dst, left, right)
+            }
+            Instruction::Less { dst, left, right } => binary(f, "R_LESS", dst, left, right),
+            Instruction::LessEqual { dst, left, right } => {
+                binary(f, "R_LESS_EQUAL", dst, left, right)
+            }
+            Instruction::Print { src } => write!(f, "{:<16} r{}", "R_PRINT", src),
+            Instruction::Discard { src } => write!(f, "{:<16} r{}", "R_DISCARD", src),
+            Instruction::DefineGlobal { name, src } => {
+                write!(f, "{:<16} k{} r{}", "R_DEFINE_GLOBAL", name, src)
+            }
+            Instruction::GetGlobal { dst, name, cache } => {
+                write!(f, "{:<16} r{} k{} c{}", "R_GET_GLOBAL", dst, name, cache)
+            }
+            Instruction::SetGlobal { name, src, cache } => {
+                write!(f, "{:<16} k{} r{} c{}", "R_SET_GLOBAL", name, src, cache)
+            }
+            Instruction::JumpIfFalse { cond, target } => {
+                write!(f, "{:<16} r{} -> {}", "R_JUMP_IF_FALSE", cond, target)
+            }
+            Instruction::JumpIfTrue { cond, target } => {
+                write!(f, "{:<16} r{} -> {}", "R_JUMP_IF_TRUE", cond, target)
+            }
+            Instruction::Jump { target } => write!(f, "{:<16} -> {}", "R_JUMP", target),
+            Instruction::Loop { target } => write!(f, "{:<16} -> {}", "R_LOOP", target),
+            Instruction::Return => write!(f, "R_RETURN"),
+        }
+    }
+}
+
+fn binary(
+    f: &mut fmt::Formatter<'_>,
+    name: &str,
+    dst: Register,
+    left: Register,
+    right: Register,
+) -> fmt::Result {
+    write!(f, "{:<16} r{} r{} r{}", name, dst, left, right)
+}
+
+/// Code for the register backend, with its own constants and inline caches.
+#[derive(Debug)]
+pub(crate) struct RegisterChunk {
+    code: Array<Instruction>,
+    lines: LineTable,
+    constants: Array<Value>,
+    caches: Array<InlineCache>,
+    frame_size: usize,
+}
+
+impl RegisterChunk {
+    pub fn code(&self) -> &Array<Instruction> {
+        &self.code
+    }
+
+    pub fn constants(&self) -> &Array<Value> {
+        &self.constants
+    }
+
+    pub fn caches(&self) -> &Array<InlineCache> {
+        &self.caches
+    }
+
+    pub fn lines(&self) -> &LineTable {
+        &self.lines
+    }
+
+    /// The source line of the instruction at `index`, which must be inside the code.
+    pub fn line_for_offset(&self, index: usize) -> usize {
+        self.lines
+            .line_for_offset(index)
+            .expect("every instruction has a line")
+    }
+
+    /// Number of registers the code uses.
+    pub fn frame_size(&self) -> usize {
+        self.frame_size
+    }
+
+    pub fn disassemble(&self, name: &str) -> std::string::String {
+        let mut output = std::string::String::new();
+
+        writeln!(output, "== {} ({} registers) ==", name, self.frame_size).unwrap();
+        for (index, (instruction, line)) in self.code.iter().zip(self.lines.iter()).enumerate() {
+            if index > 0 && self.lines.line_for_offset(index - 1) == Some(line) {
+                write!(output, "{:04}    | ", index).unwrap();
+            } else {
+                write!(output, "{:04} {:04} ", index, line).unwrap();
+            }
+            writeln!(output, "{}", instruction).unwrap();
+        }
+
+        output
+    }
+}
+
+/// A local variable, living in the register numbered as its slot.
+#[derive(Debug)]
+struct Local {
+    name: std::string::String,
+    /// Scope depth of the declaration, `None` until its initializer has been generated.
+    depth: Option<usize>,
+}
+
+/// Generates register code for a parsed program, the counterpart of `Codegen` for the register
+/// backend.
+///
+/// Locals live in the registers numbered as their slots, and the temporaries of a statement are
+/// allocated above them until the statement ends. Operators read locals straight from their
+/// registers instead of copying them first.
+///
+/// The code leaves the same result as the stack code: whatever the stack backend would pop last
+/// is discarded last here too.
+pub(crate) struct RegisterCodegen<'h> {
+    output: RegisterChunk,
+    heap: &'h mut Heap,
+    locals: Vec<Local>,
+    scope_depth: usize,
+    /// The first register holding neither a local nor a temporary.
+    next: usize,
+}
+
+impl<'h> RegisterCodegen<'h> {
+    pub fn new(heap: &'h mut Heap) -> Self {
+        Self {
+            output: RegisterChunk {
+                code: Array::new(),
+                lines: LineTable::new(),
+                constants: Array::new(),
+                caches: Array::new(),
+                frame_size: 0,
+            },
+            heap,
+            locals: Vec::new(),
+            scope_depth: 0,
+            next: 0,
+        }
+    }
+
+    pub fn generate(mut self, program: &Program) -> Result<RegisterChunk, CompilerError> {
+        for statement in &program.statements {
+            self.statement(statement)?;
+        }
+        self.emit(Instruction::Return, program.span.end_line);
+
+        Ok(self.output)
+    }
+
+    fn statement(&mut self, stmt: &Stmt) -> Result<(), CompilerError> {
+        let line = stmt.span.end_line;
+        let mark = self.next;
+
+        match &stmt.kind {
+            StmtKind::Print(expr) => {
+                let src = self.operand(expr)?;
+                self.emit(Instruction::Print { src }, line);
+            }
+            StmtKind::Expression(expr) => {
+                let src = self.operand(expr)?;
+                self.emit(Instruction::Discard { src }, line);
+            }
+            StmtKind::Var { name, initializer } if self.scope_depth == 0 => {
+                let global = self.string_constant(&name.name, name.span.line)?;
+                let src = match initializer {
+                    Some(initializer) => self.operand(initializer)?,
+                    None => {
+                        let dst = self.allocate(name.span.line)?;
+                        self.emit(Instruction::LoadNil { dst }, name.span.line);
+                        dst
+                    }
+                };
+                self.emit(Instruction::DefineGlobal { name: global, src }, line);
+            }
+            StmtKind::Var { name, initializer } => {
+                self.declare_local(name)?;
+                let dst = self.allocate(name.span.line)?;
+                match initializer {
+                    Some(initializer) => self.expression(initializer, dst)?,
+                    None => self.emit(Instruction::LoadNil { dst }, name.span.line),
+                }
+                self.locals.last_mut().unwrap().depth = Some(self.scope_depth);
+
+                // The register stays the local's until the end of its scope.
+                return Ok(());
+            }
+            StmtKind::Block(statements) => {
+                self.scope_depth += 1;
+                for statement in statements {
+                    self.statement(statement)?;
+                }
+                self.end_scope(line);
+            }
+            StmtKind::If {
+                condition,
+                then_branch,
+                else_branch,
+            } => {
+                let condition_line = condition.span.end_line;
+                let cond = self.operand(condition)?;
+                let then_jump =
+                    self.emit_jump(Instruction::JumpIfFalse { cond, target: 0 }, condition_line);
+                self.emit(Instruction::Discard { src: cond }, condition_line);
+                self.next = mark;
+                self.statement(then_branch)?;
+
+                let then_line = then_branch.span.end_line;
+                let else_jump = self.emit_jump(Instruction::Jump { target: 0 }, then_line);
+                self.patch_jump(then_jump);
+                // Only reached straight from the jump, so `cond` still holds the condition.
+                self.emit(Instruction::Discard { src: cond }, then_line);
+
+                if let Some(else_branch) = else_branch {
+                    self.statement(else_branch)?;
+                }
+                self.patch_jump(else_jump);
+            }
+            StmtKind::While { condition, body } => {
+                let loop_start = self.output.code.len();
+                let condition_line = condition.span.end_line;
+                let cond = self.operand(condition)?;
+                let exit_jump =
+                    self.emit_jump(Instruction::JumpIfFalse { cond, target: 0 }, condition_line);
+                self.emit(Instruction::Discard { src: cond }, condition_line);
+                self.next = mark;
+                self.statement(body)?;
+
+                let body_line = body.span.end_line;
+                self.emit(Instruction::Loop { target: loop_start }, body_line);
+                self.patch_jump(exit_jump);
+                self.emit(Instruction::Discard { src: cond }, body_line);
+            }
+        }
+
+        self.next = mark;
+        Ok(())
+    }
+
+    /// Generates `expr`, leaving its value in `dst`.
+    fn expression(&mut self, expr: &Expr, dst: Register) -> Result<(), CompilerError> {
+        let line = expr.span.end_line;
+
+        match &expr.kind {
+            ExprKind::Literal(literal) => {
+                let value = match literal {
+                    Literal::Nil => Value::Nil,
+                    Literal::Boolean(boolean) => Value::from(*boolean),
+                    Literal::Number(number) => Value::Number(*number),
+                    Literal::String(string) => Value::String(self.allocate_string(string, line)?),
+                };
+                self.emit_load(value, dst, line)?;
+            }
+            ExprKind::Variable(name) => match self.resolve_local(name)? {
+                Some(src) => self.emit(Instruction::Move { dst, src }, line),
+                None => {
+                    let (name, cache) = self.global(name)?;
+                    self.emit(Instruction::GetGlobal { dst, name, cache }, line);
+                }
+            },
+            ExprKind::Assign { name, value } => match self.resolve_local(name)? {
+                Some(slot) => {
+                    self.expression(value, dst)?;
+                    self.emit(
+                        Instruction::Move {
+                            dst: slot,
+                            src: dst,
+                        },
+                        line,
+                    );
+                }
+                None => {
+                    let (name, cache) = self.global(name)?;
+                    self.expression(value, dst)?;
+                    self.emit(
+                        Instruction::SetGlobal {
+                            name,
+                            src: dst,
+                            cache,
+                        },
+                        line,
+                    );
+                }
+            },
+            ExprKind::Unary { operator, operand } => {
+                let mark = self.next;
+                let src = self.operand(operand)?;
+                self.next = mark;
+
+                match operator.kind {
+                    TokenKind::Bang => self.emit(Instruction::Not { dst, src }, line),
+                    TokenKind::Minus => self.emit(Instruction::Negate { dst, src }, line),
+                    _ => unreachable!(),
+                }
+            }
+            ExprKind::Binary {
+                left,
+                operator,
+                right,
+            } => {
+                let mark = self.next;
+                // A local read in place would see an assignment made by the right operand.
+                let left = if assigns(right) {
+                    self.temporary(left)?
+                } else {
+                    self.operand(left)?
+                };
+                let right = self.operand(right)?;
+                self.next = mark;
+
+                let instruction = match operator.kind {
+                    TokenKind::BangEqual | TokenKind::EqualEqual => {
+                        Instruction::Equal { dst, left, right }
+                    }
+                    TokenKind::Greater => Instruction::Greater { dst, left, right },
+                    TokenKind::GreaterEqual => Instruction::GreaterEqual { dst, left, right },
+                    TokenKind::Less => Instruction::Less { dst, left, right },
+                    TokenKind::LessEqual => Instruction::LessEqual { dst, left, right },
+                    TokenKind::Plus => Instruction::Add { dst, left, right },
+                    TokenKind::Minus => Instruction::Substract { dst, left, right },
+                    TokenKind::Star => Instruction::Multiply { dst, left, right },
+                    TokenKind::Slash => Instruction::Divide { dst, left, right },
+                    _ => unreachable!(),
+                };
+                self.emit(instruction, line);
+
+                if operator.kind == TokenKind::BangEqual {
+                    self.emit(Instruction::Not { dst, src: dst }, line);
+                }
+            }
+            ExprKind::Logical {
+                left,
+                operator,
+                right,
+            } => {
+                let operator_line = operator.span.line;
+                self.expression(left, dst)?;
+
+                let jump = match operator.kind {
+                    TokenKind::And => Instruction::JumpIfFalse {
+                        cond: dst,
+                        target: 0,
+                    },
+                    _ => Instruction::JumpIfTrue {
+                        cond: dst,
+                        target: 0,
+                    },
+                };
+                let end_jump = self.emit_jump(jump, operator_line);
+                self.expression(right, dst)?;
+                self.patch_jump(end_jump);
+            }
+            ExprKind::Grouping(inner) => self.expression(inner, dst)?,
+        }
+
+        Ok(())
+    }
+
+    /// Returns the register holding the value of `expr`: the register of the local for a local
+    /// variable, a new temporary otherwise.
+    fn operand(&mut self, expr: &Expr) -> Result<Register, CompilerError> {
+        let mut inner = expr;
+        while let ExprKind::Grouping(grouped) = &inner.kind {
+            inner = grouped;
+        }
+
+        if let ExprKind::Variable(name) = &inner.kind {
+            if let Some(slot) = self.resolve_local(name)? {
+                return Ok(slot);
+            }
+        }
+
+        self.temporary(expr)
+    }
+
+    /// Generates `expr` into a new temporary.
+    fn temporary(&mut self, expr: &Expr) -> Result<Register, CompilerError> {
+        let dst = self.allocate(expr.span.line)?;
+        self.expression(expr, dst)?;
+        Ok(dst)
+    }
+
+    fn allocate(&mut self, line: usize) -> Result<Register, CompilerError> {
+        if self.next > Register::MAX as usize {
+            return Err(CompilerError::new(
+                "too many values in flight for the register backend.",
+                line,
+            ));
+        }
+
+        let register = self.next as Register;
+        self.next += 1;
+        self.output.frame_size = self.output.frame_size.max(self.next);
+
+        Ok(register)
+    }
+
+    fn declare_local(&mut self, name: &Identifier) -> Result<(), CompilerError> {
+        for local in self.locals.iter().rev() {
+            if local.depth.is_some_and(|depth| depth < self.scope_depth) {
+                break;
+            }
+
+            if local.name == name.name {
+                return Err(CompilerError::new(
+                    "already a variable with this name in this scope.",
+                    name.span.line,
+                ));
+            }
+        }
+
+        self.locals.push(Local {
+            name: name.name.clone(),
+            depth: None,
+        });
+        Ok(())
+    }
+
+    fn end_scope(&mut self, line: usize) {
+        self.scope_depth -= 1;
+
+        let first = self
+            .locals
+            .iter()
+            .position(|local| local.depth.is_some_and(|depth| depth > self.scope_depth));
+        if let Some(first) = first {
+            // The stack backend pops the locals of the scope, the first one last.
+            self.emit(
+                Instruction::Discard {
+                    src: first as Register,
+                },
+                line,
+            );
+            self.locals.truncate(first);
+        }
+        self.next = self.locals.len();
+    }
+
+    fn resolve_local(&self, name: &Identifier) -> Result<Option<Register>, CompilerError> {
+        for (slot, local) in self.locals.iter().enumerate().rev() {
+            if local.name == name.name {
+                if local.depth.is_none() {
+                    return Err(CompilerError::new(
+                        "can't read local variable in its own initializer.",
+                        name.span.line,
+                    ));
+                }
+                return Ok(Some(slot as Register));
+            }
+        }
+
+        Ok(None)
+    }
+
+    /// The constant naming the global `name` and a fresh inline cache for accessing it.
+    ///
+    /// As in `Codegen`, accesses to the same global share its name constant.
+    fn global(&mut self, name: &Identifier) -> Result<(u8, u8), CompilerError> {
+        let line = name.span.line;
+        let global = match name_constant(&self.output.constants, &name.name) {
+            Some(global) => global,
+            None => self.string_constant(&name.name, line)?,
+        };
+
+        self.output.caches.write(InlineCache::default());
+        let cache = self.output.caches.len() - 1;
+        if cache > u8::MAX as usize {
+            return Err(CompilerError::new(
+                "too many global accesses in one chunk.",
+                line,
+            ));
+        }
+
+        Ok((global, cache as u8))
+    }
+
+    fn string_constant(&mut self, chars: &str, line: usize) -> Result<u8, CompilerError> {
+        let string = self.allocate_string(chars, line)?;
+        self.make_constant(Value::String(string), line)
+    }
+
+    fn allocate_string(
+        &mut self,
+        chars: &str,
+        line: usize,
+    ) -> Result<Handle<String>, CompilerError> {
+        self.heap
+            .allocate_string(String::new(chars))
+            .map_err(|error| CompilerError::new(&error.to_string(), line))
+    }
+
+    fn emit_load(&mut self, value: Value, dst: Register, line: usize) -> Result<(), CompilerError> {
+        let instruction = match value {
+            Value::Nil => Instruction::LoadNil { dst },
+            Value::Boolean(true) => Instruction::LoadTrue { dst },
+            Value::Boolean(false) => Instruction::LoadFalse { dst },
+            value => Instruction::LoadConstant {
+                dst,
+                constant: self.make_constant(value, line)?,
+            },
+        };
+        self.emit(instruction, line);
+        Ok(())
+    }
+
+    fn make_constant(&mut self, value: Value, line: usize) -> Result<u8, CompilerError> {
+        self.output.constants.write(value);
+        u8::try_from(self.output.constants.len() - 1)
+            .map_err(|_| CompilerError::new("too many constants in one chunk.", line))
+    }
+
+    /// Emits a jump to be patched, returning its index.
+    fn emit_jump(&mut self, instruction: Instruction, line: usize) -> usize {
+        self.emit(instruction, line);
+        self.output.code.len() - 1
+    }
+
+    /// Points the jump at `index` to the end of the code.
+    fn patch_jump(&mut self, index: usize) {
+        let end = self.output.code.len();
+        match &mut self.output.code[index] {
+            Instruction::JumpIfFalse { target, .. }
+            | Instruction::JumpIfTrue { target, .. }
+            | Instruction::Jump { target } => *target = end,
+            _ => unreachable!("internal error: patching a non-jump instruction."),
+        }
+    }
+
+    fn emit(&mut self, instruction: Instruction, line: usize) {
+        self.output.code.push(instruction);
+        self.output.lines.push(line);
+    }
+}
+
+/// Whether evaluating `expr` may assign a variable.
+fn assigns(expr: &Expr) -> bool {
+    match &expr.kind {
+        ExprKind::Assign { .. } => true,
+        ExprKind::Unary { operand, .. } => assigns(operand),
+        ExprKind::Binary { left, right, .. } | ExprKind::Logical { left, right, .. } => {
+            assigns(left) || assigns(right)
+        }
+        ExprKind::Grouping(inner) => assigns(inner),
+        ExprKind::Literal(_) | ExprKind::Variable(_) => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::Parser;
+
+    fn generate(source: &str) -> RegisterChunk {
+        let mut program = Parser::new(source).parse().unwrap();
+        crate::folder::fold(&mut program);
+        RegisterCodegen::new(&mut Heap::new())
+            .generate(&program)
+            .unwrap()
+    }
+
+    #[test]
+    fn operators_read_locals_in_place() {
+        let chunk = generate("{ var a = 1; var b = a + a; }");
+
+        assert!(chunk.code().iter().any(|i| *i
+            == Instruction::Add {
+                dst: 1,
+                left: 0,
+                right: 0
+            }));
+        assert!(!chunk
+            .code()
+            .iter()
+            .any(|i| matches!(i, Instruction::Move { .. })));
+        assert_eq!(2, chunk.frame_size());
+    }
+
+    #[test]
+    fn assignment_leaves_copies_alone() {
+        let chunk = generate("{ var a = 1; var b = a; a = 2; }");
+
+        // `b` gets its own copy of `a` before `a` is assigned.
+        let code: Vec<_> = chunk.code().iter().copied().collect();
+        let copy = code
+            .iter()
+            .position(|i| *i == Instruction::Move { dst: 1, src: 0 })
+            .unwrap();
+        let assign = code
+            .iter()
+            .position(|i| *i == Instruction::Move { dst: 0, src: 2 })
+            .unwrap();
+        assert!(copy < assign);
+    }
+
+    #[test]
+    fn operands_assigned_later_are_copied_first() {
+        let chunk = generate("{ var a = 1; a + (a = 2); }");
+
+        assert!(chunk
+            .code()
+            .iter()
+            .any(|i| *i == Instruction::Move { dst: 2, src: 0 }));
+        assert!(!chunk
+            .code()
+            .iter()
+            .any(|i| matches!(i, Instruction::Add { left: 0, .. })));
+    }
+
+    #[test]
+    fn folds_constants() {
+        let chunk = generate("print 1 + 2 * -3;");
+
+        assert_eq!(
+            &[
+                Instruction::LoadConstant {
+                    dst: 0,
+                    constant: 0
+                },
+                Instruction::Print { src: 0 },
+                Instruction::Return
+            ],
+            &chunk.code()[..]
+        );
+        assert_eq!(Value::from(-5.0), chunk.constants()[0]);
+    }
+
+    #[test]
+    fn jumps_target_register_instructions() {
+        let chunk = generate("var a = 0; while (a < 3) a = a + 1;");
+
+        for instruction in chunk.code().iter() {
+            if let Instruction::JumpIfFalse { target, .. } | Instruction::Loop { target } =
+                instruction
+            {
+                assert!(*target < chunk.code().len());
+            }
+        }
+        assert_eq!(Some(&Instruction::Return), chunk.code().iter().last());
+    }
+
+    #[test]
+    fn too_many_constants_error() {
+        let source: Vec<_> = (0..=256).map(|i| format!("print {};", i)).collect();
+        let program = Parser::new(&source.join("\n")).parse().unwrap();
+        let error = RegisterCodegen::new(&mut Heap::new())
+            .generate(&program)
+            .unwrap_err();
+
+        assert_eq!("too many constants in one chunk.", error.msg());
+        assert_eq!(257, error.line());
+    }
+
+    #[test]
+    fn too_many_global_accesses_error() {
+        let source = vec!["print a;"; 257];
+        let program = Parser::new(&source.join("\n")).parse().unwrap();
+        let error = RegisterCodegen::new(&mut Heap::new())
+            .generate(&program)
+            .unwrap_err();
+
+        assert_eq!("too many global accesses in one chunk.", error.msg());
+        assert_eq!(257, error.line());
+    }
+}
@@ -0,0 +1,513 @@
+//! Lints over the syntax tree.
+//!
+//! The resolver binds every use of a local to its declaration, like `Codegen` does, and reports
+//! code that is legal but most likely a mistake. Globals can be read by the host or by a later
+//! script, so only locals are checked.
+
+use std::{
+    collections::{HashMap, HashSet},
+    fmt::Display,
+    str::FromStr,
+};
+
+use crate::{
+    ast::{Expr, ExprKind, Identifier, Program, Stmt, StmtKind},
+    compiler::CompilerError,
+};
+
+/// A check the resolver runs.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Lint {
+    /// A local that is never read.
+    UnusedVariable,
+    /// A function parameter that is never read. Lox functions are not compiled yet, so this
+    /// cannot fire, but its level can already be set.
+    UnusedParameter,
+    /// A local with the same name as a local of an enclosing scope.
+    ShadowedVariable,
+    /// A value stored in a local that is overwritten or goes out of scope before being read.
+    UnusedAssignment,
+    /// A statement after a `return`. Lox `return` is not compiled yet, so like
+    /// `UnusedParameter` this cannot fire.
+    UnreachableCode,
+}
+
+impl Lint {
+    pub const ALL: [Lint; 5] = [
+        Lint::UnusedVariable,
+        Lint::UnusedParameter,
+        Lint::ShadowedVariable,
+        Lint::UnusedAssignment,
+        Lint::UnreachableCode,
+    ];
+
+    /// How the lint is named on the command line.
+    pub fn name(self) -> &'static str {
+        match self {
+            Lint::UnusedVariable => "unused-variable",
+            Lint::UnusedParameter => "unused-parameter",
+            Lint::ShadowedVariable => "shadowed-variable",
+            Lint::UnusedAssignment => "unused-assignment",
+            Lint::UnreachableCode => "unreachable-code",
+        }
+    }
+}
+
+impl Display for Lint {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+impl FromStr for Lint {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Lint::ALL
+            .into_iter()
+            .find(|lint| lint.name() == s)
+            .ok_or_else(|| {
+                let names: Vec<_> = Lint::ALL.iter().map(|lint| lint.name()).collect();
+                format!(
+                    "unknown lint '{}', expected one of {}.",
+                    s,
+                    names.join(", ")
+                )
+            })
+    }
+}
+
+/// What happens when a lint finds something.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum LintLevel {
+    /// Ignore it.
+    Allow,
+    /// Report a `Warning` and compile anyway.
+    #[default]
+    Warn,
+    /// Fail compilation with a `CompilerError`.
+    Deny,
+}
+
+/// The level of every lint. All lints warn by default.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Lints {
+    levels: [LintLevel; Lint::ALL.len()],
+}
+
+impl Lints {
+    pub fn level(&self, lint: Lint) -> LintLevel {
+        self.levels[lint as usize]
+    }
+
+    pub fn set(&mut self, lint: Lint, level: LintLevel) {
+        self.levels[lint as usize] = level;
+    }
+}
+
+/// Something a lint found in the source.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Warning {
+    lint: Lint,
+    msg: String,
+    line: usize,
+}
+
+impl Warning {
+    fn new(lint: Lint, msg: String, line: usize) -> Self {
+        Self { lint, msg, line }
+    }
+
+    pub fn lint(&self) -> Lint {
+        self.lint
+    }
+
+    pub fn msg(&self) -> &String {
+        &self.msg
+    }
+
+    pub fn line(&self) -> usize {
+        self.line
+    }
+}
+
+impl Display for Warning {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "[line: {}] warning: {} [{}]",
+            self.line, self.msg, self.lint
+        )
+    }
+}
+
+/// Runs the lints over `program`, keeping the warnings `lints` does not allow.
+///
+/// The first finding of a denied lint is returned as an error instead.
+pub(crate) fn check(program: &Program, lints: &Lints) -> Result<Vec<Warning>, CompilerError> {
+    let mut resolver = Resolver::default();
+    for statement in &program.statements {
+        resolver.statement(statement);
+    }
+    resolver.liveness(program);
+
+    let mut warnings = resolver.warnings;
+    warnings.sort_by_key(|warning| warning.line);
+    warnings.retain(|warning| lints.level(warning.lint) != LintLevel::Allow);
+
+    if let Some(denied) = warnings
+        .iter()
+        .find(|warning| lints.level(warning.lint) == LintLevel::Deny)
+    {
+        return Err(CompilerError::new(
+            &format!("{} [{}]", denied.msg, denied.lint),
+            denied.line,
+        ));
+    }
+
+    Ok(warnings)
+}
+
+/// A local variable declaration.
+struct Local {
+    name: String,
+    line: usize,
+    reads: usize,
+}
+
+#[derive(Default)]
+struct Resolver {
+    locals: Vec<Local>,
+    /// The locals in scope, innermost scope last. The global scope is not included.
+    scopes: Vec<Vec<usize>>,
+    /// The local each identifier refers to, by the offset of the identifier.
+    bindings: HashMap<usize, usize>,
+    /// Whether the liveness pass reports what it finds, which it only does once it has settled
+    /// on the values live around a loop.
+    reporting: bool,
+    warnings: Vec<Warning>,
+}
+
+impl Resolver {
+    fn statement(&mut self, stmt: &Stmt) {
+        match &stmt.kind {
+            StmtKind::Print(expr) | StmtKind::Expression(expr) => self.expression(expr),
+            StmtKind::Var { name, initializer } => {
+                if let Some(initializer) = initializer {
+                    self.expression(initializer);
+                }
+                self.declare(name);
+            }
+            StmtKind::Block(statements) => {
+                self.scopes.push(Vec::new());
+                for statement in statements {
+                    self.statement(statement);
+                }
+                self.end_scope();
+            }
+            StmtKind::If {
+                condition,
+                then_branch,
+                else_branch,
+            } => {
+                self.expression(condition);
+                self.statement(then_branch);
+                if let Some(else_branch) = else_branch {
+                    self.statement(else_branch);
+                }
+            }
+            StmtKind::While { condition, body } => {
+                self.expression(condition);
+                self.statement(body);
+            }
+        }
+    }
+
+    fn expression(&mut self, expr: &Expr) {
+        match &expr.kind {
+            ExprKind::Literal(_) => {}
+            ExprKind::Variable(name) => {
+                if let Some(local) = self.bind(name) {
+                    self.locals[local].reads += 1;
+                }
+            }
+            ExprKind::Assign { name, value } => {
+                self.bind(name);
+                self.expression(value);
+            }
+            ExprKind::Unary { operand, .. } => self.expression(operand),
+            ExprKind::Binary { left, right, .. } | ExprKind::Logical { left, right, .. } => {
+                self.expression(left);
+                self.expression(right);
+            }
+            ExprKind::Grouping(inner) => self.expression(inner),
+        }
+    }
+
+    fn declare(&mut self, name: &Identifier) {
+        let Some((_, enclosing)) = self.scopes.split_last() else {
+            return;
+        };
+
+        if let Some(&outer) = enclosing
+            .iter()
+            .rev()
+            .flatten()
+            .find(|&&local| self.locals[local].name == name.name)
+        {
+            self.warnings.push(Warning::new(
+                Lint::ShadowedVariable,
+                format!(
+                    "'{}' shadows the local declared on line {}.",
+                    name.name, self.locals[outer].line
+                ),
+                name.span.line,
+            ));
+        }
+
+        let local = self.locals.len();
+        self.locals.push(Local {
+            name: name.name.clone(),
+            line: name.span.line,
+            reads: 0,
+        });
+        self.bindings.insert(name.span.start, local);
+        self.scopes.last_mut().unwrap().push(local);
+    }
+
+    fn end_scope(&mut self) {
+        for local in self.scopes.pop().unwrap() {
+            let Local { name, line, reads } = &self.locals[local];
+            if *reads == 0 {
+                self.warnings.push(Warning::new(
+                    Lint::UnusedVariable,
+                    format!("'{}' is never read.", name),
+                    *line,
+                ));
+            }
+        }
+    }
+
+    /// Binds `name` to the innermost local it refers to, if any.
+    fn bind(&mut self, name: &Identifier) -> Option<usize> {
+        let local = self
+            .scopes
+            .iter()
+            .rev()
+            .flatten()
+            .copied()
+            .find(|&local| self.locals[local].name == name.name)?;
+        self.bindings.insert(name.span.start, local);
+
+        Some(local)
+    }
+
+    /// Reports stores to locals that are never read, walking the program backwards while
+    /// tracking which locals may still be read.
+    fn liveness(&mut self, program: &Program) {
+        self.reporting = true;
+
+        let mut live = HashSet::new();
+        for statement in program.statements.iter().rev() {
+            live = self.live_statement(statement, live);
+        }
+    }
+
+    /// The locals live before `stmt`, given those live after it.
+    fn live_statement(&mut self, stmt: &Stmt, live: HashSet<usize>) -> HashSet<usize> {
+        match &stmt.kind {
+            StmtKind::Print(expr) | StmtKind::Expression(expr) => self.live_expression(expr, live),
+            StmtKind::Var { name, initializer } => {
+                let mut live = live;
+                // Without an initializer the local is implicitly `nil`, which is not worth
+                // reporting.
+                if let Some(initializer) = initializer {
+                    self.store(name, &live);
+                    if let Some(local) = self.bindings.get(&name.span.start) {
+                        live.remove(local);
+                    }
+                    live = self.live_expression(initializer, live);
+                }
+                live
+            }
+            StmtKind::Block(statements) => statements
+                .iter()
+                .rev()
+                .fold(live, |live, statement| self.live_statement(statement, live)),
+            StmtKind::If {
+                condition,
+                then_branch,
+                else_branch,
+            } => {
+                let mut branches = self.live_statement(then_branch, live.clone());
+                match else_branch {
+                    Some(else_branch) => branches.extend(self.live_statement(else_branch, live)),
+                    None => branches.extend(live),
+                }
+                self.live_expression(condition, branches)
+            }
+            StmtKind::While { condition, body } => {
+                // The body can run again, so what it reads is live at its own end. Find the
+                // values live at the start of the loop quietly first, then report with them.
+                let reporting = std::mem::replace(&mut self.reporting, false);
+                let mut start = self.live_expression(condition, live.clone());
+                loop {
+                    let mut after_condition = self.live_statement(body, start.clone());
+                    after_condition.extend(live.iter().copied());
+                    let next = self.live_expression(condition, after_condition);
+                    if next == start {
+                        break;
+                    }
+                    start = next;
+                }
+                self.reporting = reporting;
+
+                let mut after_condition = self.live_statement(body, start);
+                after_condition.extend(live);
+                self.live_expression(condition, after_condition)
+            }
+        }
+    }
+
+    /// The locals live before `expr` runs, given those live after it.
+    fn live_expression(&mut self, expr: &Expr, mut live: HashSet<usize>) -> HashSet<usize> {
+        match &expr.kind {
+            ExprKind::Literal(_) => live,
+            ExprKind::Variable(name) => {
+                if let Some(&local) = self.bindings.get(&name.span.start) {
+                    live.insert(local);
+                }
+                live
+            }
+            ExprKind::Assign { name, value } => {
+                self.store(name, &live);
+                if let Some(local) = self.bindings.get(&name.span.start) {
+                    live.remove(local);
+                }
+                self.live_expression(value, live)
+            }
+            ExprKind::Unary { operand, .. } => self.live_expression(operand, live),
+            ExprKind::Binary { left, right, .. } => {
+                let live = self.live_expression(right, live);
+                self.live_expression(left, live)
+            }
+            ExprKind::Logical { left, right, .. } => {
+                let mut live = live;
+                live.extend(self.live_expression(right, live.clone()));
+                self.live_expression(left, live)
+            }
+            ExprKind::Grouping(inner) => self.live_expression(inner, live),
+        }
+    }
+
+    /// Reports a store to `name` that is not read before `live` is reached.
+    ///
+    /// Locals that are never read at all are left to `Lint::UnusedVariable`.
+    fn store(&mut self, name: &Identifier, live: &HashSet<usize>) {
+        let Some(&local) = self.bindings.get(&name.span.start) else {
+            return;
+        };
+
+        if self.reporting && !live.contains(&local) && self.locals[local].reads > 0 {
+            self.warnings.push(Warning::new(
+                Lint::UnusedAssignment,
+                format!("value assigned to '{}' is never read.", name.name),
+                name.span.line,
+            ));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::Parser;
+
+    fn warnings(source: &str) -> Vec<(Lint, usize)> {
+        let program = Parser::new(source).parse().unwrap();
+        check(&program, &Lints::default())
+            .unwrap()
+            .iter()
+            .map(|warning| (warning.lint(), warning.line()))
+            .collect()
+    }
+
+    #[test]
+    fn reports_unused_and_shadowed_locals() {
+        let source = "{\n  var a = 1;\n  var b = 2;\n  {\n    var a = 3;\n    print a + b;\n  }\n}";
+
+        assert_eq!(
+            vec![(Lint::UnusedVariable, 2), (Lint::ShadowedVariable, 5)],
+            warnings(source)
+        );
+    }
+
+    #[test]
+    fn reports_assignments_never_read() {
+        let source = "{\n  var a = 1;\n  a = 2;\n  print a;\n  a = 3;\n}";
+
+        assert_eq!(
+            vec![(Lint::UnusedAssignment, 2), (Lint::UnusedAssignment, 5)],
+            warnings(source)
+        );
+    }
+
+    #[test]
+    fn follows_values_through_branches_and_loops() {
+        let source = "{
+            var i = 0;
+            var last = nil;
+            if (i > 0) last = 1;
+            while (i < 3) {
+                print last;
+                last = i;
+                i = i + 1;
+            }
+        }";
+
+        assert_eq!(Vec::<(Lint, usize)>::new(), warnings(source));
+    }
+
+    #[test]
+    fn ignores_globals() {
+        assert_eq!(
+            Vec::<(Lint, usize)>::new(),
+            warnings("var a = 1; a = 2; { var b = a; print b; }")
+        );
+    }
+
+    #[test]
+    fn applies_lint_levels() {
+        let program = Parser::new("{\n  var a;\n}").parse().unwrap();
+
+        let mut lints = Lints::default();
+        lints.set(Lint::UnusedVariable, LintLevel::Allow);
+        assert_eq!(Ok(vec![]), check(&program, &lints));
+
+        lints.set(Lint::UnusedVariable, LintLevel::Deny);
+        assert_eq!(
+            Err(CompilerError::new(
+                "'a' is never read. [unused-variable]",
+                2
+            )),
+            check(&program, &lints)
+        );
+    }
+
+    #[test]
+    fn levels_of_lints_that_cannot_fire_yet() {
+        assert_eq!(Ok(Lint::UnusedParameter), "unused-parameter".parse());
+        assert_eq!(Ok(Lint::UnreachableCode), "unreachable-code".parse());
+
+        let mut lints = Lints::default();
+        lints.set(Lint::UnusedParameter, LintLevel::Deny);
+        lints.set(Lint::UnreachableCode, LintLevel::Allow);
+        assert_eq!(LintLevel::Deny, lints.level(Lint::UnusedParameter));
+        assert_eq!(LintLevel::Allow, lints.level(Lint::UnreachableCode));
+        assert_eq!(LintLevel::Warn, lints.level(Lint::UnusedVariable));
+
+        // Denying them cannot fail a script until functions and `return` exist.
+        let program = Parser::new("{ var a = 1; print a; }").parse().unwrap();
+        assert_eq!(Ok(vec![]), check(&program, &lints));
+    }
+}
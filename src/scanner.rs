@@ -1,9 +1,10 @@
-use std::str::Chars;
+use std::{fmt::Display, str::Chars};
 
 use strum_macros::{EnumCount, EnumIter};
 
-#[derive(Clone, Copy, Debug, EnumCount, EnumIter, Hash, PartialEq, Eq)]
-pub(crate) enum TokenKind {
+/// What a [`Token`] is.
+#[derive(Clone, Copy, Debug, Default, EnumCount, EnumIter, Hash, PartialEq, Eq)]
+pub enum TokenKind {
     // Single-char tokens
     LeftParen,
     RightParen,
@@ -51,16 +52,14 @@ pub(crate) enum TokenKind {
     While,
 
     Comment,
+    /// A character that starts no token. Its lexeme is the character.
+    Error,
     Eof,
+    /// Placeholder for the token before the first one. The scanner never produces it.
+    #[default]
     Dummy,
 }
 
-impl Default for TokenKind {
-    fn default() -> Self {
-        TokenKind::Dummy
-    }
-}
-
 impl TokenKind {
     /// Returns `true` if the token kind is [`Eof`].
     ///
@@ -71,8 +70,9 @@ impl TokenKind {
     }
 }
 
+/// A lexeme of the source, with the line it starts on and its byte offset.
 #[derive(Copy, Clone, Debug, Default, Eq)]
-pub(crate) struct Token<'source> {
+pub struct Token<'source> {
     pub kind: TokenKind,
     pub line: usize,
     pub start: usize,
@@ -86,7 +86,12 @@ impl<'source> PartialEq for Token<'source> {
 }
 
 impl<'source> Token<'source> {
-    pub fn new(kind: TokenKind, line: usize, start: usize, lexeme: Option<&'source str>) -> Self {
+    pub(crate) fn new(
+        kind: TokenKind,
+        line: usize,
+        start: usize,
+        lexeme: Option<&'source str>,
+    ) -> Self {
         Self {
             kind,
             line,
@@ -95,7 +100,7 @@ impl<'source> Token<'source> {
         }
     }
 
-    pub fn dummy() -> Self {
+    pub(crate) fn dummy() -> Self {
         Self {
             kind: TokenKind::Dummy,
             ..Default::default()
@@ -116,6 +121,52 @@ impl<'source> Token<'source> {
     }
 }
 
+/// Source between tokens, which the compiler ignores.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TriviaKind {
+    /// Spaces, tabs and carriage returns.
+    Whitespace,
+    Newline,
+    /// A `//` comment, up to the end of its line.
+    Comment,
+}
+
+/// A run of whitespace, a newline or a comment, with the line it is on and its byte offset.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Trivia<'source> {
+    pub kind: TriviaKind,
+    pub line: usize,
+    pub start: usize,
+    pub text: &'source str,
+}
+
+/// A token with the trivia around it, as produced by [`scan_lossless`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct LosslessToken<'source> {
+    /// Trivia from the end of the previous token's line up to the token.
+    pub leading: Vec<Trivia<'source>>,
+    pub token: Token<'source>,
+    /// Whitespace and comments after the token on the same line.
+    pub trailing: Vec<Trivia<'source>>,
+}
+
+impl Display for LosslessToken<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for trivia in &self.leading {
+            write!(f, "{}", trivia.text)?;
+        }
+        if !self.token.is_eof() {
+            write!(f, "{}", self.token.lexeme())?;
+        }
+        for trivia in &self.trailing {
+            write!(f, "{}", trivia.text)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Splits source into tokens. Offsets into the source are in bytes.
 #[derive(Debug)]
 pub(crate) struct Scanner<'source> {
     chars: Chars<'source>,
@@ -138,6 +189,7 @@ impl<'source> Scanner<'source> {
         }
     }
 
+    #[cfg(test)]
     pub fn line(&self) -> usize {
         self.line
     }
@@ -210,6 +262,74 @@ impl<'source> Scanner<'source> {
         self.make_token(TokenKind::Comment)
     }
 
+    /// Scans the whitespace, newline or comment at the current position, if any.
+    fn trivia(&mut self) -> Option<Trivia<'source>> {
+        self.start = self.current;
+        let line = self.line;
+
+        let kind = match self.peek()? {
+            ' ' | '\r' | '\t' => {
+                while let Some(' ' | '\r' | '\t') = self.peek() {
+                    self.advance();
+                }
+                TriviaKind::Whitespace
+            }
+            '\n' => {
+                self.advance();
+                self.line += 1;
+                TriviaKind::Newline
+            }
+            '/' if self.peek_next() == Some('/') => {
+                self.comment();
+                TriviaKind::Comment
+            }
+            _ => return None,
+        };
+
+        Some(Trivia {
+            kind,
+            line,
+            start: self.start,
+            text: &self.source[self.start..self.current],
+        })
+    }
+
+    /// Scans the whole source, attaching whitespace and comments to the tokens around them
+    /// instead of skipping them, so that the tokens written back in order give the source.
+    ///
+    /// The last token is always `Eof`, which holds the trivia at the end of the source.
+    pub fn scan_lossless(mut self) -> Result<Vec<LosslessToken<'source>>, ScannerError> {
+        let mut tokens = Vec::new();
+
+        loop {
+            let mut leading = Vec::new();
+            while let Some(trivia) = self.trivia() {
+                leading.push(trivia);
+            }
+
+            let token = self.scan_token()?;
+
+            let mut trailing = Vec::new();
+            if !token.is_eof() {
+                while self.peek() != Some('\n') {
+                    match self.trivia() {
+                        Some(trivia) => trailing.push(trivia),
+                        None => break,
+                    }
+                }
+            }
+
+            tokens.push(LosslessToken {
+                leading,
+                token,
+                trailing,
+            });
+            if token.is_eof() {
+                return Ok(tokens);
+            }
+        }
+    }
+
     pub fn scan_token(&mut self) -> Result<Token<'source>, ScannerError> {
         self.skip_whitespace();
 
@@ -276,7 +396,7 @@ impl<'source> Scanner<'source> {
                 }
             }
             '"' => self.string()?,
-            _ => unreachable!("internal error: cannot recognize character for current token."),
+            _ => self.make_token(TokenKind::Error),
         };
 
         Ok(result)
@@ -348,41 +468,43 @@ impl<'source> Scanner<'source> {
     }
 
     fn identifier_kind(&self) -> TokenKind {
-        match &self.source[self.start..self.start + 1] {
-            "a" => self.check_keyword(1, 2, "nd", TokenKind::And),
-            "c" => self.check_keyword(1, 4, "lass", TokenKind::Class),
-            "e" => self.check_keyword(1, 3, "lse", TokenKind::Else),
-            "f" => {
+        let bytes = self.source.as_bytes();
+
+        match bytes[self.start] {
+            b'a' => self.check_keyword(1, 2, "nd", TokenKind::And),
+            b'c' => self.check_keyword(1, 4, "lass", TokenKind::Class),
+            b'e' => self.check_keyword(1, 3, "lse", TokenKind::Else),
+            b'f' => {
                 if self.current - self.start > 1 {
-                    match &self.source[self.start + 1..self.start + 2] {
-                        "a" => self.check_keyword(2, 3, "lse", TokenKind::False),
-                        "o" => self.check_keyword(2, 1, "r", TokenKind::For),
-                        "u" => self.check_keyword(2, 1, "n", TokenKind::Fun),
+                    match bytes[self.start + 1] {
+                        b'a' => self.check_keyword(2, 3, "lse", TokenKind::False),
+                        b'o' => self.check_keyword(2, 1, "r", TokenKind::For),
+                        b'u' => self.check_keyword(2, 1, "n", TokenKind::Fun),
                         _ => TokenKind::Identifier,
                     }
                 } else {
                     TokenKind::Identifier
                 }
             }
-            "i" => self.check_keyword(1, 1, "f", TokenKind::If),
-            "n" => self.check_keyword(1, 2, "il", TokenKind::Nil),
-            "o" => self.check_keyword(1, 1, "r", TokenKind::Or),
-            "p" => self.check_keyword(1, 4, "rint", TokenKind::Print),
-            "r" => self.check_keyword(1, 5, "eturn", TokenKind::Return),
-            "s" => self.check_keyword(1, 4, "uper", TokenKind::Super),
-            "t" => {
+            b'i' => self.check_keyword(1, 1, "f", TokenKind::If),
+            b'n' => self.check_keyword(1, 2, "il", TokenKind::Nil),
+            b'o' => self.check_keyword(1, 1, "r", TokenKind::Or),
+            b'p' => self.check_keyword(1, 4, "rint", TokenKind::Print),
+            b'r' => self.check_keyword(1, 5, "eturn", TokenKind::Return),
+            b's' => self.check_keyword(1, 4, "uper", TokenKind::Super),
+            b't' => {
                 if self.current - self.start > 1 {
-                    match &self.source[self.start + 1..self.start + 2] {
-                        "h" => self.check_keyword(2, 2, "is", TokenKind::This),
-                        "r" => self.check_keyword(2, 2, "ue", TokenKind::True),
+                    match bytes[self.start + 1] {
+                        b'h' => self.check_keyword(2, 2, "is", TokenKind::This),
+                        b'r' => self.check_keyword(2, 2, "ue", TokenKind::True),
                         _ => TokenKind::Identifier,
                     }
                 } else {
                     TokenKind::Identifier
                 }
             }
-            "v" => self.check_keyword(1, 2, "ar", TokenKind::Var),
-            "w" => self.check_keyword(1, 4, "hile", TokenKind::While),
+            b'v' => self.check_keyword(1, 2, "ar", TokenKind::Var),
+            b'w' => self.check_keyword(1, 4, "hile", TokenKind::While),
             _ => TokenKind::Identifier,
         }
     }
@@ -410,7 +532,7 @@ impl<'source> Scanner<'source> {
     pub fn next(&mut self) -> Option<char> {
         match self.chars.next() {
             Some(ch) => {
-                self.current += 1;
+                self.current += ch.len_utf8();
                 Some(ch)
             }
             None => None,
@@ -430,6 +552,15 @@ impl<'source> Iterator for Scanner<'source> {
     }
 }
 
+/// Splits `source` into tokens that keep the whitespace and comments around them, for tools
+/// that rewrite source such as formatters. Writing the tokens back in order gives `source`.
+///
+/// Compiling skips trivia instead, which is faster.
+pub fn scan_lossless(source: &str) -> Result<Vec<LosslessToken<'_>>, ScannerError> {
+    Scanner::new(source).scan_lossless()
+}
+
+/// A lexical error, such as an unterminated string.
 #[derive(Debug, PartialEq, Eq)]
 pub struct ScannerError {
     msg: String,
@@ -437,7 +568,7 @@ pub struct ScannerError {
 }
 
 impl ScannerError {
-    pub fn new(msg: &str, line: usize) -> Self {
+    pub(crate) fn new(msg: &str, line: usize) -> Self {
         Self {
             msg: msg.to_string(),
             line,
@@ -463,14 +594,14 @@ mod tests {
     fn is_at_end_works() {
         let mut scanner = Scanner::new(SOURCE);
 
-        assert_ne!(true, scanner.is_at_end());
+        assert!(!scanner.is_at_end());
         assert_ne!(None, scanner.peek());
 
         for _ in 0..SOURCE.len() {
             scanner.next();
         }
 
-        assert_eq!(true, scanner.is_at_end());
+        assert!(scanner.is_at_end());
         assert_eq!(None, scanner.peek());
     }
 
@@ -478,20 +609,20 @@ mod tests {
     fn is_at_end_works_on_empty() {
         let mut scanner = Scanner::new("");
 
-        assert_eq!(true, scanner.is_at_end());
+        assert!(scanner.is_at_end());
     }
 
     #[test]
     fn next_works() {
         let mut scanner = Scanner::new(SOURCE);
 
-        assert_eq!(false, scanner.is_at_end());
+        assert!(!scanner.is_at_end());
 
         for c in SOURCE.chars() {
             assert_eq!(c, scanner.next().unwrap());
         }
 
-        assert_eq!(true, scanner.is_at_end());
+        assert!(scanner.is_at_end());
     }
 
     #[test]
@@ -499,20 +630,20 @@ mod tests {
         let mut scanner = Scanner::new("");
 
         assert_eq!(None, scanner.next());
-        assert_eq!(true, scanner.is_at_end());
+        assert!(scanner.is_at_end());
     }
 
     #[test]
     fn advance_works() {
         let mut scanner = Scanner::new(SOURCE);
 
-        assert_eq!(false, scanner.is_at_end());
+        assert!(!scanner.is_at_end());
 
         for c in SOURCE.chars() {
             assert_eq!(c, scanner.advance().unwrap());
         }
 
-        assert_eq!(true, scanner.is_at_end());
+        assert!(scanner.is_at_end());
     }
 
     #[test]
@@ -520,7 +651,7 @@ mod tests {
         let mut scanner = Scanner::new("");
 
         assert_eq!(None, scanner.next());
-        assert_eq!(true, scanner.is_at_end());
+        assert!(scanner.is_at_end());
     }
 
     #[test]
@@ -536,7 +667,7 @@ mod tests {
     fn peek_works_at_beginning() {
         let mut scanner = Scanner::new(SOURCE);
 
-        assert_eq!(false, scanner.is_at_end());
+        assert!(!scanner.is_at_end());
         assert_eq!(Some('p'), scanner.peek());
     }
 
@@ -560,7 +691,7 @@ mod tests {
     fn peek_next_works_at_beginning() {
         let mut scanner = Scanner::new(SOURCE);
 
-        assert_eq!(false, scanner.is_at_end());
+        assert!(!scanner.is_at_end());
         assert_eq!(Some('r'), scanner.peek_next());
     }
 
@@ -577,10 +708,10 @@ mod tests {
 
         scanner.next();
         // matches and advances
-        assert_eq!(true, scanner.matches('r'));
+        assert!(scanner.matches('r'));
         assert_eq!(Some('i'), scanner.peek());
         // does not match and does not advance
-        assert_eq!(false, scanner.matches('z'));
+        assert!(!scanner.matches('z'));
         assert_eq!(Some('i'), scanner.next());
     }
 
@@ -588,16 +719,16 @@ mod tests {
     fn matches_works_on_empty() {
         let mut scanner = Scanner::new("");
 
-        assert_eq!(false, scanner.matches('p'));
+        assert!(!scanner.matches('p'));
     }
 
     #[test]
     fn skip_whitespace_works_only_spaces() {
         let mut scanner = Scanner::new(" \t \t      \n  \t  \n   \t   ");
 
-        scanner.scan_token();
+        scanner.scan_token().unwrap();
 
-        assert_eq!(true, scanner.is_at_end());
+        assert!(scanner.is_at_end());
         assert_eq!(3, scanner.line());
     }
 
@@ -605,9 +736,9 @@ mod tests {
     fn skip_whitespace_with_comments_only() {
         let mut scanner = Scanner::new("//this should all be ignored");
 
-        scanner.scan_token();
+        scanner.scan_token().unwrap();
 
-        assert_eq!(true, scanner.is_at_end());
+        assert!(scanner.is_at_end());
         assert_eq!(1, scanner.line());
     }
 
@@ -617,29 +748,29 @@ mod tests {
 
         let token = scanner.scan_token().unwrap();
         assert_eq!("//this should all be ignored", token.lexeme());
-        assert_eq!(false, scanner.is_at_end());
+        assert!(!scanner.is_at_end());
         assert_eq!(1, scanner.line());
         let token = scanner.scan_token().unwrap();
         assert_eq!("//so should this", token.lexeme());
-        assert_eq!(false, scanner.is_at_end());
+        assert!(!scanner.is_at_end());
         assert_eq!(2, scanner.line());
         // still have whitespeces to consume
-        scanner.scan_token();
-        assert_eq!(true, scanner.is_at_end());
+        scanner.scan_token().unwrap();
+        assert!(scanner.is_at_end());
     }
 
     #[test]
     fn skip_whitespace_works_spaces_and_valid_chars() {
         let mut scanner = Scanner::new(" \t \t      1\n  \t  \n   \t   ");
 
-        scanner.scan_token();
+        scanner.scan_token().unwrap();
 
-        assert_eq!(false, scanner.is_at_end());
+        assert!(!scanner.is_at_end());
         assert_eq!(1, scanner.line());
 
-        scanner.scan_token();
+        scanner.scan_token().unwrap();
 
-        assert_eq!(true, scanner.is_at_end());
+        assert!(scanner.is_at_end());
         // not sure
         assert_eq!(3, scanner.line());
     }
@@ -648,9 +779,9 @@ mod tests {
     fn scan_token_skip_whitespace_works_on_empty() {
         let mut scanner = Scanner::new("");
 
-        scanner.scan_token();
+        scanner.scan_token().unwrap();
 
-        assert_eq!(true, scanner.is_at_end());
+        assert!(scanner.is_at_end());
     }
 
     #[test]
@@ -1050,4 +1181,67 @@ mod tests {
 
         assert_eq!(expected_tokens, tokens);
     }
+
+    #[test]
+    fn scan_token_unexpected_characters() {
+        let mut scanner = Scanner::new("@ a\n#é");
+
+        let token = scanner.scan_token().unwrap();
+        assert_eq!(TokenKind::Error, token.kind);
+        assert_eq!("@", token.lexeme());
+        assert_eq!(TokenKind::Identifier, scanner.scan_token().unwrap().kind);
+        let token = scanner.scan_token().unwrap();
+        assert_eq!(TokenKind::Error, token.kind);
+        assert_eq!((2, 4, "#"), (token.line, token.start, token.lexeme()));
+        assert_eq!("é", scanner.scan_token().unwrap().lexeme());
+        assert!(scanner.scan_token().unwrap().is_eof());
+    }
+
+    #[test]
+    fn scan_token_non_ascii() {
+        let mut scanner = Scanner::new("print \"héllo\"; var ñame = 1;");
+
+        scanner.scan_token().unwrap();
+        let token = scanner.scan_token().unwrap();
+        assert_eq!("\"héllo\"", token.lexeme());
+        assert_eq!(6, token.start);
+        scanner.scan_token().unwrap();
+        scanner.scan_token().unwrap();
+        let token = scanner.scan_token().unwrap();
+        assert_eq!(TokenKind::Identifier, *token.kind());
+        assert_eq!("ñame", token.lexeme());
+        assert_eq!(20, token.start);
+    }
+
+    #[test]
+    fn scan_lossless_reproduces_source() {
+        let source = "  // héader\r\nvar a = 1; // trailing\n\n{\tprint a @;}\n// end";
+
+        let tokens = Scanner::new(source).scan_lossless().unwrap();
+
+        let text: String = tokens.iter().map(ToString::to_string).collect();
+        assert_eq!(source, text);
+        assert!(tokens.last().unwrap().token.is_eof());
+    }
+
+    #[test]
+    fn scan_lossless_attaches_trivia() {
+        let tokens = Scanner::new("// lead\nprint 1; // same line\n")
+            .scan_lossless()
+            .unwrap();
+
+        let kinds = |trivia: &[Trivia]| trivia.iter().map(|t| t.kind).collect::<Vec<_>>();
+        assert_eq!(
+            vec![TriviaKind::Comment, TriviaKind::Newline],
+            kinds(&tokens[0].leading)
+        );
+        assert_eq!(TokenKind::Print, tokens[0].token.kind);
+        assert_eq!(
+            vec![TriviaKind::Whitespace, TriviaKind::Comment],
+            kinds(&tokens[2].trailing)
+        );
+        assert_eq!("// same line", tokens[2].trailing[1].text);
+        assert_eq!(vec![TriviaKind::Newline], kinds(&tokens[3].leading));
+        assert!(tokens[3].token.is_eof());
+    }
 }
@@ -1,13 +1,8 @@
-use std::collections::LinkedList;
+use std::cmp::Ordering;
 use std::fmt::{self, Debug, Display};
-use std::mem::forget;
-use std::ops::{Add, Deref, DerefMut, Div, Mul, Neg, Sub};
-use std::ptr::NonNull;
-use std::rc::Rc;
-use std::sync::Mutex;
 
+use crate::heap::{Heap, OutOfMemory};
 use crate::string::String;
-use crate::vm::{self, HEAP};
 use crate::{function::Function, object::Handle};
 
 #[derive(Clone, Copy, Debug)]
@@ -94,100 +89,201 @@ impl fmt::Display for Value {
     }
 }
 
-impl Neg for Value {
-    type Output = Value;
+/// A value handed out by a `Vm`, borrowing its strings from the VM's heap.
+///
+/// The borrow keeps the VM from being dropped or running another script while the value is in
+/// use. Copy the strings out, e.g. with `to_string`, to keep them longer.
+///
+/// ```compile_fail
+/// use rlox::Vm;
+///
+/// let mut vm = Vm::new(None);
+/// let value = vm.interpret("\"x\" + \"y\";".to_string()).unwrap();
+/// drop(vm);
+/// println!("{}", value);
+/// ```
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum LoxValue<'vm> {
+    Number(f64),
+    Boolean(bool),
+    Nil,
+    String(&'vm str),
+    /// A function, by name.
+    Function(&'vm str),
+}
 
-    fn neg(self) -> Self::Output {
+impl fmt::Display for LoxValue<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
-            Self::Number(number) => Self::Number(-number),
-            Value::Boolean(_) => panic!("unsupported integer negation for booleans"),
-            Value::Nil => panic!("unsupported integer negation for Nil"),
-            Value::String(_) => panic!("unsupported integer negation for string objects"),
-            Value::Function(_) => panic!("unsupported integer negation for function objects"),
+            LoxValue::Number(inner) => write!(f, "{}", inner),
+            LoxValue::Boolean(inner) => write!(f, "{}", inner),
+            LoxValue::Nil => write!(f, "nil"),
+            LoxValue::String(string) => write!(f, "{}", string),
+            LoxValue::Function(name) => write!(f, "{}", name),
         }
     }
 }
 
-impl Add for Value {
-    type Output = Value;
-
-    fn add(self, rhs: Self) -> Self::Output {
-        match (self, rhs) {
-            (Value::Number(number), Value::Number(rhs_number)) => {
-                Value::Number(number + rhs_number)
-            }
-            (Value::String(left), Value::String(right)) => {
-                let new_obj = &*left + &*right;
-                Value::from(new_obj)
-            }
-            (left, right) => panic!("unsupported addition between {} and {}", left, right),
+impl Value {
+    /// Views the value as a `LoxValue`.
+    ///
+    /// # Safety
+    ///
+    /// The objects the value points to must stay alive and unchanged for `'h`.
+    pub(crate) unsafe fn to_lox_value<'h>(self) -> LoxValue<'h> {
+        match self {
+            Value::Number(number) => LoxValue::Number(number),
+            Value::Boolean(boolean) => LoxValue::Boolean(boolean),
+            Value::Nil => LoxValue::Nil,
+            Value::String(string) => LoxValue::String(&*(string.as_str() as *const str)),
+            Value::Function(function) => LoxValue::Function(&*(function.name() as *const str)),
         }
     }
 }
 
-impl Sub for Value {
-    type Output = Value;
+/// Error from applying an operator to operands of the wrong type.
+#[derive(Debug, PartialEq, Eq)]
+pub struct TypeError {
+    msg: &'static str,
+}
+
+impl TypeError {
+    pub(crate) const NUMBER_OPERAND: Self = Self {
+        msg: "operand must be a number.",
+    };
+    pub(crate) const NUMBERS_OR_STRINGS: Self = Self {
+        msg: "operands must be two numbers or two strings.",
+    };
+    /// Two strings are valid operands of `+`, but their concatenation has to go in a heap.
+    pub(crate) const NEEDS_HEAP: Self = Self {
+        msg: "strings can only be concatenated by a Vm.",
+    };
+
+    pub fn msg(&self) -> &str {
+        self.msg
+    }
+}
+
+impl Display for TypeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.msg)
+    }
+}
 
-    fn sub(self, rhs: Self) -> Self::Output {
+impl Value {
+    #[inline]
+    fn numbers(self, rhs: Value) -> Result<(f64, f64), TypeError> {
         match (self, rhs) {
-            (Value::Number(number), Value::Number(rhs_number)) => {
-                Value::Number(number - rhs_number)
-            }
-            (left, right) => panic!("unsupported substraction between {} and {}", left, right),
+            (Value::Number(left), Value::Number(right)) => Ok((left, right)),
+            _ => Err(TypeError::NUMBER_OPERAND),
         }
     }
-}
 
-impl Div for Value {
-    type Output = Value;
+    #[inline]
+    pub fn try_neg(self) -> Result<Value, TypeError> {
+        match self {
+            Value::Number(number) => Ok(Value::Number(-number)),
+            _ => Err(TypeError::NUMBER_OPERAND),
+        }
+    }
 
-    fn div(self, rhs: Self) -> Self::Output {
+    /// Adds two numbers.
+    ///
+    /// Lox also adds two strings, but the result has to be allocated in the heap of a `Vm`, so
+    /// only the VM concatenates them and two strings fail here with `TypeError::NEEDS_HEAP`.
+    #[inline]
+    pub fn try_add(self, rhs: Value) -> Result<Value, TypeError> {
         match (self, rhs) {
-            (Value::Number(number), Value::Number(rhs_number)) => {
-                Value::Number(number / rhs_number)
-            }
-            (left, right) => panic!("unsupported division between {} and {}", left, right),
+            (Value::Number(left), Value::Number(right)) => Ok(Value::Number(left + right)),
+            (Value::String(_), Value::String(_)) => Err(TypeError::NEEDS_HEAP),
+            _ => Err(TypeError::NUMBERS_OR_STRINGS),
         }
     }
-}
 
-impl Mul for Value {
-    type Output = Value;
+    #[inline]
+    pub fn try_sub(self, rhs: Value) -> Result<Value, TypeError> {
+        let (left, right) = self.numbers(rhs)?;
+        Ok(Value::Number(left - right))
+    }
+
+    #[inline]
+    pub fn try_mul(self, rhs: Value) -> Result<Value, TypeError> {
+        let (left, right) = self.numbers(rhs)?;
+        Ok(Value::Number(left * right))
+    }
 
-    fn mul(self, rhs: Self) -> Self::Output {
+    #[inline]
+    pub fn try_div(self, rhs: Value) -> Result<Value, TypeError> {
+        let (left, right) = self.numbers(rhs)?;
+        Ok(Value::Number(left / right))
+    }
+
+    /// Orders two numbers or two strings, failing for any other pair of operands.
+    #[inline]
+    fn compare(self, rhs: Value) -> Result<Option<Ordering>, TypeError> {
         match (self, rhs) {
-            (Value::Number(number), Value::Number(rhs_number)) => {
-                Value::Number(number * rhs_number)
+            (Value::Number(_), Value::Number(_)) | (Value::String(_), Value::String(_)) => {
+                Ok(self.partial_cmp(&rhs))
             }
-            (left, right) => panic!("unsupported multiplication between {} and {}", left, right),
+            _ => Err(TypeError::NUMBERS_OR_STRINGS),
         }
     }
+
+    #[inline]
+    pub fn try_greater(self, rhs: Value) -> Result<Value, TypeError> {
+        Ok(Value::Boolean(
+            self.compare(rhs)? == Some(Ordering::Greater),
+        ))
+    }
+
+    #[inline]
+    pub fn try_greater_equal(self, rhs: Value) -> Result<Value, TypeError> {
+        Ok(Value::Boolean(matches!(
+            self.compare(rhs)?,
+            Some(Ordering::Greater | Ordering::Equal)
+        )))
+    }
+
+    #[inline]
+    pub fn try_less(self, rhs: Value) -> Result<Value, TypeError> {
+        Ok(Value::Boolean(self.compare(rhs)? == Some(Ordering::Less)))
+    }
+
+    #[inline]
+    pub fn try_less_equal(self, rhs: Value) -> Result<Value, TypeError> {
+        Ok(Value::Boolean(matches!(
+            self.compare(rhs)?,
+            Some(Ordering::Less | Ordering::Equal)
+        )))
+    }
 }
 
+/// Lox equality. Numbers follow IEEE 754, so `NaN` differs from itself, strings compare by
+/// content and functions by identity. Values of different types are never equal.
 impl PartialEq for Value {
     fn eq(&self, other: &Self) -> bool {
         match (self, other) {
             (Self::Number(left), Self::Number(right)) => left == right,
             (Self::Boolean(left), Self::Boolean(right)) => left == right,
+            (Self::Nil, Self::Nil) => true,
             (Self::String(left), Self::String(right)) => left == right,
-            _ => core::mem::discriminant(self) == core::mem::discriminant(other),
+            (Self::Function(left), Self::Function(right)) => Handle::ptr_eq(left, right),
+            _ => false,
         }
     }
 }
 
+/// Only numbers and strings are ordered, strings lexicographically by their bytes.
 impl PartialOrd for Value {
     fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
         match (self, other) {
             (Self::Number(left), Self::Number(right)) => left.partial_cmp(right),
-            (Self::Boolean(left), Self::Boolean(right)) => left.partial_cmp(right),
-            (Self::String(left), Self::String(right)) => left.partial_cmp(right),
-            (left, right) => left.partial_cmp(right),
+            (Self::String(left), Self::String(right)) => Some(left.as_str().cmp(right.as_str())),
+            _ => None,
         }
     }
 }
 
-impl Eq for Value {}
-
 impl From<f64> for Value {
     fn from(inner: f64) -> Self {
         Self::Number(inner)
@@ -200,17 +296,259 @@ impl From<bool> for Value {
     }
 }
 
-impl From<String> for Value {
-    fn from(string_obj: String) -> Self {
-        let string_handle = HEAP.with(|heap| heap.borrow_mut().allocate_string(string_obj));
-        Self::String(string_handle)
+/// Conversion of a Rust value into a Lox value.
+///
+/// Strings are allocated in `heap`, which must belong to the `Vm` the value is handed to. This
+/// fails when the heap is full.
+///
+/// The trait is sealed: an implementation outside the crate could keep a `Value` pointing into
+/// a heap past the life of its `Vm`.
+pub trait IntoLox: sealed::Sealed {
+    fn into_lox(self, heap: &mut Heap) -> Result<Value, OutOfMemory>;
+}
+
+/// Conversion of a Lox value back into a Rust value.
+///
+/// `from_lox` returns `None` when the Lox value has a different type. Sealed like `IntoLox`.
+///
+/// Strings come back as an owned `String` rather than a `&str`: the characters live in the heap
+/// of the VM and can move or be freed by the next script, so a host keeping them has to copy
+/// them anyway. A script's result borrows them for as long as the VM does, as a `LoxValue`.
+pub trait FromLox: Sized + sealed::Sealed {
+    fn from_lox(value: Value) -> Option<Self>;
+}
+
+mod sealed {
+    pub trait Sealed {}
+
+    impl Sealed for f64 {}
+    impl Sealed for bool {}
+    impl Sealed for &str {}
+    impl Sealed for std::string::String {}
+    impl<T: Sealed> Sealed for Option<T> {}
+}
+
+impl IntoLox for f64 {
+    fn into_lox(self, _heap: &mut Heap) -> Result<Value, OutOfMemory> {
+        Ok(Value::Number(self))
+    }
+}
+
+impl FromLox for f64 {
+    fn from_lox(value: Value) -> Option<Self> {
+        value.as_number().copied()
+    }
+}
+
+impl IntoLox for bool {
+    fn into_lox(self, _heap: &mut Heap) -> Result<Value, OutOfMemory> {
+        Ok(Value::Boolean(self))
+    }
+}
+
+impl FromLox for bool {
+    fn from_lox(value: Value) -> Option<Self> {
+        value.as_boolean()
+    }
+}
+
+impl IntoLox for &str {
+    fn into_lox(self, heap: &mut Heap) -> Result<Value, OutOfMemory> {
+        Ok(Value::String(heap.allocate_string(String::new(self))?))
+    }
+}
+
+impl IntoLox for std::string::String {
+    fn into_lox(self, heap: &mut Heap) -> Result<Value, OutOfMemory> {
+        self.as_str().into_lox(heap)
+    }
+}
+
+impl FromLox for std::string::String {
+    fn from_lox(value: Value) -> Option<Self> {
+        value.as_string().map(|string| string.as_str().to_owned())
+    }
+}
+
+/// `None` is `nil`.
+impl<T: IntoLox> IntoLox for Option<T> {
+    fn into_lox(self, heap: &mut Heap) -> Result<Value, OutOfMemory> {
+        match self {
+            Some(value) => value.into_lox(heap),
+            None => Ok(Value::Nil),
+        }
+    }
+}
+
+/// `nil` is `None`, any other value must convert to `T`.
+impl<T: FromLox> FromLox for Option<T> {
+    fn from_lox(value: Value) -> Option<Self> {
+        match value {
+            Value::Nil => Some(None),
+            value => T::from_lox(value).map(Some),
+        }
     }
 }
 
-impl From<&str> for Value {
-    fn from(string: &str) -> Self {
-        let string_handle =
-            HEAP.with(|heap| heap.borrow_mut().allocate_string(String::new(string)));
-        Self::String(string_handle)
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn conversions_round_trip() {
+        let mut heap = Heap::new();
+        assert_eq!(Some(1.5), f64::from_lox(1.5.into_lox(&mut heap).unwrap()));
+        assert_eq!(
+            Some(true),
+            bool::from_lox(true.into_lox(&mut heap).unwrap())
+        );
+        assert_eq!(
+            Some("lox".to_string()),
+            std::string::String::from_lox("lox".into_lox(&mut heap).unwrap())
+        );
+        assert_eq!(
+            Some(None),
+            Option::<f64>::from_lox(None::<f64>.into_lox(&mut heap).unwrap())
+        );
+        assert_eq!(
+            Some(Some(2.0)),
+            Option::<f64>::from_lox(Some(2.0).into_lox(&mut heap).unwrap())
+        );
+    }
+
+    #[test]
+    fn arithmetic_on_numbers() {
+        let (two, three) = (Value::Number(2.0), Value::Number(3.0));
+
+        assert_eq!(Ok(Value::Number(-2.0)), two.try_neg());
+        assert_eq!(Ok(Value::Number(5.0)), two.try_add(three));
+        assert_eq!(Ok(Value::Number(-1.0)), two.try_sub(three));
+        assert_eq!(Ok(Value::Number(6.0)), two.try_mul(three));
+        assert_eq!(Ok(Value::Number(1.5)), three.try_div(two));
+        assert_eq!(Ok(Value::Boolean(true)), three.try_greater(two));
+        assert_eq!(Ok(Value::Boolean(false)), three.try_less(two));
+    }
+
+    #[test]
+    fn arithmetic_type_errors() {
+        let mut heap = Heap::new();
+        let string = "a".into_lox(&mut heap).unwrap();
+        let operands = [Value::Nil, Value::Boolean(true), string];
+
+        for operand in operands {
+            assert_eq!(Err(TypeError::NUMBER_OPERAND), operand.try_neg());
+            assert_eq!(
+                Err(TypeError::NUMBERS_OR_STRINGS),
+                operand.try_add(Value::Number(1.0))
+            );
+            assert_eq!(
+                Err(TypeError::NUMBERS_OR_STRINGS),
+                Value::Number(1.0).try_add(operand)
+            );
+            assert_eq!(
+                Err(TypeError::NUMBER_OPERAND),
+                operand.try_sub(Value::Number(1.0))
+            );
+            assert_eq!(
+                Err(TypeError::NUMBER_OPERAND),
+                Value::Number(1.0).try_mul(operand)
+            );
+            assert_eq!(Err(TypeError::NUMBER_OPERAND), operand.try_div(operand));
+        }
+        assert_eq!(Err(TypeError::NEEDS_HEAP), string.try_add(string));
+    }
+
+    /// One value of every type, each different from the others.
+    fn one_of_each(heap: &mut Heap) -> [Value; 5] {
+        [
+            Value::Number(1.0),
+            Value::Boolean(true),
+            Value::Nil,
+            "a".into_lox(heap).unwrap(),
+            Value::Function(heap.allocate(Function::new(None, None)).unwrap()),
+        ]
+    }
+
+    #[test]
+    fn equality_for_every_type_pair() {
+        let mut heap = Heap::new();
+        let values = one_of_each(&mut heap);
+        let copies = one_of_each(&mut heap);
+
+        for (i, left) in values.iter().enumerate() {
+            for (j, right) in values.iter().enumerate() {
+                assert_eq!(i == j, left == right, "{:?} == {:?}", left, right);
+            }
+        }
+
+        // Numbers, booleans, nil and strings compare by value, functions by identity.
+        let equal: Vec<_> = values.iter().zip(&copies).map(|(a, b)| a == b).collect();
+        assert_eq!(vec![true, true, true, true, false], equal);
+        assert_ne!(Value::Boolean(true), Value::Boolean(false));
+        assert_ne!(Value::Number(0.0), Value::Boolean(false));
+        assert_ne!(Value::Nil, Value::Boolean(false));
+    }
+
+    #[test]
+    fn comparison_for_every_type_pair() {
+        let mut heap = Heap::new();
+        let values = one_of_each(&mut heap);
+
+        for (i, left) in values.iter().enumerate() {
+            for (j, right) in values.iter().enumerate() {
+                // Only number-number and string-string pairs are ordered.
+                let ordered = i == j && (left.is_number() || left.is_string());
+                assert_eq!(
+                    ordered,
+                    left.try_less(*right).is_ok(),
+                    "{:?} < {:?}",
+                    left,
+                    right
+                );
+                assert_eq!(ordered, left.try_greater(*right).is_ok());
+                assert_eq!(ordered, left.partial_cmp(right).is_some());
+            }
+        }
+    }
+
+    #[test]
+    fn strings_compare_lexicographically() {
+        let mut heap = Heap::new();
+        let [a, ab, b] = ["a", "ab", "b"].map(|s| s.into_lox(&mut heap).unwrap());
+
+        assert_eq!(Ok(Value::Boolean(true)), a.try_less(b));
+        assert_eq!(Ok(Value::Boolean(true)), a.try_less(ab));
+        assert_eq!(Ok(Value::Boolean(true)), b.try_greater(ab));
+        assert_eq!(Ok(Value::Boolean(false)), a.try_greater(a));
+        assert_eq!(Ok(Value::Boolean(false)), a.try_less(a));
+    }
+
+    #[test]
+    fn nan_is_not_equal_to_itself() {
+        let nan = Value::Number(f64::NAN);
+
+        assert_ne!(nan, nan);
+        assert_eq!(None, nan.partial_cmp(&nan));
+        assert_eq!(Ok(Value::Boolean(false)), nan.try_less(nan));
+        assert_eq!(
+            Ok(Value::Boolean(false)),
+            nan.try_greater(Value::Number(1.0))
+        );
+        assert_eq!(Ok(Value::Boolean(false)), nan.try_greater_equal(nan));
+        assert_eq!(
+            Ok(Value::Boolean(false)),
+            Value::Number(1.0).try_less_equal(nan)
+        );
+        assert_eq!(
+            Ok(Value::Boolean(true)),
+            Value::Number(1.0).try_less_equal(Value::Number(1.0))
+        );
+    }
+
+    #[test]
+    fn conversions_check_types() {
+        assert_eq!(None, f64::from_lox(Value::Nil));
+        assert_eq!(None, bool::from_lox(Value::Number(0.0)));
+        assert_eq!(None, Option::<bool>::from_lox(Value::Number(0.0)));
     }
 }
@@ -0,0 +1,364 @@
+//! Checks a chunk is well formed before the VM runs it.
+//!
+//! The VM decodes bytecode without any checks: it reads constants with `get_unchecked`, moves
+//! `ip` by raw jump offsets and indexes locals straight into the stack. The verifier makes that
+//! safe for any chunk, including ones that did not come from the compiler. It checks that
+//!
+//! * every opcode is valid and its operands fit in the chunk,
+//! * constant, cache and line indices are in bounds, and global names are strings,
+//! * jumps land on instruction boundaries inside the chunk,
+//! * the stack depth at each instruction is the same along every path, never underflows and
+//!   covers every local slot accessed,
+//! * execution cannot run past the last instruction.
+
+use crate::bytecode::{Chunk, OpCode};
+
+/// A malformed chunk, with the offset of the offending instruction.
+#[derive(Debug, PartialEq, Eq)]
+pub struct VerifierError {
+    msg: String,
+    offset: usize,
+}
+
+impl VerifierError {
+    fn new(msg: String, offset: usize) -> Self {
+        Self { msg, offset }
+    }
+
+    pub fn msg(&self) -> &str {
+        &self.msg
+    }
+
+    pub fn offset(&self) -> usize {
+        self.offset
+    }
+}
+
+/// Verifies `chunk`, returning the first problem found.
+pub(crate) fn verify(chunk: &Chunk) -> Result<(), VerifierError> {
+    let verifier = Verifier::new(chunk)?;
+    verifier.check_jumps()?;
+    verifier.check_stack()
+}
+
+struct Verifier<'c> {
+    chunk: &'c Chunk,
+    /// The opcode starting at each offset, `None` for operand bytes.
+    opcodes: Vec<Option<OpCode>>,
+}
+
+impl<'c> Verifier<'c> {
+    /// Decodes every instruction, checking its operands along the way.
+    fn new(chunk: &'c Chunk) -> Result<Self, VerifierError> {
+        let code = chunk.code();
+        if code.is_empty() {
+            return Err(VerifierError::new("empty chunk.".into(), 0));
+        }
+        if chunk.lines().len() < code.len() {
+            return Err(VerifierError::new(
+                "missing line information.".into(),
+                chunk.lines().len(),
+            ));
+        }
+
+        let mut opcodes = vec![None; code.len()];
+        let mut offset = 0;
+        while offset < code.len() {
+            let opcode = OpCode::from_repr(code[offset]).ok_or_else(|| {
+                VerifierError::new(format!("invalid opcode {:#04x}.", code[offset]), offset)
+            })?;
+            let next = offset + 1 + opcode.operand_width();
+            if next > code.len() {
+                return Err(VerifierError::new(
+                    format!("{} is missing operands.", opcode),
+                    offset,
+                ));
+            }
+
+            check_operands(chunk, opcode, offset)?;
+            opcodes[offset] = Some(opcode);
+            offset = next;
+        }
+
+        Ok(Self { chunk, opcodes })
+    }
+
+    fn jump_target(&self, opcode: OpCode, offset: usize) -> Option<usize> {
+        let code = self.chunk.code();
+        let jump = u16::from_le_bytes([code[offset + 1], code[offset + 2]]) as usize;
+        let next = offset + 3;
+
+        match opcode {
+            OpCode::Loop => next.checked_sub(jump),
+            _ => Some(next + jump),
+        }
+    }
+
+    fn check_jumps(&self) -> Result<(), VerifierError> {
+        for (offset, opcode) in self.instructions() {
+            if !opcode.is_jump() {
+                continue;
+            }
+
+            let target = self.jump_target(opcode, offset);
+            match target {
+                Some(target) if self.opcodes.get(target).is_some_and(Option::is_some) => {}
+                Some(target) => {
+                    return Err(VerifierError::new(
+                        format!("{} to {} does not land on an instruction.", opcode, target),
+                        offset,
+                    ))
+                }
+                None => {
+                    return Err(VerifierError::new(
+                        format!("{} jumps before the start of the chunk.", opcode),
+                        offset,
+                    ))
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Follows every path through the chunk, computing the stack depth before each instruction.
+    fn check_stack(&self) -> Result<(), VerifierError> {
+        let code = self.chunk.code();
+        let mut depths: Vec<Option<usize>> = vec![None; code.len()];
+        let mut pending = vec![(0, 0)];
+
+        while let Some((offset, depth)) = pending.pop() {
+            match depths[offset] {
+                Some(known) if known == depth => continue,
+                Some(known) => {
+                    return Err(VerifierError::new(
+                        format!(
+                            "stack depth is {} on one path and {} on another.",
+                            known, depth
+                        ),
+                        offset,
+                    ))
+                }
+                None => depths[offset] = Some(depth),
+            }
+
+            let opcode = self.opcodes[offset].expect("offsets are instruction starts.");
+            let (pops, pushes) = stack_effect(opcode);
+            if depth < pops {
+                return Err(VerifierError::new(
+                    format!("{} underflows the stack.", opcode),
+                    offset,
+                ));
+            }
+            if matches!(opcode, OpCode::GetLocal | OpCode::SetLocal) {
+                let slot = code[offset + 1] as usize;
+                if slot >= depth {
+                    return Err(VerifierError::new(
+                        format!(
+                            "local slot {} out of range for stack depth {}.",
+                            slot, depth
+                        ),
+                        offset,
+                    ));
+                }
+            }
+            let depth = depth - pops + pushes;
+
+            if opcode.is_jump() {
+                let target = self
+                    .jump_target(opcode, offset)
+                    .expect("jumps are checked.");
+                pending.push((target, depth));
+            }
+            if !matches!(opcode, OpCode::Return | OpCode::Jump | OpCode::Loop) {
+                let next = offset + 1 + opcode.operand_width();
+                if next >= code.len() {
+                    return Err(VerifierError::new(
+                        "execution runs past the end of the chunk.".into(),
+                        offset,
+                    ));
+                }
+                pending.push((next, depth));
+            }
+        }
+
+        Ok(())
+    }
+
+    fn instructions(&self) -> impl Iterator<Item = (usize, OpCode)> + '_ {
+        self.opcodes
+            .iter()
+            .enumerate()
+            .filter_map(|(offset, opcode)| opcode.map(|opcode| (offset, opcode)))
+    }
+}
+
+/// Checks the constant and cache operands of the instruction at `offset`.
+fn check_operands(chunk: &Chunk, opcode: OpCode, offset: usize) -> Result<(), VerifierError> {
+    let code = chunk.code();
+    let error = |msg: String| Err(VerifierError::new(msg, offset));
+
+    if matches!(
+        opcode,
+        OpCode::AddConstant | OpCode::DefineGlobal | OpCode::GetGlobal | OpCode::SetGlobal
+    ) {
+        let constant = code[offset + 1] as usize;
+        match chunk.constants().get(constant) {
+            None => return error(format!("constant {} out of bounds.", constant)),
+            Some(value) if opcode != OpCode::AddConstant && !value.is_string() => {
+                return error(format!("constant {} is not a variable name.", constant))
+            }
+            _ => {}
+        }
+    }
+
+    if matches!(opcode, OpCode::GetGlobal | OpCode::SetGlobal) {
+        let cache = code[offset + 2] as usize;
+        if cache >= chunk.caches().len() {
+            return error(format!("inline cache {} out of bounds.", cache));
+        }
+    }
+
+    Ok(())
+}
+
+/// How many values an instruction needs on the stack, and how many it leaves in their place.
+fn stack_effect(opcode: OpCode) -> (usize, usize) {
+    match opcode {
+        OpCode::Return | OpCode::Jump | OpCode::Loop => (0, 0),
+        OpCode::AddConstant
+        | OpCode::AddNil
+        | OpCode::AddTrue
+        | OpCode::AddFalse
+        | OpCode::GetGlobal
+        | OpCode::GetLocal => (0, 1),
+        OpCode::Equal
+        | OpCode::Greater
+        | OpCode::GreaterEqual
+        | OpCode::Less
+        | OpCode::LessEqual
+        | OpCode::Add
+        | OpCode::Substract
+        | OpCode::Multiply
+        | OpCode::Divide => (2, 1),
+        OpCode::Negate
+        | OpCode::Not
+        | OpCode::SetGlobal
+        | OpCode::SetLocal
+        | OpCode::JumpIfFalse
+        | OpCode::JumpIfTrue => (1, 1),
+        OpCode::Print | OpCode::Pop | OpCode::DefineGlobal => (1, 0),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compiler::Compiler;
+    use crate::heap::Heap;
+    use crate::value::Value;
+
+    fn chunk(code: &[u8]) -> Chunk {
+        let mut chunk = Chunk::new();
+        for byte in code {
+            chunk.write(*byte, 1);
+        }
+        chunk
+    }
+
+    fn error(chunk: &Chunk) -> (String, usize) {
+        let error = verify(chunk).unwrap_err();
+        (error.msg().to_string(), error.offset())
+    }
+
+    #[test]
+    fn compiled_code_verifies() {
+        let mut heap = Heap::new();
+        let source = "var a = 1; { var b = a; while (b < 3) { b = b + 1; } print b or a; }";
+        let chunk = Compiler::new(None).compile(source, &mut heap).unwrap();
+
+        assert_eq!(Ok(()), verify(&chunk));
+    }
+
+    #[test]
+    fn rejects_invalid_opcodes_and_operands() {
+        assert_eq!(("empty chunk.".into(), 0), error(&chunk(&[])));
+        assert_eq!(
+            ("invalid opcode 0xff.".into(), 1),
+            error(&chunk(&[OpCode::AddNil as u8, 0xff]))
+        );
+        assert_eq!(
+            ("OP_JUMP is missing operands.".into(), 0),
+            error(&chunk(&[OpCode::Jump as u8, 0]))
+        );
+        assert_eq!(
+            ("constant 0 out of bounds.".into(), 0),
+            error(&chunk(&[
+                OpCode::AddConstant as u8,
+                0,
+                OpCode::Return as u8
+            ]))
+        );
+
+        let mut number_name = chunk(&[OpCode::GetGlobal as u8, 0, 0, OpCode::Return as u8]);
+        number_name.add_constant(Value::Number(1.0));
+        number_name.add_cache();
+        assert_eq!(
+            ("constant 0 is not a variable name.".into(), 0),
+            error(&number_name)
+        );
+    }
+
+    #[test]
+    fn rejects_bad_jumps() {
+        let [low, high] = 1u16.to_le_bytes();
+        let into_operand = chunk(&[OpCode::Jump as u8, low, high, OpCode::Return as u8]);
+        assert_eq!(
+            ("OP_JUMP to 4 does not land on an instruction.".into(), 0),
+            error(&into_operand)
+        );
+
+        let [low, high] = 4u16.to_le_bytes();
+        let before_start = chunk(&[OpCode::Loop as u8, low, high, OpCode::Return as u8]);
+        assert_eq!(
+            ("OP_LOOP jumps before the start of the chunk.".into(), 0),
+            error(&before_start)
+        );
+    }
+
+    #[test]
+    fn rejects_inconsistent_stacks() {
+        assert_eq!(
+            ("OP_POP underflows the stack.".into(), 0),
+            error(&chunk(&[OpCode::Pop as u8, OpCode::Return as u8]))
+        );
+        assert_eq!(
+            ("local slot 1 out of range for stack depth 1.".into(), 1),
+            error(&chunk(&[
+                OpCode::AddNil as u8,
+                OpCode::GetLocal as u8,
+                1,
+                OpCode::Return as u8
+            ]))
+        );
+        assert_eq!(
+            ("execution runs past the end of the chunk.".into(), 0),
+            error(&chunk(&[OpCode::AddNil as u8]))
+        );
+
+        // The path that skips the push reaches the return with one value fewer.
+        let [low, high] = 1u16.to_le_bytes();
+        let unbalanced = chunk(&[
+            OpCode::AddTrue as u8,
+            OpCode::JumpIfFalse as u8,
+            low,
+            high,
+            OpCode::AddNil as u8,
+            OpCode::Return as u8,
+        ]);
+        assert_eq!(
+            ("stack depth is 2 on one path and 1 on another.".into(), 5),
+            error(&unbalanced)
+        );
+    }
+}
@@ -1,33 +1,58 @@
-use std::cell::RefCell;
-use std::collections::LinkedList;
-use std::ops::Deref;
-use std::rc::Rc;
+use std::io::{self, Write};
+use std::str::FromStr;
 use std::string::String;
-use std::sync::Mutex;
-use std::{fmt::Display, ptr, result};
-
-use once_cell::sync::OnceCell;
-
-use crate::bytecode::{Chunk, Disassembler, OpCode};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::{
+    fmt::{Debug, Display},
+    ptr, result,
+};
+
+use crate::assembler::{Assembler, AssemblerError};
+use crate::ast::{self, Program};
+use crate::bytecode::{Chunk, Disassembler, DisassemblerOptions, InlineCache, OpCode};
+use crate::cfg::Cfg;
 use crate::compiler::{Compiler, CompilerError, CompilerOptions};
-use crate::heap::Heap;
+use crate::decompiler::{self, DecompileError};
+use crate::folder;
+use crate::heap::{Heap, OutOfMemory};
+use crate::loxc::{self, LoadError};
 use crate::object::Handle;
+use crate::parser::Parser;
+use crate::register::{Instruction, RegisterChunk, RegisterCodegen};
+use crate::resolver::Warning;
 use crate::string::String as LoxString;
-use crate::value::Value;
-use rlox_common::{Array, HashMap, Stack};
-
-thread_local! {
-    pub(crate) static HEAP: RefCell<Heap> = RefCell::new(Heap::new());
-}
+use crate::value::{FromLox, IntoLox, LoxValue, TypeError, Value};
+use crate::verifier::{self, VerifierError};
+use rlox_common::{Array, FixedStack, HashMap};
 
+/// Why a `Vm` stopped.
 #[derive(Debug, PartialEq, Eq)]
 pub enum VmError {
-    Compile(CompilerError),
+    /// The source did not compile. Syntax errors are all reported, in source order.
+    Compile(Vec<CompilerError>),
+    /// The bytecode assembly did not assemble.
+    Assemble(AssemblerError),
+    /// A `.loxc` file could not be read.
+    Load(LoadError),
+    /// The compiled chunk is malformed.
+    Verify(VerifierError),
+    /// The chunk has code the decompiler does not recognize.
+    Decompile(DecompileError),
+    /// The script failed while running.
     Runtime(RuntimeError),
+    /// The host misused the embedding API, e.g. resumed a script that was not suspended.
+    ///
+    /// Until Lox has functions, `Vm::call_global` also fails with this for every global.
+    Call(String),
+    /// The script used up its instruction budget. `Vm::refuel` and `Vm::resume` continue it.
+    OutOfFuel,
+    /// The script was stopped through an `InterruptHandle`.
+    Interrupted,
 }
 
 impl VmError {
-    pub fn runtime(msg: &str, line: usize) -> Self {
+    pub(crate) fn runtime(msg: &str, line: usize) -> Self {
         Self::Runtime(RuntimeError {
             msg: msg.to_string(),
             line,
@@ -37,7 +62,37 @@ impl VmError {
 
 impl From<CompilerError> for VmError {
     fn from(error: CompilerError) -> Self {
-        VmError::Compile(error)
+        VmError::Compile(vec![error])
+    }
+}
+
+impl From<Vec<CompilerError>> for VmError {
+    fn from(errors: Vec<CompilerError>) -> Self {
+        VmError::Compile(errors)
+    }
+}
+
+impl From<AssemblerError> for VmError {
+    fn from(error: AssemblerError) -> Self {
+        VmError::Assemble(error)
+    }
+}
+
+impl From<LoadError> for VmError {
+    fn from(error: LoadError) -> Self {
+        VmError::Load(error)
+    }
+}
+
+impl From<VerifierError> for VmError {
+    fn from(error: VerifierError) -> Self {
+        VmError::Verify(error)
+    }
+}
+
+impl From<DecompileError> for VmError {
+    fn from(error: DecompileError) -> Self {
+        VmError::Decompile(error)
     }
 }
 
@@ -47,23 +102,59 @@ impl From<RuntimeError> for VmError {
     }
 }
 
-type InterpretResult = result::Result<Value, VmError>;
+/// The last value a script produced, or why it stopped.
+pub type InterpretResult<'vm> = result::Result<LoxValue<'vm>, VmError>;
+
+type RunResult = result::Result<Value, VmError>;
 
 impl Display for VmError {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
-            VmError::Compile(error) => {
-                write!(f, "[line: {}] compile error: {}", error.line(), error.msg())
+            VmError::Compile(errors) => {
+                let errors: Vec<_> = errors
+                    .iter()
+                    .map(|error| format!("[line: {}] compile error: {}", error.line(), error.msg()))
+                    .collect();
+                write!(f, "{}", errors.join("\n"))
+            }
+            VmError::Assemble(error) => {
+                write!(
+                    f,
+                    "[line: {}] assemble error: {}",
+                    error.line(),
+                    error.msg()
+                )
+            }
+            VmError::Load(error) => write!(f, "load error: {}", error.msg()),
+            VmError::Verify(error) => {
+                write!(
+                    f,
+                    "[offset: {}] verify error: {}",
+                    error.offset(),
+                    error.msg()
+                )
+            }
+            VmError::Decompile(error) => {
+                write!(
+                    f,
+                    "[offset: {}] decompile error: {}",
+                    error.offset(),
+                    error.msg()
+                )
             }
             VmError::Runtime(error) => {
                 write!(f, "[line: {}] runtime error: {}", error.line(), error.msg())
             }
+            VmError::Call(msg) => write!(f, "call error: {}", msg),
+            VmError::OutOfFuel => write!(f, "out of fuel."),
+            VmError::Interrupted => write!(f, "interrupted."),
         }?;
 
         Ok(())
     }
 }
 
+/// An error raised by a running script.
 #[derive(Debug, PartialEq, Eq)]
 pub struct RuntimeError {
     msg: String,
@@ -80,53 +171,468 @@ impl RuntimeError {
     }
 }
 
-#[derive(Debug, Default)]
-pub(crate) struct VmOptions {
+/// The instruction set a `Vm` executes.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Backend {
+    /// The stack machine the compiler targets.
+    #[default]
+    Stack,
+    /// Three-address register code generated from the syntax tree. Experimental.
+    Register,
+}
+
+impl FromStr for Backend {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "stack" => Ok(Backend::Stack),
+            "register" => Ok(Backend::Register),
+            _ => Err(format!(
+                "unknown backend '{}', expected 'stack' or 'register'.",
+                s
+            )),
+        }
+    }
+}
+
+/// Default size of the value stack, in values.
+pub const DEFAULT_MAX_STACK: usize = 16 * 1024;
+
+/// How a `Vm` compiles and runs scripts.
+pub struct VmOptions {
+    /// Print the stack and every instruction as it executes.
     pub trace_execution: bool,
     pub compiler: CompilerOptions,
+    pub backend: Backend,
+    /// Print the hit and miss counters of every inline cache after running.
+    pub profile: bool,
+    /// Number of values the stack can hold. Pushing past it is a "stack overflow" error.
+    pub max_stack: usize,
+    /// Approximate number of bytes of objects the heap may hold. Allocating past it is an
+    /// "out of memory" error. Unlimited when `None`.
+    ///
+    /// There is no garbage collector yet, so objects live as long as the `Vm` and the count
+    /// only grows: the limit covers everything every script run on this `Vm` has allocated.
+    pub max_heap_bytes: Option<usize>,
+    /// Number of instructions each script may execute before stopping with
+    /// `VmError::OutOfFuel`. Unlimited when `None`.
+    pub fuel: Option<u64>,
+    /// Where `print` statements, traces, disassembly and profiles are written. Defaults to the
+    /// standard output.
+    pub output: Option<Box<dyn Write + Send>>,
+}
+
+impl Debug for VmOptions {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("VmOptions")
+            .field("trace_execution", &self.trace_execution)
+            .field("compiler", &self.compiler)
+            .field("backend", &self.backend)
+            .field("profile", &self.profile)
+            .field("max_stack", &self.max_stack)
+            .field("max_heap_bytes", &self.max_heap_bytes)
+            .field("fuel", &self.fuel)
+            .field("output", &self.output.as_ref().map(|_| ".."))
+            .finish()
+    }
+}
+
+impl Default for VmOptions {
+    fn default() -> Self {
+        Self {
+            trace_execution: false,
+            compiler: CompilerOptions::default(),
+            backend: Backend::default(),
+            profile: false,
+            max_stack: DEFAULT_MAX_STACK,
+            max_heap_bytes: None,
+            fuel: None,
+            output: None,
+        }
+    }
+}
+
+/// Stops the script running in a `Vm`, from any thread.
+///
+/// The VM polls the handle at every backward jump, so a script that loops forever still stops
+/// promptly while straight-line code runs to the end. A request stays pending until a script
+/// honors it, so one made between scripts stops the next script at its first backward jump
+/// unless the host drops it with `clear`. Clones share the same flag.
+#[derive(Clone, Debug, Default)]
+pub struct InterruptHandle {
+    requested: Arc<AtomicBool>,
+}
+
+impl InterruptHandle {
+    /// Asks the running script to stop with `VmError::Interrupted`.
+    pub fn interrupt(&self) {
+        self.requested.store(true, Ordering::Relaxed);
+    }
+
+    /// Drops a pending request, e.g. one that came in after its script already finished.
+    pub fn clear(&self) {
+        self.requested.store(false, Ordering::Relaxed);
+    }
+
+    /// Consumes a pending request, if any.
+    #[inline]
+    fn take(&self) -> bool {
+        self.requested.load(Ordering::Relaxed) && self.requested.swap(false, Ordering::Relaxed)
+    }
 }
 
-pub(crate) struct Vm {
+/// Global variables, stored in slots that never move so inline caches can remember them.
+struct Globals {
+    slots: HashMap<LoxString, usize>,
+    values: Array<Value>,
+}
+
+impl Globals {
+    fn new() -> Self {
+        Self {
+            slots: HashMap::new(),
+            values: Array::new(),
+        }
+    }
+
+    fn define(&mut self, name: &LoxString, value: Value) {
+        match self.slots.get(name) {
+            Some(slot) => self.values[*slot] = value,
+            None => {
+                self.slots.insert(name.clone(), self.values.len());
+                self.values.write(value);
+            }
+        }
+    }
+
+    /// Finds the slot of a global, trying the inline cache of the instruction before the table.
+    #[inline]
+    fn slot(&self, name: &LoxString, cache: &InlineCache) -> Option<usize> {
+        if let Some(slot) = cache.lookup() {
+            return Some(slot);
+        }
+
+        let slot = *self.slots.get(name)?;
+        cache.fill(slot);
+        Some(slot)
+    }
+}
+
+/// A Lox virtual machine.
+///
+/// Global variables outlive each call to `interpret`, so a host can run several scripts against
+/// the same state, or exchange values with them through `get_global` and `set_global`.
+///
+/// A `Vm` is `Send` but not `Sync`: it can move to another thread between scripts, but a single
+/// VM never runs on two threads at once.
+///
+/// ```
+/// fn assert_send<T: Send>() {}
+/// assert_send::<rlox::Vm>();
+/// ```
+///
+/// ```compile_fail
+/// fn assert_sync<T: Sync>() {}
+/// assert_sync::<rlox::Vm>();
+/// ```
+pub struct Vm {
     chunk: Option<Chunk>,
+    register_chunk: Option<RegisterChunk>,
     source: Option<String>,
+    /// What the lints found in the last compiled source.
+    warnings: Vec<Warning>,
     ip: *mut u8,
+    /// Where the register backend resumes after running out of fuel.
+    pc: usize,
+    /// Instructions left before the running script stops, if it is metered.
+    fuel: Option<u64>,
+    /// Whether a script is prepared or ran out of fuel, and can be resumed.
+    suspended: bool,
+    interrupt: InterruptHandle,
     options: VmOptions,
-    stack: Stack<Value>,
-    globals: HashMap<LoxString, Value>,
+    output: Box<dyn Write + Send>,
+    stack: FixedStack<Value>,
+    globals: Globals,
     last: Value,
+    /// Every object the VM allocated. Dropping the VM frees them.
+    heap: Heap,
 }
 
+// Safety: the raw pointers inside a `Vm` (`ip`, and the handles in its values, constants and
+// globals) all point into memory it owns, its chunks and its heap, and the heap only holds
+// strings and functions, which are `Send`. Nothing outside the VM can hold one of them while it
+// moves:
+// - the values a host gets back are `LoxValue<'vm>`, which borrow the VM;
+// - `Heap` is not exported, so a host cannot build one or allocate into it;
+// - `IntoLox` and `FromLox` are sealed, so no host impl can keep a raw `Value` past a call.
+unsafe impl Send for Vm {}
+
 impl Vm {
+    /// Creates a VM, with the default options when `options` is `None`.
     pub fn new(options: Option<VmOptions>) -> Self {
-        let options = options.unwrap_or_default();
+        let mut options = options.unwrap_or_default();
+        let output = options
+            .output
+            .take()
+            .unwrap_or_else(|| Box::new(io::stdout()));
+        let heap = Heap::with_limit(options.max_heap_bytes);
 
         Self {
+            output,
             chunk: None,
+            register_chunk: None,
             ip: ptr::null_mut(),
-            stack: Stack::new(),
+            pc: 0,
+            fuel: options.fuel,
+            suspended: false,
+            interrupt: InterruptHandle::default(),
+            stack: FixedStack::with_capacity(options.max_stack),
             options,
             source: None,
-            globals: HashMap::new(),
+            warnings: Vec::new(),
+            globals: Globals::new(),
             last: Value::Nil,
+            heap,
+        }
+    }
+
+    /// Compiles and runs `source`, returning the last value it produced.
+    ///
+    /// The value borrows the VM, since its strings live in the VM's heap.
+    ///
+    /// When `VmOptions::fuel` is set, the script gets a fresh budget of that many instructions.
+    pub fn interpret(&mut self, source: String) -> InterpretResult<'_> {
+        self.prepare(source)?;
+        self.execute()
+    }
+
+    /// Compiles `source` and gets it ready to run without running it, so the host can look at
+    /// the `warnings` first. `resume` then runs the script.
+    pub fn prepare(&mut self, source: String) -> Result<(), VmError> {
+        self.source = Some(source);
+        self.reset();
+        match self.options.backend {
+            Backend::Stack => {
+                let chunk = self.compile()?;
+                self.load(chunk)?;
+            }
+            Backend::Register => {
+                let register_chunk = self.compile_registers()?;
+                self.load_registers(register_chunk)?;
+            }
+        }
+        self.suspended = true;
+        Ok(())
+    }
+
+    /// Runs a script compiled by `compile_bytecode`, skipping compilation.
+    ///
+    /// The chunk goes through the verifier first, so a corrupted or hand-made file is rejected
+    /// instead of crashing the VM.
+    ///
+    /// Bytecode is stack code, so this fails on a VM using the register backend.
+    pub fn interpret_bytecode(&mut self, bytes: &[u8]) -> InterpretResult<'_> {
+        self.prepare_bytecode(bytes)?;
+        self.execute()
+    }
+
+    /// Gets a script compiled by `compile_bytecode` ready to run, like `prepare`.
+    pub fn prepare_bytecode(&mut self, bytes: &[u8]) -> Result<(), VmError> {
+        if self.options.backend == Backend::Register {
+            return Err(VmError::Load(LoadError::new(
+                "the register backend only runs source, not bytecode.",
+            )));
         }
+
+        self.reset();
+        let chunk = loxc::decode(bytes, &mut self.heap)?;
+        self.load(chunk)?;
+        self.suspended = true;
+        Ok(())
+    }
+
+    /// Compiles `source` to the `.loxc` format without running it.
+    pub fn compile_bytecode(&mut self, source: String) -> Result<Vec<u8>, VmError> {
+        self.source = Some(source);
+        let chunk = self.compile()?;
+        Ok(loxc::encode(&chunk))
+    }
+
+    /// Compiles `source` and lists its bytecode.
+    pub fn disassemble(
+        &mut self,
+        source: String,
+        options: DisassemblerOptions,
+    ) -> Result<String, VmError> {
+        self.source = Some(source);
+        let chunk = self.compile()?;
+        listing(&chunk, options)
+    }
+
+    /// Lists the bytecode in a `.loxc` file.
+    ///
+    /// The chunk is only verified for `DisassemblerOptions::cfg`, so listings also work for files
+    /// the VM refuses to run.
+    pub fn disassemble_bytecode(
+        &mut self,
+        bytes: &[u8],
+        options: DisassemblerOptions,
+    ) -> Result<String, VmError> {
+        let chunk = loxc::decode(bytes, &mut self.heap)?;
+        listing(&chunk, options)
     }
 
-    pub fn interpret(&mut self, source: String) -> InterpretResult {
+    /// Assembles textual bytecode to the `.loxc` format.
+    ///
+    /// The code is not verified until it is loaded, so the file may hold a malformed chunk.
+    pub fn assemble_bytecode(&mut self, source: &str) -> Result<Vec<u8>, VmError> {
+        let chunk = Assembler::new(source, &mut self.heap).assemble()?;
+        Ok(loxc::encode(&chunk))
+    }
+
+    /// Compiles `source` and writes it back as Lox source.
+    pub fn decompile(&mut self, source: String) -> Result<String, VmError> {
         self.source = Some(source);
         let chunk = self.compile()?;
-        let ip_start = chunk.start();
+        verifier::verify(&chunk)?;
+        Ok(decompiler::decompile(&chunk)?)
+    }
+
+    /// Reconstructs Lox source from a `.loxc` file.
+    ///
+    /// Local variable names come from the file's debug information.
+    pub fn decompile_bytecode(&mut self, bytes: &[u8]) -> Result<String, VmError> {
+        let chunk = loxc::decode(bytes, &mut self.heap)?;
+        verifier::verify(&chunk)?;
+        Ok(decompiler::decompile(&chunk)?)
+    }
+
+    /// Forgets the state of the previous script before running a new one.
+    fn reset(&mut self) {
+        self.suspended = false;
+        self.fuel = self.options.fuel;
+    }
+
+    /// Verifies `chunk` and gets the stack backend ready to run it.
+    fn load(&mut self, chunk: Chunk) -> Result<(), VmError> {
+        verifier::verify(&chunk)?;
+
+        if self.options.compiler.print_code {
+            let bytecode = Disassembler::disassemble_chunk(&chunk, "code");
+            let _ = writeln!(self.output, "{}", bytecode);
+        }
+
+        self.ip = chunk.start();
         self.chunk = Some(chunk);
-        self.ip = ip_start;
 
-        run(self)
+        Ok(())
+    }
+
+    /// Gets the register backend ready to run `register_chunk`.
+    fn load_registers(&mut self, register_chunk: RegisterChunk) -> Result<(), VmError> {
+        if self.options.compiler.print_code {
+            let _ = writeln!(self.output, "{}", register_chunk.disassemble("registers"));
+        }
+
+        self.register_chunk = Some(register_chunk);
+        enter_registers(self)
+    }
+
+    /// Runs the script `prepare` got ready, or continues the one that last ran out of fuel from
+    /// the instruction it stopped at.
+    pub fn resume(&mut self) -> InterpretResult<'_> {
+        if !self.suspended {
+            return Err(VmError::Call("no script to resume.".to_string()));
+        }
+
+        self.execute()
+    }
+
+    /// The warnings about the source compiled last, in line order.
+    pub fn warnings(&self) -> &[Warning] {
+        &self.warnings
+    }
+
+    /// Returns a handle other threads can use to stop the scripts this VM runs.
+    pub fn interrupt_handle(&self) -> InterruptHandle {
+        self.interrupt.clone()
+    }
+
+    /// Sets how many more instructions the script may execute.
+    pub fn refuel(&mut self, fuel: u64) {
+        self.fuel = Some(fuel);
+    }
+
+    /// Instructions left in the budget, or `None` when execution is not metered.
+    pub fn fuel(&self) -> Option<u64> {
+        self.fuel
+    }
+
+    fn execute(&mut self) -> InterpretResult<'_> {
+        let result = match self.options.backend {
+            Backend::Stack => run(self),
+            Backend::Register => run_registers(self),
+        };
+        self.suspended = result == Err(VmError::OutOfFuel);
+        self.print_profile();
+
+        // Safety: objects stay in the heap until the VM drops, and the result borrows the VM.
+        result.map(|value| unsafe { value.to_lox_value() })
+    }
+
+    /// Reads the global variable `name`, or `None` if it is not defined or is not a `T`.
+    pub fn get_global<T: FromLox>(&self, name: &str) -> Option<T> {
+        let slot = *self.globals.slots.get(&LoxString::new(name))?;
+        T::from_lox(self.globals.values[slot])
     }
 
-    pub fn compile(&mut self) -> Result<Chunk, VmError> {
+    /// Defines the global variable `name`, or overwrites it if scripts already defined it.
+    ///
+    /// Fails when the value does not fit in the heap.
+    pub fn set_global(&mut self, name: &str, value: impl IntoLox) -> Result<(), OutOfMemory> {
+        let value = value.into_lox(&mut self.heap)?;
+        self.globals.define(&LoxString::new(name), value);
+        Ok(())
+    }
+
+    /// Calls the global function `name` with `args`, returning the value the call produced.
+    ///
+    /// Lox has no function declarations yet, so no global is callable and this always fails
+    /// with `VmError::Call`. Hosts can already build on the signature, which calls will keep.
+    pub fn call_global(&mut self, name: &str, args: &[LoxValue<'_>]) -> InterpretResult<'_> {
+        // The arguments get converted once there is a function to pass them to.
+        let _ = args;
+        Err(VmError::Call(format!("'{}' is not callable.", name)))
+    }
+
+    pub(crate) fn compile(&mut self) -> Result<Chunk, VmError> {
+        let mut program = self.parse()?;
+        folder::fold(&mut program);
+        let compiler = Compiler::new(Some(&self.options.compiler));
+        // Denied lints stop compilation before any constant is allocated.
+        self.warnings = compiler.lint(&program)?;
+        Ok(compiler.generate(&program, &mut self.heap)?)
+    }
+
+    /// Compiles the source to code for the register backend.
+    fn compile_registers(&mut self) -> Result<RegisterChunk, VmError> {
+        let mut program = self.parse()?;
+        folder::fold(&mut program);
+        self.warnings = Compiler::new(Some(&self.options.compiler)).lint(&program)?;
+        Ok(RegisterCodegen::new(&mut self.heap).generate(&program)?)
+    }
+
+    fn parse(&mut self) -> Result<Program, VmError> {
         let source = self.source.as_ref().unwrap().clone();
-        let mut compiler = Compiler::new(Some(&self.options.compiler));
-        let chunk = compiler.compile(&source)?;
+        self.warnings.clear();
+        let program = Parser::new(&source).parse()?;
+
+        if self.options.compiler.dump_ast {
+            let _ = write!(self.output, "{}", ast::dump(&program));
+        }
 
-        Ok(chunk)
+        Ok(program)
     }
 
     #[inline]
@@ -144,7 +650,7 @@ impl Vm {
             let bytes = [*self.ip, *self.ip.add(1)];
             self.ip = self.ip.add(2);
 
-            u16::from_ne_bytes(bytes)
+            u16::from_le_bytes(bytes)
         }
     }
 
@@ -162,33 +668,110 @@ impl Vm {
     }
 
     #[inline]
-    fn read_string(&mut self) -> LoxString {
+    fn read_string(&mut self) -> Handle<LoxString> {
         let constant = self.read_constant();
-        let string = constant.as_string().unwrap();
-        (**string).clone()
+        *constant.as_string().unwrap()
+    }
+
+    #[inline]
+    fn global_cache(&self, cache: u8) -> &InlineCache {
+        &self.chunk.as_ref().unwrap().caches()[cache as usize]
+    }
+
+    /// Prints how often the inline cache of every global access hit, when profiling is on.
+    fn print_profile(&mut self) {
+        if !self.options.profile {
+            return;
+        }
+
+        // Every global access, as its offset, opcode, the name it accesses and its cache.
+        let mut accesses = Vec::new();
+        match self.options.backend {
+            Backend::Stack => {
+                let chunk = self.chunk.as_ref().unwrap();
+                let code = chunk.code();
+                let mut offset = 0;
+                while offset < code.len() {
+                    let opcode = OpCode::from_repr(code[offset]).unwrap();
+                    if matches!(opcode, OpCode::GetGlobal | OpCode::SetGlobal) {
+                        accesses.push((
+                            offset,
+                            opcode.to_string(),
+                            chunk.constants()[code[offset + 1] as usize],
+                            &chunk.caches()[code[offset + 2] as usize],
+                        ));
+                    }
+                    offset += 1 + opcode.operand_width();
+                }
+            }
+            Backend::Register => {
+                let register_chunk = self.register_chunk.as_ref().unwrap();
+                for (index, instruction) in register_chunk.code().iter().enumerate() {
+                    let (opcode, name, cache) = match *instruction {
+                        Instruction::GetGlobal { name, cache, .. } => ("R_GET_GLOBAL", name, cache),
+                        Instruction::SetGlobal { name, cache, .. } => ("R_SET_GLOBAL", name, cache),
+                        _ => continue,
+                    };
+                    accesses.push((
+                        index,
+                        opcode.to_string(),
+                        register_chunk.constants()[name as usize],
+                        &register_chunk.caches()[cache as usize],
+                    ));
+                }
+            }
+        }
+
+        let output = &mut self.output;
+        let (mut hits, mut misses) = (0, 0);
+
+        let _ = writeln!(output, "== inline caches ==");
+        let _ = writeln!(
+            output,
+            "{:<6} {:<16} {:<16} {:>10} {:>10}",
+            "offset", "opcode", "name", "hits", "misses"
+        );
+
+        for (offset, opcode, name, cache) in accesses {
+            let _ = writeln!(
+                output,
+                "{:04}   {:<16} {:<16} {:>10} {:>10}",
+                offset,
+                opcode,
+                name.to_string(),
+                cache.hits(),
+                cache.misses()
+            );
+            hits += cache.hits();
+            misses += cache.misses();
+        }
+
+        let _ = writeln!(output, "{:<40} {:>10} {:>10}", "total", hits, misses);
     }
 
     #[inline]
-    fn push(&mut self, value: Value) {
-        self.stack.push(value)
+    fn push(&mut self, value: Value) -> Result<(), RuntimeError> {
+        if self.stack.is_full() {
+            return self.runtime_error("stack overflow.");
+        }
+
+        unsafe { self.stack.push_unchecked(value) };
+        Ok(())
     }
 
+    // The compiler only emits pops and peeks for values it pushed first, so the stack cannot
+    // underflow on the paths below.
+
     #[inline]
     fn pop(&mut self) -> Value {
-        let value = self.stack.pop().expect("empty stack");
+        let value = unsafe { self.stack.pop_unchecked() };
         self.last = value;
         value
     }
 
     #[inline]
-    fn peek(&mut self, distance: usize) -> Result<Value, RuntimeError> {
-        match self.stack.peek(distance) {
-            Some(value) => Ok(*value),
-            None => Err(RuntimeError {
-                msg: format!("no value at distance: {} in the stack.", distance),
-                line: self.current_line(),
-            }),
-        }
+    fn peek(&self, distance: usize) -> Value {
+        unsafe { *self.stack.peek_unchecked(distance) }
     }
 
     #[inline]
@@ -208,74 +791,56 @@ impl Vm {
     fn current_line(&self) -> usize {
         let instruction = self.current_instruction_offset();
 
-        self.chunk.as_ref().unwrap().lines()[instruction]
+        self.chunk.as_ref().unwrap().line_for_offset(instruction)
     }
 
-    fn print_stack(&self) {
-        println!("{}", self.stack);
+    fn print_stack(&mut self) {
+        let _ = writeln!(self.output, "{}", self.stack);
     }
 
     fn dissasemble_current_instruction(&mut self) {
         let mut dissasembler = Disassembler::new(self.chunk.as_ref().unwrap(), "chunk");
-        let mut output = String::new();
 
         let disassembled_instruction =
             dissasembler.disassemble_instruction(self.current_instruction_offset());
 
-        print!("{}", disassembled_instruction)
-    }
-
-    #[inline]
-    fn check_both_number(&mut self) -> Result<(), RuntimeError> {
-        if let (Some(left), Some(right)) = (self.stack.peek(1), self.stack.peek(0)) {
-            if !left.is_number() || !right.is_number() {
-                return self.runtime_error("operand must be a number.");
-            }
-        } else {
-            return self.runtime_error("missing operand.");
-        }
-
-        Ok(())
-    }
-
-    #[inline]
-    fn check_number(&mut self) -> Result<(), RuntimeError> {
-        if !self.stack.peek(0).unwrap().is_number() {
-            return self.runtime_error("operand must be a number.");
-        }
-
-        Ok(())
+        let _ = write!(self.output, "{}", disassembled_instruction);
     }
 
-    fn vm_error(&mut self, message: &str) -> InterpretResult {
-        let instruction = self.current_instruction_offset();
+    fn vm_error(&mut self, message: &str) -> RunResult {
+        let line = self.current_line();
 
         self.reset_stack();
 
-        Err(VmError::runtime(
-            message,
-            self.chunk.as_ref().unwrap().lines()[instruction],
-        ))
+        Err(VmError::runtime(message, line))
     }
 
     fn runtime_error(&mut self, message: &str) -> Result<(), RuntimeError> {
-        let instruction = self.current_instruction_offset();
+        let line = self.current_line();
 
         self.reset_stack();
 
         Err(RuntimeError {
             msg: message.to_string(),
-            line: self.chunk.as_ref().unwrap().lines()[instruction],
+            line,
         })
     }
 }
 
-fn run(vm: &mut Vm) -> InterpretResult {
+fn run(vm: &mut Vm) -> RunResult {
     debug_assert!(!vm.ip.is_null());
 
     // vm.reset_stack();
 
     loop {
+        // Checked before decoding, so `ip` still points at the instruction to resume from.
+        if let Some(fuel) = vm.fuel.as_mut() {
+            if *fuel == 0 {
+                return Err(VmError::OutOfFuel);
+            }
+            *fuel -= 1;
+        }
+
         if vm.options.trace_execution {
             vm.print_stack();
             vm.dissasemble_current_instruction();
@@ -289,94 +854,83 @@ fn run(vm: &mut Vm) -> InterpretResult {
             OpCode::Return => return Ok(vm.last),
             OpCode::AddConstant => {
                 let constant = vm.read_constant();
-                vm.push(constant);
-            }
-            OpCode::Negate => {
-                vm.check_number()?;
-                let negated = -vm.pop();
-                vm.push(negated);
+                vm.push(constant)?;
             }
+            OpCode::Negate => match vm.peek(0).try_neg() {
+                Ok(negated) => {
+                    vm.pop();
+                    vm.push(negated)?;
+                }
+                Err(error) => return vm.vm_error(error.msg()),
+            },
             OpCode::Add => op_add(vm)?,
-            OpCode::Substract => {
-                vm.check_both_number()?;
-                let right = vm.pop();
-                let left = vm.pop();
-                vm.push(left - right);
-            }
-            OpCode::Multiply => {
-                vm.check_both_number()?;
-                let right = vm.pop();
-                let left = vm.pop();
-                vm.push(left * right);
-            }
-            OpCode::Divide => {
-                vm.check_both_number()?;
-                let right = vm.pop();
-                let left = vm.pop();
-                vm.push(left / right);
-            }
-            OpCode::AddNil => vm.push(Value::Nil),
-            OpCode::AddTrue => vm.push(Value::r#true()),
-            OpCode::AddFalse => vm.push(Value::r#false()),
+            OpCode::Substract => binary_op(vm, Value::try_sub)?,
+            OpCode::Multiply => binary_op(vm, Value::try_mul)?,
+            OpCode::Divide => binary_op(vm, Value::try_div)?,
+            OpCode::AddNil => vm.push(Value::Nil)?,
+            OpCode::AddTrue => vm.push(Value::r#true())?,
+            OpCode::AddFalse => vm.push(Value::r#false())?,
             OpCode::Not => {
                 let value = vm.pop();
-                vm.push(Value::from(value.is_falsey()))
+                vm.push(Value::from(value.is_falsey()))?
             }
             OpCode::Equal => {
                 let right = vm.pop();
                 let left = vm.pop();
-                vm.push(Value::from(left == right))
-            }
-            OpCode::Greater => {
-                vm.check_both_number()?;
-                let right = vm.pop();
-                let left = vm.pop();
-                vm.push(Value::from(left > right));
-            }
-            OpCode::Less => {
-                vm.check_both_number()?;
-                let right = vm.pop();
-                let left = vm.pop();
-                vm.push(Value::from(left < right));
+                vm.push(Value::from(left == right))?
             }
+            OpCode::Greater => binary_op(vm, Value::try_greater)?,
+            OpCode::GreaterEqual => binary_op(vm, Value::try_greater_equal)?,
+            OpCode::Less => binary_op(vm, Value::try_less)?,
+            OpCode::LessEqual => binary_op(vm, Value::try_less_equal)?,
             OpCode::Print => {
-                println!("{}", vm.pop());
+                let value = vm.pop();
+                if writeln!(vm.output, "{}", value).is_err() {
+                    return vm.vm_error("cannot write output.");
+                }
             }
             OpCode::Pop => {
                 vm.pop();
             }
             OpCode::DefineGlobal => {
                 let name = vm.read_string();
-                let value = vm.peek(0)?;
-                vm.globals.insert(name, value);
+                let value = vm.peek(0);
+                vm.globals.define(&name, value);
                 vm.pop();
             }
             OpCode::GetGlobal => {
                 let name = vm.read_string();
-                match vm.globals.get(&name) {
-                    Some(value) => vm.push(*value),
-                    None => return vm.vm_error(&format!("undefined variable '{}'.", name)),
+                let cache = vm.read_byte();
+                match vm.globals.slot(&name, vm.global_cache(cache)) {
+                    Some(slot) => vm.push(vm.globals.values[slot])?,
+                    None => return vm.vm_error(&format!("undefined variable '{}'.", *name)),
                 };
             }
             OpCode::SetGlobal => {
                 let name = vm.read_string();
-                let value = vm.peek(0)?;
-                if vm.globals.insert(name.clone(), value) {
-                    vm.globals.remove(&name);
-                    return vm.vm_error(&format!("undefined variable '{}'.", name));
-                }
+                let cache = vm.read_byte();
+                match vm.globals.slot(&name, vm.global_cache(cache)) {
+                    Some(slot) => vm.globals.values[slot] = vm.peek(0),
+                    None => return vm.vm_error(&format!("undefined variable '{}'.", *name)),
+                };
             }
             OpCode::GetLocal => {
                 let slot = vm.read_byte();
-                vm.push(vm.stack[slot as usize]);
+                vm.push(vm.stack[slot as usize])?;
             }
             OpCode::SetLocal => {
                 let slot = vm.read_byte();
-                vm.stack[slot as usize] = vm.peek(0)?;
+                vm.stack[slot as usize] = vm.peek(0);
             }
             OpCode::JumpIfFalse => {
                 let offset = vm.read_short();
-                if vm.peek(0)?.is_falsey() {
+                if vm.peek(0).is_falsey() {
+                    unsafe { vm.ip = vm.ip.add(offset.into()) };
+                }
+            }
+            OpCode::JumpIfTrue => {
+                let offset = vm.read_short();
+                if !vm.peek(0).is_falsey() {
                     unsafe { vm.ip = vm.ip.add(offset.into()) };
                 }
             }
@@ -386,34 +940,630 @@ fn run(vm: &mut Vm) -> InterpretResult {
             }
             OpCode::Loop => {
                 let offset = vm.read_short();
+                if vm.interrupt.take() {
+                    vm.reset_stack();
+                    return Err(VmError::Interrupted);
+                }
                 unsafe { vm.ip = vm.ip.sub(offset.into()) };
             }
         }
     }
 }
 
+/// The run loop for the register backend.
+///
+/// Registers live in the value stack, so register `n` is the `n`th slot of the current frame.
+fn run_registers(vm: &mut Vm) -> RunResult {
+    let register_chunk = vm
+        .register_chunk
+        .take()
+        .expect("register code expected here.");
+    let result = execute_registers(vm, &register_chunk);
+    vm.register_chunk = Some(register_chunk);
+
+    // The registers of a script that ran out of fuel are kept for `resume`.
+    if result.is_err() && result != Err(VmError::OutOfFuel) {
+        vm.reset_stack();
+    }
+
+    result
+}
+
+/// Sets up the registers of the register code in `vm`, to run it from the start.
+fn enter_registers(vm: &mut Vm) -> Result<(), VmError> {
+    let register_chunk = vm
+        .register_chunk
+        .as_ref()
+        .expect("register code expected here.");
+
+    vm.pc = 0;
+    vm.stack.reset();
+    if register_chunk.frame_size() > vm.stack.capacity() {
+        return Err(VmError::runtime(
+            "stack overflow.",
+            register_chunk
+                .lines()
+                .line_for_offset(0)
+                .unwrap_or_default(),
+        ));
+    }
+    for _ in 0..register_chunk.frame_size() {
+        unsafe { vm.stack.push_unchecked(Value::Nil) };
+    }
+
+    Ok(())
+}
+
+fn execute_registers(vm: &mut Vm, register_chunk: &RegisterChunk) -> RunResult {
+    let code = register_chunk.code();
+    let (constants, caches) = (register_chunk.constants(), register_chunk.caches());
+    let error = |message: &str, pc: usize| {
+        Err(VmError::runtime(
+            message,
+            register_chunk.line_for_offset(pc),
+        ))
+    };
+    let registers = &mut *vm.stack;
+
+    // Safety: the code generator never produces a register at or above the frame size, nor a
+    // constant index or jump target outside the register chunk.
+    macro_rules! reg {
+        ($register:expr) => {
+            *unsafe { registers.get_unchecked_mut($register as usize) }
+        };
+    }
+
+    macro_rules! number {
+        ($register:expr, $pc:expr) => {
+            match reg!($register) {
+                Value::Number(number) => number,
+                _ => return error(TypeError::NUMBER_OPERAND.msg(), $pc),
+            }
+        };
+    }
+
+    let mut pc = vm.pc;
+    loop {
+        if let Some(fuel) = vm.fuel.as_mut() {
+            if *fuel == 0 {
+                vm.pc = pc;
+                return Err(VmError::OutOfFuel);
+            }
+            *fuel -= 1;
+        }
+
+        let instruction = unsafe { *code.get_unchecked(pc) };
+
+        if vm.options.trace_execution {
+            let _ = write!(vm.output, "[ ");
+            for value in registers.iter() {
+                let _ = write!(vm.output, "{} ", value);
+            }
+            let _ = writeln!(vm.output, "]");
+            let _ = writeln!(vm.output, "{:04} {}", pc, instruction);
+        }
+
+        match instruction {
+            Instruction::Return => return Ok(vm.last),
+            Instruction::LoadConstant { dst, constant } => {
+                reg!(dst) = unsafe { *constants.get_unchecked(constant as usize) }
+            }
+            Instruction::LoadNil { dst } => reg!(dst) = Value::Nil,
+            Instruction::LoadTrue { dst } => reg!(dst) = Value::r#true(),
+            Instruction::LoadFalse { dst } => reg!(dst) = Value::r#false(),
+            Instruction::Move { dst, src } => reg!(dst) = reg!(src),
+            Instruction::Negate { dst, src } => reg!(dst) = Value::from(-number!(src, pc)),
+            Instruction::Not { dst, src } => reg!(dst) = Value::from(reg!(src).is_falsey()),
+            Instruction::Add { dst, left, right } => {
+                reg!(dst) = match (reg!(left), reg!(right)) {
+                    (Value::Number(left), Value::Number(right)) => Value::from(left + right),
+                    (Value::String(left), Value::String(right)) => {
+                        match vm.heap.concatenate(&left, &right) {
+                            Ok(string) => Value::String(string),
+                            Err(out_of_memory) => return error(&out_of_memory.to_string(), pc),
+                        }
+                    }
+                    _ => return error(TypeError::NUMBERS_OR_STRINGS.msg(), pc),
+                }
+            }
+            Instruction::Substract { dst, left, right } => {
+                let (left, right) = (number!(left, pc), number!(right, pc));
+                reg!(dst) = Value::from(left - right)
+            }
+            Instruction::Multiply { dst, left, right } => {
+                let (left, right) = (number!(left, pc), number!(right, pc));
+                reg!(dst) = Value::from(left * right)
+            }
+            Instruction::Divide { dst, left, right } => {
+                let (left, right) = (number!(left, pc), number!(right, pc));
+                reg!(dst) = Value::from(left / right)
+            }
+            Instruction::Equal { dst, left, right } => {
+                let (left, right) = (reg!(left), reg!(right));
+                reg!(dst) = Value::from(left == right)
+            }
+            Instruction::Greater { dst, left, right } => {
+                reg!(dst) = match reg!(left).try_greater(reg!(right)) {
+                    Ok(result) => result,
+                    Err(type_error) => return error(type_error.msg(), pc),
+                }
+            }
+            Instruction::GreaterEqual { dst, left, right } => {
+                reg!(dst) = match reg!(left).try_greater_equal(reg!(right)) {
+                    Ok(result) => result,
+                    Err(type_error) => return error(type_error.msg(), pc),
+                }
+            }
+            Instruction::Less { dst, left, right } => {
+                reg!(dst) = match reg!(left).try_less(reg!(right)) {
+                    Ok(result) => result,
+                    Err(type_error) => return error(type_error.msg(), pc),
+                }
+            }
+            Instruction::LessEqual { dst, left, right } => {
+                reg!(dst) = match reg!(left).try_less_equal(reg!(right)) {
+                    Ok(result) => result,
+                    Err(type_error) => return error(type_error.msg(), pc),
+                }
+            }
+            Instruction::Print { src } => {
+                vm.last = reg!(src);
+                if writeln!(vm.output, "{}", vm.last).is_err() {
+                    return error("cannot write output.", pc);
+                }
+            }
+            Instruction::Discard { src } => vm.last = reg!(src),
+            Instruction::DefineGlobal { name, src } => {
+                let name = constants[name as usize].as_string().unwrap();
+                vm.globals.define(name, reg!(src));
+                vm.last = reg!(src);
+            }
+            Instruction::GetGlobal { dst, name, cache } => {
+                let name = constants[name as usize].as_string().unwrap();
+                match vm.globals.slot(name, &caches[cache as usize]) {
+                    Some(slot) => reg!(dst) = vm.globals.values[slot],
+                    None => return error(&format!("undefined variable '{}'.", **name), pc),
+                }
+            }
+            Instruction::SetGlobal { name, src, cache } => {
+                let name = constants[name as usize].as_string().unwrap();
+                match vm.globals.slot(name, &caches[cache as usize]) {
+                    Some(slot) => vm.globals.values[slot] = reg!(src),
+                    None => return error(&format!("undefined variable '{}'.", **name), pc),
+                }
+            }
+            Instruction::JumpIfFalse { cond, target } => {
+                if reg!(cond).is_falsey() {
+                    pc = target;
+                    continue;
+                }
+            }
+            Instruction::JumpIfTrue { cond, target } => {
+                if !reg!(cond).is_falsey() {
+                    pc = target;
+                    continue;
+                }
+            }
+            Instruction::Jump { target } => {
+                pc = target;
+                continue;
+            }
+            Instruction::Loop { target } => {
+                if vm.interrupt.take() {
+                    return Err(VmError::Interrupted);
+                }
+                pc = target;
+                continue;
+            }
+        }
+
+        pc += 1;
+    }
+}
+
+/// Lists `chunk`, or draws its control-flow graph.
+fn listing(chunk: &Chunk, options: DisassemblerOptions) -> Result<String, VmError> {
+    if options.cfg {
+        verifier::verify(chunk)?;
+        let cfg = Cfg::new(chunk).expect("verified code decodes");
+        return Ok(cfg.to_dot(chunk, "code", options));
+    }
+
+    Ok(Disassembler::with_options(chunk, "code", options)
+        .disassemble()
+        .to_string())
+}
+
+/// Replaces the two operands on top of the stack with `op(left, right)`.
+#[inline]
+fn binary_op(
+    vm: &mut Vm,
+    op: fn(Value, Value) -> Result<Value, TypeError>,
+) -> Result<(), RuntimeError> {
+    match op(vm.peek(1), vm.peek(0)) {
+        Ok(result) => {
+            vm.pop();
+            vm.pop();
+            vm.push(result)
+        }
+        Err(error) => vm.runtime_error(error.msg()),
+    }
+}
+
 #[inline(always)]
 fn op_add(vm: &mut Vm) -> Result<(), RuntimeError> {
-    let (left, right) = (vm.peek(1)?, vm.peek(0)?);
-    if (left.is_number() && right.is_number()) || (left.is_string() && right.is_string()) {
-        let right = vm.pop();
-        let left = vm.pop();
-        vm.push(left + right);
-        Ok(())
-    } else {
-        vm.runtime_error("operands must be two numbers of two strings.")
+    if let (Value::String(left), Value::String(right)) = (vm.peek(1), vm.peek(0)) {
+        let string = match vm.heap.concatenate(&left, &right) {
+            Ok(string) => string,
+            Err(error) => return vm.runtime_error(&error.to_string()),
+        };
+        vm.pop();
+        vm.pop();
+        return vm.push(Value::String(string));
     }
+
+    binary_op(vm, Value::try_add)
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::resolver::{Lint, LintLevel, Lints};
+    use std::sync::Mutex;
+
+    /// An output sink tests can read back after the VM wrote to it.
+    #[derive(Clone, Default)]
+    struct Captured(Arc<Mutex<Vec<u8>>>);
+
+    impl Captured {
+        fn contents(&self) -> String {
+            String::from_utf8(self.0.lock().unwrap().clone()).unwrap()
+        }
+    }
+
+    impl Write for Captured {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn capturing_vm(options: VmOptions) -> (Vm, Captured) {
+        let captured = Captured::default();
+        let options = VmOptions {
+            output: Some(Box::new(captured.clone())),
+            ..options
+        };
+        (Vm::new(Some(options)), captured)
+    }
+
+    /// Runs `source` on `backend`, printing the result before the VM and its heap go away.
+    fn interpret_with(backend: Backend, source: &str) -> (Result<String, VmError>, String) {
+        let (mut vm, output) = capturing_vm(VmOptions {
+            backend,
+            ..Default::default()
+        });
+        let result = vm
+            .interpret(source.to_string())
+            .map(|value| value.to_string());
+        (result, output.contents())
+    }
+
+    #[test]
+    fn backends_agree() {
+        let sources = [
+            "1 + 2 * 3;",
+            "var a = 1; { var b = a; a = 2; b; }",
+            "var a = \"x\"; { var b = a + \"y\"; b + b; }",
+            "var i = 0; while (i < 10) { i = i + 1; } i;",
+            "var a = 1; if (a == 1 and !false) { a = 10; } else { a = 20; } a;",
+            "{ var a = 1; } { var b = 2; b; }",
+            "{ var a = 1; var b = a or 3; !b; }",
+            "-\"a\";",
+            "{ var a = nil; a + 1; }",
+            "a = 1;",
+            "print 1; { var a = \"a\"; print a + a; } print nil;",
+            "{ var a = 1; a + (a = 2); }",
+            "{ var a = 1; var b = 2; }",
+            "var a = true; if (a) {} while (!a) {}",
+            "{ var a = 2; var b = a and (a = nil); print a; b or 3 >= 1; }",
+        ];
+
+        for source in sources {
+            assert_eq!(
+                interpret_with(Backend::Stack, source),
+                interpret_with(Backend::Register, source),
+                "{}",
+                source
+            );
+        }
+    }
+
+    #[test]
+    fn print_writes_to_output() {
+        for backend in [Backend::Stack, Backend::Register] {
+            let source = "var a = 1; print a; print \"two\"; print a == 1;";
+            let (result, output) = interpret_with(backend, source);
+
+            assert_eq!(Ok("true".to_string()), result);
+            assert_eq!("1\ntwo\ntrue\n", output);
+        }
+    }
+
+    #[test]
+    fn diagnostics_write_to_output() {
+        let (mut vm, output) = capturing_vm(VmOptions {
+            trace_execution: true,
+            compiler: CompilerOptions {
+                print_code: true,
+                ..Default::default()
+            },
+            ..Default::default()
+        });
+        vm.interpret("print 1;".to_string()).unwrap();
+
+        let output = output.contents();
+        assert!(output.starts_with("== code =="), "{}", output);
+        assert!(output.contains("[ 1 ]"), "{}", output);
+        assert!(output.contains("OP_PRINT"), "{}", output);
+        assert!(output.contains("\n1\n"), "{}", output);
+    }
+
+    #[test]
+    fn fuel_bounds_instructions() {
+        for backend in [Backend::Stack, Backend::Register] {
+            let options = |fuel| VmOptions {
+                backend,
+                fuel: Some(fuel),
+                ..Default::default()
+            };
+            // OP_CONSTANT, OP_POP and OP_RETURN, or R_CONSTANT, R_DISCARD and R_RETURN.
+            let source = "1;".to_string();
+
+            let mut vm = Vm::new(Some(options(2)));
+            assert_eq!(Err(VmError::OutOfFuel), vm.interpret(source.clone()));
+            assert_eq!(Some(0), vm.fuel());
+
+            let mut vm = Vm::new(Some(options(3)));
+            assert_eq!(Ok(LoxValue::Number(1.0)), vm.interpret(source));
+            assert_eq!(Some(0), vm.fuel());
+        }
+    }
+
+    #[test]
+    fn resume_after_refuel() {
+        let source =
+            "var sum = 0; var i = 0; while (i < 20) { sum = sum + i; print sum; i = i + 1; } sum;";
+
+        for backend in [Backend::Stack, Backend::Register] {
+            let (expected, expected_output) = interpret_with(backend, source);
+            let (mut vm, output) = capturing_vm(VmOptions {
+                backend,
+                fuel: Some(10),
+                ..Default::default()
+            });
+
+            let mut slices = 1;
+            let mut result = vm.interpret(source.to_string());
+            while result == Err(VmError::OutOfFuel) {
+                slices += 1;
+                vm.refuel(10);
+                result = vm.resume();
+            }
+
+            assert!(slices > 10, "{:?}: ran in {} slices", backend, slices);
+            assert_eq!(expected, result.map(|value| value.to_string()));
+            assert_eq!(expected_output, output.contents());
+            assert!(matches!(vm.resume(), Err(VmError::Call(_))));
+        }
+    }
+
+    #[test]
+    fn prepare_reports_warnings_before_running() {
+        for backend in [Backend::Stack, Backend::Register] {
+            let (mut vm, output) = capturing_vm(VmOptions {
+                backend,
+                ..Default::default()
+            });
+
+            vm.prepare("{ var a = 1; } print 2;".to_string()).unwrap();
+            assert_eq!(1, vm.warnings().len());
+            assert_eq!("", output.contents());
+
+            assert_eq!(Ok(LoxValue::Number(2.0)), vm.resume());
+            assert_eq!("2\n", output.contents());
+            assert!(matches!(vm.resume(), Err(VmError::Call(_))));
+        }
+    }
+
+    #[test]
+    fn interrupt_stops_infinite_loop() {
+        for backend in [Backend::Stack, Backend::Register] {
+            let options = VmOptions {
+                backend,
+                ..Default::default()
+            };
+            let mut vm = Vm::new(Some(options));
+            let handle = vm.interrupt_handle();
+
+            // A request made before the script starts waits for its first loop.
+            handle.interrupt();
+            assert_eq!(
+                Err(VmError::Interrupted),
+                vm.interpret("while (true) {}".to_string())
+            );
+            assert_eq!(Ok(LoxValue::Number(1.0)), vm.interpret("1;".to_string()));
+
+            let interrupter = std::thread::spawn(move || {
+                std::thread::sleep(std::time::Duration::from_millis(20));
+                handle.interrupt();
+            });
+            let result = vm.interpret("var i = 0; while (true) { i = i + 1; }".to_string());
+            interrupter.join().unwrap();
+
+            assert_eq!(Err(VmError::Interrupted), result, "{:?}", backend);
+            assert!(vm.get_global::<f64>("i").unwrap() > 0.0);
+            assert_eq!(
+                Ok(LoxValue::Number(2.0)),
+                vm.interpret("1 + 1;".to_string())
+            );
+        }
+    }
+
+    #[test]
+    fn cleared_interrupt_does_not_stop_the_next_loop() {
+        for backend in [Backend::Stack, Backend::Register] {
+            let options = VmOptions {
+                backend,
+                ..Default::default()
+            };
+            let mut vm = Vm::new(Some(options));
+            let handle = vm.interrupt_handle();
+
+            // A request the loop-free script never honored is still pending when it ends.
+            handle.interrupt();
+            assert_eq!(Ok(LoxValue::Number(1.0)), vm.interpret("1;".to_string()));
+            handle.clear();
+
+            let source = "var i = 0; while (i < 10) i = i + 1;";
+            vm.interpret(source.to_string()).unwrap();
+            assert_eq!(Some(10.0), vm.get_global::<f64>("i"), "{:?}", backend);
+        }
+    }
+
+    #[test]
+    fn heap_limit_stops_runaway_concatenation() {
+        for backend in [Backend::Stack, Backend::Register] {
+            let options = VmOptions {
+                backend,
+                max_heap_bytes: Some(64 * 1024),
+                ..Default::default()
+            };
+            let mut vm = Vm::new(Some(options));
+            let source = "var s = \"ab\";\nwhile (true) {\n  s = s + s;\n}";
+
+            let expected_error = Err(VmError::Runtime(RuntimeError {
+                msg: "out of memory.".into(),
+                line: 3,
+            }));
+            assert_eq!(
+                expected_error,
+                vm.interpret(source.to_string()),
+                "{:?}",
+                backend
+            );
+            assert!(vm.heap.bytes_allocated() <= 64 * 1024);
+            assert_eq!(
+                Some(16 * 1024),
+                vm.get_global::<String>("s").map(|s| s.len())
+            );
+        }
+    }
+
+    #[test]
+    fn heap_limit_applies_to_constants_and_host_values() {
+        let options = VmOptions {
+            max_heap_bytes: Some(128),
+            ..Default::default()
+        };
+        let mut vm = Vm::new(Some(options));
+        let long = "x".repeat(128);
+
+        assert_eq!(Err(OutOfMemory), vm.set_global("long", long.as_str()));
+        assert!(matches!(
+            vm.interpret(format!("\"{}\";", long)),
+            Err(VmError::Compile(_))
+        ));
+    }
+
+    #[test]
+    fn denied_lints_fail_before_constants_are_allocated() {
+        for backend in [Backend::Stack, Backend::Register] {
+            let mut lints = Lints::default();
+            lints.set(Lint::UnusedVariable, LintLevel::Deny);
+            let options = VmOptions {
+                backend,
+                max_heap_bytes: Some(128),
+                compiler: CompilerOptions {
+                    lints,
+                    ..Default::default()
+                },
+                ..Default::default()
+            };
+            let mut vm = Vm::new(Some(options));
+            let source = format!("{{ var a = \"{}\"; }}", "x".repeat(128));
+
+            // The string would not fit in the heap, but the lint rejects the script first.
+            assert_eq!(
+                Err(VmError::Compile(vec![CompilerError::new(
+                    "'a' is never read. [unused-variable]",
+                    1
+                )])),
+                vm.interpret(source),
+                "{:?}",
+                backend
+            );
+        }
+    }
+
+    #[test]
+    fn global_caches_count_hits() {
+        for backend in [Backend::Stack, Backend::Register] {
+            let options = VmOptions {
+                backend,
+                ..Default::default()
+            };
+            let mut vm = Vm::new(Some(options));
+            let source = "var i = 0; while (i < 3) { i = i + 1; } var i = 10; i;";
+
+            assert_eq!(Ok(LoxValue::Number(10.0)), vm.interpret(source.to_string()));
+            let caches = match backend {
+                Backend::Stack => vm.chunk.as_ref().unwrap().caches(),
+                Backend::Register => vm.register_chunk.as_ref().unwrap().caches(),
+            };
+            let hits: u64 = caches.iter().map(InlineCache::hits).sum();
+            let misses: u64 = caches.iter().map(InlineCache::misses).sum();
+            // `i < 3` runs 4 times, the body reads and writes `i` 3 times, then `i` once more.
+            assert_eq!((7, 4), (hits, misses), "{:?}", backend);
+        }
+    }
+
+    #[test]
+    fn stack_overflow_error() {
+        let options = VmOptions {
+            max_stack: 2,
+            ..Default::default()
+        };
+
+        let expected_error = Err(VmError::Runtime(RuntimeError {
+            msg: "stack overflow.".into(),
+            line: 2,
+        }));
+        let source = "var a = 1;\na + (a + a);";
+
+        let mut vm = Vm::new(Some(options));
+        assert_eq!(expected_error, vm.interpret(source.to_string()));
+
+        // The register backend checks the whole frame fits before running.
+        let mut vm = Vm::new(Some(VmOptions {
+            backend: Backend::Register,
+            max_stack: 2,
+            ..Default::default()
+        }));
+        match vm.interpret(source.to_string()) {
+            Err(VmError::Runtime(error)) => assert_eq!("stack overflow.", error.msg()),
+            result => panic!("expected a stack overflow, got {:?}", result),
+        }
+    }
 
     #[test]
     fn op_add_two_numbers() {
         let mut vm = Vm::new(None);
         assert_eq!(
-            Value::from(2.0),
+            LoxValue::Number(2.0),
             vm.interpret("1 + 1;".to_string()).unwrap()
         );
     }
@@ -422,9 +1572,10 @@ mod tests {
     fn op_add_two_strings() {
         let mut vm = Vm::new(None);
         assert_eq!(
-            Value::from("hello world!"),
+            "hello world!",
             vm.interpret("\"hello\" + \" world!\";".to_string())
                 .unwrap()
+                .to_string()
         );
     }
 
@@ -433,7 +1584,7 @@ mod tests {
         let mut vm = Vm::new(None);
 
         let expected_error = Err(VmError::Runtime(RuntimeError {
-            msg: "operands must be two numbers of two strings.".into(),
+            msg: "operands must be two numbers or two strings.".into(),
             line: 1,
         }));
 
@@ -445,13 +1596,68 @@ mod tests {
         let mut vm = Vm::new(None);
 
         let expected_error = Err(VmError::Runtime(RuntimeError {
-            msg: "operands must be two numbers of two strings.".into(),
+            msg: "operands must be two numbers or two strings.".into(),
             line: 1,
         }));
 
         assert_eq!(expected_error, vm.interpret("\"1\" + nil;".to_string()));
     }
 
+    #[test]
+    fn bytecode_runs_like_source() {
+        let source = "var a = \"one\"; { var b = 2; print a; print b; }";
+        let bytes = Vm::new(None).compile_bytecode(source.to_string()).unwrap();
+
+        let (mut vm, output) = capturing_vm(VmOptions::default());
+        assert!(vm.interpret_bytecode(&bytes).is_ok());
+        assert_eq!("one\n2\n", output.contents());
+
+        // Bytecode is stack code, which the register backend cannot run.
+        let mut vm = Vm::new(Some(VmOptions {
+            backend: Backend::Register,
+            ..Default::default()
+        }));
+        assert!(matches!(
+            vm.interpret_bytecode(&bytes),
+            Err(VmError::Load(_))
+        ));
+    }
+
+    #[test]
+    fn bytecode_decompiles_with_local_names() {
+        let source = "var a = \"one\"; { var b = 2; print a; print b; }";
+        let mut vm = Vm::new(None);
+        let bytes = vm.compile_bytecode(source.to_string()).unwrap();
+
+        assert_eq!(
+            Ok("var a = \"one\";\n{\n  var b = 2;\n  print a;\n  print b;\n}\n".to_string()),
+            vm.decompile_bytecode(&bytes)
+        );
+    }
+
+    #[test]
+    fn bytecode_is_verified_before_running() {
+        let mut vm = Vm::new(None);
+        let bytes = vm.compile_bytecode("print 1;".to_string()).unwrap();
+
+        let mut heap = Heap::new();
+        let mut chunk = loxc::decode(&bytes, &mut heap).unwrap();
+        chunk.code_mut()[0] = OpCode::Pop as u8;
+        let tampered = loxc::encode(&chunk);
+
+        match vm.interpret_bytecode(&tampered) {
+            Err(VmError::Verify(error)) => {
+                assert_eq!("OP_POP underflows the stack.", error.msg());
+                assert_eq!(0, error.offset());
+            }
+            result => panic!("expected a verify error, got {:?}", result),
+        }
+        assert!(matches!(
+            vm.interpret_bytecode(b"print 1;"),
+            Err(VmError::Load(_))
+        ));
+    }
+
     #[test]
     fn undefinded_local_error() {
         let mut vm = Vm::new(None);
@@ -0,0 +1,121 @@
+//! Drives the interpreter through its public API, the way a host application would.
+
+use rlox::{scan_lossless, Backend, LoxValue, TokenKind, TriviaKind, Vm, VmError, VmOptions};
+
+#[test]
+fn globals_cross_the_api() {
+    for backend in [Backend::Stack, Backend::Register] {
+        let options = VmOptions {
+            backend,
+            ..Default::default()
+        };
+        let mut vm = Vm::new(Some(options));
+        vm.set_global("name", "lox").unwrap();
+        vm.set_global("count", 2.0).unwrap();
+        vm.set_global("missing", None::<f64>).unwrap();
+
+        let source =
+            "var greeting = \"hello \" + name; count = count + 1; var empty = missing == nil;";
+        vm.interpret(source.to_string()).unwrap();
+
+        assert_eq!(Some("hello lox".to_string()), vm.get_global("greeting"));
+        assert_eq!(Some(3.0), vm.get_global::<f64>("count"));
+        assert_eq!(Some(true), vm.get_global::<bool>("empty"));
+        assert_eq!(Some(None), vm.get_global::<Option<f64>>("missing"));
+        assert_eq!(None, vm.get_global::<f64>("greeting"));
+        assert_eq!(None, vm.get_global::<f64>("undefined"));
+    }
+}
+
+#[test]
+fn state_persists_between_scripts() {
+    let mut vm = Vm::new(None);
+    vm.interpret("var total = 1;".to_string()).unwrap();
+    vm.interpret("total = total + 41;".to_string()).unwrap();
+
+    assert_eq!(Some(42.0), vm.get_global::<f64>("total"));
+    assert_eq!(
+        Ok(LoxValue::Number(42.0)),
+        vm.interpret("total;".to_string())
+    );
+}
+
+#[test]
+fn call_global_fails_until_lox_has_functions() {
+    let mut vm = Vm::new(None);
+    vm.set_global("answer", 42.0).unwrap();
+
+    assert_eq!(
+        Err(VmError::Call("'answer' is not callable.".to_string())),
+        vm.call_global("answer", &[])
+    );
+    assert_eq!(
+        Err(VmError::Call("'nope' is not callable.".to_string())),
+        vm.call_global("nope", &[LoxValue::Number(1.0), LoxValue::String("x")])
+    );
+}
+
+#[test]
+fn vms_on_one_thread_are_isolated() {
+    let mut first = Vm::new(None);
+    let mut second = Vm::new(None);
+    first
+        .interpret("var name = \"first\";".to_string())
+        .unwrap();
+    second
+        .interpret("var name = \"sec\" + \"ond\";".to_string())
+        .unwrap();
+    drop(second);
+
+    assert_eq!(Some("first".to_string()), first.get_global("name"));
+    assert_eq!(
+        Ok("first!".to_string()),
+        first
+            .interpret("name + \"!\";".to_string())
+            .map(|value| value.to_string())
+    );
+}
+
+#[test]
+fn vms_run_concurrently_on_threads() {
+    let vms: Vec<_> = (0..16)
+        .map(|n| {
+            let mut vm = Vm::new(None);
+            vm.set_global("n", n as f64).unwrap();
+            vm.interpret("var label = \"vm \";".to_string()).unwrap();
+            vm
+        })
+        .collect();
+
+    let workers: Vec<_> = vms
+        .into_iter()
+        .map(|mut vm| {
+            std::thread::spawn(move || {
+                let source = "var i = 0; var sum = 0; while (i < 1000) { sum = sum + n; i = i + 1; } label = label + \"done\";";
+                vm.interpret(source.to_string()).unwrap();
+                (
+                    vm.get_global::<f64>("sum").unwrap(),
+                    vm.get_global::<String>("label").unwrap(),
+                )
+            })
+        })
+        .collect();
+
+    for (n, worker) in workers.into_iter().enumerate() {
+        let (sum, label) = worker.join().unwrap();
+        assert_eq!(n as f64 * 1000.0, sum);
+        assert_eq!("vm done", label);
+    }
+}
+
+#[test]
+fn lossless_tokens_give_back_the_source() {
+    let source = "// total\nvar a = 1;  // one\n\tprint a;\n";
+    let tokens = scan_lossless(source).unwrap();
+
+    let written: String = tokens.iter().map(|token| token.to_string()).collect();
+    assert_eq!(source, written);
+    assert_eq!(TokenKind::Var, tokens[0].token.kind);
+    assert_eq!(TriviaKind::Comment, tokens[0].leading[0].kind);
+    assert_eq!(TokenKind::Eof, tokens.last().unwrap().token.kind);
+}
@@ -0,0 +1,109 @@
+//! Runs every script in `tests/scripts` through the `rlox` binary on each backend, and compiled to
+//! `.loxc` first on the stack backend, the only one running bytecode.
+//!
+//! Scripts state what they print with `// expect: <output>` comments and the runtime error they
+//! stop with, if any, with `// expect runtime error: <message>`.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+const BACKENDS: [&str; 2] = ["stack", "register"];
+
+struct Expectations {
+    output: Vec<String>,
+    runtime_error: Option<(usize, String)>,
+}
+
+fn expectations(source: &str) -> Expectations {
+    let mut output = Vec::new();
+    let mut runtime_error = None;
+
+    for (index, line) in source.lines().enumerate() {
+        if let Some((_, expected)) = line.split_once("// expect: ") {
+            output.push(expected.to_string());
+        } else if let Some((_, message)) = line.split_once("// expect runtime error: ") {
+            runtime_error = Some((index + 1, message.to_string()));
+        }
+    }
+
+    Expectations {
+        output,
+        runtime_error,
+    }
+}
+
+fn scripts() -> Vec<PathBuf> {
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/scripts");
+    let mut scripts: Vec<_> = fs::read_dir(dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.extension().is_some_and(|ext| ext == "lox"))
+        .collect();
+    scripts.sort();
+    scripts
+}
+
+/// Runs `program`, the compiled or source form of `script`, checking it does what `script` says.
+fn run(script: &Path, program: &Path, backend: &str) {
+    let source = fs::read_to_string(script).unwrap();
+    let expected = expectations(&source);
+    let output = Command::new(env!("CARGO_BIN_EXE_rlox"))
+        .arg(format!("--backend={}", backend))
+        .arg(program)
+        .output()
+        .unwrap();
+    let name = format!("{} ({})", program.display(), backend);
+
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    let printed: Vec<_> = stdout.lines().collect();
+    assert_eq!(expected.output, printed, "{}: unexpected output", name);
+
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    match expected.runtime_error {
+        Some((line, message)) => {
+            assert_eq!(Some(70), output.status.code(), "{}: exit code", name);
+            assert_eq!(
+                format!("[line: {}] runtime error: {}", line, message),
+                stderr.trim_end(),
+                "{}: unexpected error",
+                name
+            );
+        }
+        None => {
+            assert!(output.status.success(), "{}: failed with {}", name, stderr);
+        }
+    }
+}
+
+#[test]
+fn scripts_pass_on_every_backend() {
+    let scripts = scripts();
+    assert!(!scripts.is_empty());
+
+    for script in &scripts {
+        for backend in BACKENDS {
+            run(script, script, backend);
+        }
+    }
+}
+
+#[test]
+fn compiled_scripts_pass() {
+    let dir = Path::new(env!("CARGO_TARGET_TMPDIR")).join("scripts");
+    fs::create_dir_all(&dir).unwrap();
+
+    for script in &scripts() {
+        let compiled = dir.join(script.file_name().unwrap()).with_extension("loxc");
+        let status = Command::new(env!("CARGO_BIN_EXE_rlox"))
+            .arg("compile")
+            .arg(script)
+            .arg("-o")
+            .arg(&compiled)
+            .status()
+            .unwrap();
+        assert!(status.success(), "{}: failed to compile", script.display());
+
+        run(script, &compiled, "stack");
+    }
+}